@@ -0,0 +1,341 @@
+//! Optional framebuffer for systems with enough RAM.
+//!
+//! The framebuffer implements `Display`, so a `gui::Layout` can be rendered
+//! into it. It keeps the previously displayed frame and uses it to decide
+//! whether the display has to be updated at all, and whether a partial
+//! refresh of the changed area is sufficient.
+
+use crate::{Display, Error, PartialRefresh};
+
+use core::cmp::{max, min};
+
+/// Returns the size of the buffers required for a framebuffer with the
+/// specified size, e.g., `FrameBuffer<400, 300, { buffer_size(400, 300) }>`.
+pub const fn buffer_size(width: u32, height: u32) -> usize {
+    (width as usize).div_ceil(8) * height as usize
+}
+
+/// Kind of refresh performed by `FrameBuffer::update()`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Update {
+    /// The frame did not change.
+    None,
+    Full,
+    Partial {
+        left: u32,
+        top: u32,
+        right: u32,
+        bottom: u32,
+    },
+}
+
+/// Framebuffer with a width of `W` and a height of `H` pixels. `N` has to be
+/// `buffer_size(W, H)`, otherwise `new()` fails to compile.
+///
+/// The pixels are stored in the format expected by `Display::draw_row()`.
+pub struct FrameBuffer<const W: u32, const H: u32, const N: usize> {
+    current: [u8; N],
+    previous: [u8; N],
+    has_previous: bool,
+    next_row: u32,
+    partial_threshold: u32,
+}
+
+impl<const W: u32, const H: u32, const N: usize> FrameBuffer<W, H, N> {
+    const STRIDE: usize = (W as usize).div_ceil(8);
+    const VALID: () = assert!(N == buffer_size(W, H), "invalid framebuffer size");
+
+    pub fn new() -> Self {
+        let () = Self::VALID;
+        FrameBuffer {
+            current: [0xff; N],
+            previous: [0xff; N],
+            has_previous: false,
+            next_row: 0,
+            partial_threshold: 50,
+        }
+    }
+
+    /// Sets the maximum size of the changed area (in percent of the display
+    /// area) for which `update()` performs a partial refresh. The default is
+    /// 50%.
+    pub fn set_partial_threshold(&mut self, percent: u32) {
+        self.partial_threshold = percent;
+    }
+
+    pub fn row(&self, y: u32) -> &[u8] {
+        let start = y as usize * Self::STRIDE;
+        &self.current[start..start + Self::STRIDE]
+    }
+
+    pub fn row_mut(&mut self, y: u32) -> &mut [u8] {
+        let start = y as usize * Self::STRIDE;
+        &mut self.current[start..start + Self::STRIDE]
+    }
+
+    /// Returns the smallest rectangle `(left, top, right, bottom)` containing
+    /// all pixels which changed since the last call to `mark_displayed()`, or
+    /// the whole framebuffer if no frame has been displayed yet. The left and
+    /// right edges are aligned to multiples of eight pixels.
+    pub fn changed_area(&self) -> Option<(u32, u32, u32, u32)> {
+        if !self.has_previous {
+            return Some((0, 0, W, H));
+        }
+        let mut area: Option<(usize, usize, usize, usize)> = None;
+        let rows = self
+            .current
+            .chunks(Self::STRIDE)
+            .zip(self.previous.chunks(Self::STRIDE));
+        for (y, (current, previous)) in rows.enumerate() {
+            let first = current.iter().zip(previous).position(|(a, b)| a != b);
+            let first = match first {
+                Some(first) => first,
+                None => continue,
+            };
+            let last = current.iter().zip(previous).rposition(|(a, b)| a != b);
+            let last = last.unwrap();
+            area = Some(match area {
+                None => (first, y, last, y),
+                Some((left, top, right, _)) => (min(left, first), top, max(right, last), y),
+            });
+        }
+        area.map(|(left, top, right, bottom)| {
+            (
+                left as u32 * 8,
+                top as u32,
+                min((right as u32 + 1) * 8, W),
+                bottom as u32 + 1,
+            )
+        })
+    }
+
+    /// Records that the current frame is shown on the display, so that
+    /// further changes are calculated relative to it.
+    pub fn mark_displayed(&mut self) {
+        self.previous.copy_from_slice(&self.current);
+        self.has_previous = true;
+    }
+
+    /// Sends the complete frame to the display. The caller has to call
+    /// `start_frame()` and `end_frame()`.
+    pub fn draw<DisplayType>(&self, display: &mut DisplayType)
+    where
+        DisplayType: Display,
+    {
+        for row in self.current.chunks(Self::STRIDE) {
+            display.draw_row(row);
+        }
+    }
+
+    /// Sends the rows between `top` and `bottom` to the display. The caller
+    /// has to call `start_partial()` and `end_partial()`.
+    pub fn draw_partial<DisplayType>(&self, display: &mut DisplayType, top: u32, bottom: u32)
+    where
+        DisplayType: Display + PartialRefresh,
+    {
+        for y in top..bottom {
+            display.draw_partial_row(self.row(y));
+        }
+    }
+
+    /// Updates the display if the frame changed since it was last displayed,
+    /// using a partial refresh if only a small part of the frame changed.
+    pub fn update<DisplayType>(&mut self, display: &mut DisplayType) -> nb::Result<Update, Error>
+    where
+        DisplayType: Display + PartialRefresh,
+    {
+        let (left, top, right, bottom) = match self.changed_area() {
+            Some(area) => area,
+            None => return Ok(Update::None),
+        };
+        let area = (right - left) * (bottom - top);
+        let update = if self.has_previous && area * 100 <= W * H * self.partial_threshold {
+            display.start_partial(left, top, right, bottom)?;
+            self.draw_partial(display, top, bottom);
+            display.end_partial();
+            Update::Partial {
+                left,
+                top,
+                right,
+                bottom,
+            }
+        } else {
+            display.start_frame()?;
+            self.draw(display);
+            display.end_frame();
+            Update::Full
+        };
+        self.mark_displayed();
+        Ok(update)
+    }
+}
+
+impl<const W: u32, const H: u32, const N: usize> Default for FrameBuffer<W, H, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Rendering into the framebuffer, e.g., via `gui::Layout::render()`.
+impl<const W: u32, const H: u32, const N: usize> Display for FrameBuffer<W, H, N> {
+    const WIDTH: u32 = W;
+    const HEIGHT: u32 = H;
+
+    fn start_frame(&mut self) -> nb::Result<(), Error> {
+        self.next_row = 0;
+        Ok(())
+    }
+
+    fn end_frame(&mut self) {}
+
+    fn draw_row(&mut self, row: &[u8]) {
+        if self.next_row >= H {
+            return;
+        }
+        let y = self.next_row;
+        self.row_mut(y).copy_from_slice(&row[..Self::STRIDE]);
+        self.next_row += 1;
+    }
+}
+
+#[cfg(feature = "graphics")]
+mod graphics {
+    use super::FrameBuffer;
+
+    use core::convert::Infallible;
+    use embedded_graphics_core::draw_target::DrawTarget;
+    use embedded_graphics_core::geometry::{OriginDimensions, Size};
+    use embedded_graphics_core::pixelcolor::BinaryColor;
+    use embedded_graphics_core::Pixel;
+
+    impl<const W: u32, const H: u32, const N: usize> OriginDimensions for FrameBuffer<W, H, N> {
+        fn size(&self) -> Size {
+            Size::new(W, H)
+        }
+    }
+
+    impl<const W: u32, const H: u32, const N: usize> DrawTarget for FrameBuffer<W, H, N> {
+        type Color = BinaryColor;
+        type Error = Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            for Pixel(point, color) in pixels {
+                if point.x < 0 || point.x >= W as i32 || point.y < 0 || point.y >= H as i32 {
+                    continue;
+                }
+                let index = point.y as usize * Self::STRIDE + (point.x / 8) as usize;
+                let mask = 0x80 >> (point.x & 7);
+                match color {
+                    BinaryColor::On => self.current[index] &= !mask,
+                    BinaryColor::Off => self.current[index] |= mask,
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{buffer_size, FrameBuffer, Update};
+    use crate::gui::{Fill, HorizontalSplit, Layout};
+    use crate::{Color, Display, Error, PartialRefresh};
+
+    use std::vec::Vec;
+
+    type TestBuffer = FrameBuffer<64, 16, { buffer_size(64, 16) }>;
+
+    struct RefreshDisplay {
+        full: u32,
+        partial: Vec<(u32, u32, u32, u32)>,
+        rows: u32,
+    }
+
+    impl Display for RefreshDisplay {
+        const WIDTH: u32 = 64;
+        const HEIGHT: u32 = 16;
+
+        fn start_frame(&mut self) -> nb::Result<(), Error> {
+            self.full += 1;
+            Ok(())
+        }
+        fn end_frame(&mut self) {}
+        fn draw_row(&mut self, _row: &[u8]) {
+            self.rows += 1;
+        }
+    }
+
+    impl PartialRefresh for RefreshDisplay {
+        fn start_partial(
+            &mut self,
+            left: u32,
+            top: u32,
+            right: u32,
+            bottom: u32,
+        ) -> nb::Result<(), Error> {
+            self.partial.push((left, top, right, bottom));
+            Ok(())
+        }
+        fn end_partial(&mut self) {}
+        fn draw_partial_row(&mut self, _row: &[u8]) {
+            self.rows += 1;
+        }
+    }
+
+    fn render(framebuffer: &mut TestBuffer, split_at: u32) {
+        let layout = Layout::new(
+            64,
+            16,
+            HorizontalSplit::expand_right(
+                split_at,
+                Fill::new(Color::Black),
+                Fill::new(Color::White),
+            ),
+        );
+        framebuffer.start_frame().ok();
+        layout.render(framebuffer, &mut [0u8; 8]);
+        framebuffer.end_frame();
+    }
+
+    #[test]
+    fn test_framebuffer_update() {
+        let mut display = RefreshDisplay {
+            full: 0,
+            partial: Vec::new(),
+            rows: 0,
+        };
+        let mut framebuffer = TestBuffer::new();
+        render(&mut framebuffer, 4);
+        assert_eq!(
+            framebuffer.row(3),
+            &[0x0f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(framebuffer.update(&mut display).ok(), Some(Update::Full));
+        assert_eq!((display.full, display.rows), (1, 16));
+
+        render(&mut framebuffer, 4);
+        assert_eq!(framebuffer.update(&mut display).ok(), Some(Update::None));
+
+        render(&mut framebuffer, 20);
+        let area = (0, 0, 24, 16);
+        assert_eq!(framebuffer.changed_area(), Some(area));
+        assert_eq!(
+            framebuffer.update(&mut display).ok(),
+            Some(Update::Partial {
+                left: 0,
+                top: 0,
+                right: 24,
+                bottom: 16
+            })
+        );
+        assert_eq!(display.partial, [area]);
+        assert_eq!(display.rows, 32);
+
+        render(&mut framebuffer, 60);
+        assert_eq!(framebuffer.update(&mut display).ok(), Some(Update::Full));
+        assert_eq!(display.full, 2);
+    }
+}
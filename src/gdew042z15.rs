@@ -1,4 +1,4 @@
-use crate::{Display, Error, Hertz};
+use crate::{Display, Error, GrayscaleDisplay, Hertz, Mirror, RedDisplay};
 
 enum InitState {
     Uninitialized,
@@ -16,6 +16,9 @@ pub struct GDEW042Z15<SPI, Busy, Reset, DataCmd, CS, Timer> {
     cs: CS,
     timer: Timer,
     init_state: InitState,
+    mirror: Mirror,
+    gray_mode: bool,
+    gray_plane: u8,
 }
 
 impl<SPI, Busy, Reset, DataCmd, CS, Timer> GDEW042Z15<SPI, Busy, Reset, DataCmd, CS, Timer>
@@ -46,6 +49,19 @@ where
             cs: cs,
             timer: timer,
             init_state: InitState::Uninitialized,
+            mirror: Mirror::None,
+            gray_mode: false,
+            gray_plane: 0,
+        }
+    }
+
+    /// Mirrors the image by changing the scan direction of the panel. If the
+    /// display is already initialized, the setting takes effect with the next
+    /// frame.
+    pub fn set_mirror(&mut self, mirror: Mirror) {
+        self.mirror = mirror;
+        if let InitState::Initialized = self.init_state {
+            self.send_panel_setting();
         }
     }
 
@@ -96,8 +112,7 @@ where
                 if self.busy.is_low().ok().unwrap() {
                     return Err(nb::Error::WouldBlock);
                 }
-                self.send_command(DisplayCommand::PanelSetting);
-                self.send_data(0x0f);
+                self.send_panel_setting();
                 self.init_state = InitState::Initialized;
                 Ok(())
             }
@@ -116,6 +131,37 @@ where
         )
     }
 
+    fn send_panel_setting(&mut self) {
+        // LUT from OTP, black/white/red mode, gates scanning up (UD), sources
+        // shifting right (SHL), booster on and no soft reset. Grayscale mode
+        // uses the LUTs from the registers and black/white mode.
+        let mut setting = if self.gray_mode { 0x3f } else { 0x0f };
+        if self.mirror.is_vertical() {
+            setting &= !PANEL_SETTING_UD;
+        }
+        if self.mirror.is_horizontal() {
+            setting &= !PANEL_SETTING_SHL;
+        }
+        self.send_command(DisplayCommand::PanelSetting);
+        self.send_data(setting);
+    }
+
+    fn send_gray_luts(&mut self) {
+        let luts = [
+            (DisplayCommand::LUTForVCOM, &GRAY_LUT_VCOM[..]),
+            (DisplayCommand::LUTWhiteToWhite, &GRAY_LUT_WW[..]),
+            (DisplayCommand::LUTBlackToWhite, &GRAY_LUT_BW[..]),
+            (DisplayCommand::LUTWhiteToBlack, &GRAY_LUT_WB[..]),
+            (DisplayCommand::LUTBlackToBlack, &GRAY_LUT_BB[..]),
+        ];
+        for (command, lut) in luts.iter() {
+            self.send_command(*command);
+            for &data in lut.iter() {
+                self.send_data(data);
+            }
+        }
+    }
+
     fn send_command(&mut self, command: DisplayCommand) {
         self.data_cmd.set_low().ok();
         self.cs.set_low().ok();
@@ -163,36 +209,156 @@ where
         if self.busy.is_low().ok().unwrap() {
             return Err(nb::Error::WouldBlock);
         }
+        if self.gray_mode {
+            // Switch back to the LUTs from OTP.
+            self.gray_mode = false;
+            self.send_panel_setting();
+        }
         self.send_command(DisplayCommand::DataStartTransmission1);
         self.delay_2ms();
         Ok(())
     }
 
     fn end_frame(&mut self) {
-        self.delay_2ms();
-        self.send_command(DisplayCommand::DataStartTransmission2);
-        self.delay_2ms();
-        // We need to send the red color frame, even though we do not support it
-        // yet.
+        // The display always expects a red plane, which is empty for
+        // black/white frames.
+        self.next_red_plane();
         for _ in 0..Self::WIDTH * Self::HEIGHT / 8 {
             self.send_data(0xff);
         }
+        self.end_red_frame();
+    }
+
+    fn draw_row(&mut self, row: &[u8]) {
+        assert!(row.len() >= Self::WIDTH as usize / 8);
+        for i in 0..Self::WIDTH as usize / 8 {
+            self.send_data(row[i]);
+        }
+    }
+}
+
+/// Red support. In the red plane, cleared bits are red.
+impl<SPI, Busy, Reset, DataCmd, CS, Timer> RedDisplay
+    for GDEW042Z15<SPI, Busy, Reset, DataCmd, CS, Timer>
+where
+    SPI: embedded_hal::spi::FullDuplex<u8>,
+    Busy: embedded_hal::digital::v2::InputPin,
+    Reset: embedded_hal::digital::v2::OutputPin,
+    DataCmd: embedded_hal::digital::v2::OutputPin,
+    CS: embedded_hal::digital::v2::OutputPin,
+    Timer: embedded_hal::timer::CountDown<Time = Hertz>,
+{
+    fn start_red_frame(&mut self) -> nb::Result<(), Error> {
+        self.start_frame()
+    }
+
+    fn next_red_plane(&mut self) {
+        self.delay_2ms();
+        self.send_command(DisplayCommand::DataStartTransmission2);
         self.delay_2ms();
+    }
 
+    fn end_red_frame(&mut self) {
+        self.delay_2ms();
         self.send_command(DisplayCommand::DisplayRefresh);
         // Give the display some time to deassert the busy pin.
         // TODO: Get rid of this blocking delay?
         self.delay_10ms();
     }
+}
 
-    fn draw_row(&mut self, row: &[u8]) {
-        assert!(row.len() >= Self::WIDTH as usize / 8);
+/// Grayscale support using custom LUTs. Note that this mode is only supported
+/// by the black/white variant of the panel.
+impl<SPI, Busy, Reset, DataCmd, CS, Timer> GrayscaleDisplay
+    for GDEW042Z15<SPI, Busy, Reset, DataCmd, CS, Timer>
+where
+    SPI: embedded_hal::spi::FullDuplex<u8>,
+    Busy: embedded_hal::digital::v2::InputPin,
+    Reset: embedded_hal::digital::v2::OutputPin,
+    DataCmd: embedded_hal::digital::v2::OutputPin,
+    CS: embedded_hal::digital::v2::OutputPin,
+    Timer: embedded_hal::timer::CountDown<Time = Hertz>,
+{
+    fn start_gray_frame(&mut self) -> nb::Result<(), Error> {
+        if self.busy.is_low().ok().unwrap() {
+            return Err(nb::Error::WouldBlock);
+        }
+        if !self.gray_mode {
+            self.gray_mode = true;
+            self.send_panel_setting();
+        }
+        self.gray_plane = 0;
+        self.send_command(DisplayCommand::DataStartTransmission1);
+        self.delay_2ms();
+        Ok(())
+    }
+
+    fn next_gray_plane(&mut self) {
+        self.delay_2ms();
+        self.send_command(DisplayCommand::DataStartTransmission2);
+        self.delay_2ms();
+        self.gray_plane = 1;
+    }
+
+    fn end_gray_frame(&mut self) {
+        self.delay_2ms();
+        self.send_gray_luts();
+        self.send_command(DisplayCommand::DisplayRefresh);
+        // Give the display some time to deassert the busy pin.
+        self.delay_10ms();
+    }
+
+    fn draw_gray_row(&mut self, row: &[u8]) {
+        assert!(row.len() >= Self::WIDTH as usize / 4);
+        // The first plane contains the high bits, the second plane the low
+        // bits of the gray levels.
+        let shift = 1 - self.gray_plane;
         for i in 0..Self::WIDTH as usize / 8 {
-            self.send_data(row[i]);
+            let pixels = ((row[2 * i] as u16) << 8) | row[2 * i + 1] as u16;
+            let mut data = 0;
+            for pixel in 0..8 {
+                let level = (pixels >> (14 - 2 * pixel)) & 3;
+                data |= (((level >> shift) & 1) as u8) << (7 - pixel);
+            }
+            self.send_data(data);
         }
     }
 }
 
+const PANEL_SETTING_UD: u8 = 0x08;
+const PANEL_SETTING_SHL: u8 = 0x04;
+
+/// Grayscale LUTs from the reference code for the black/white version of the
+/// 4.2" panel. The first bit plane is sent as the "old" and the second as the
+/// "new" data, so every combination selects one of the LUTs for the four
+/// levels.
+const GRAY_LUT_VCOM: [u8; 44] = [
+    0x00, 0x0a, 0x00, 0x00, 0x00, 0x01, 0x60, 0x14, 0x14, 0x00, 0x00, 0x01, 0x00, 0x14, 0x00, 0x00,
+    0x00, 0x01, 0x00, 0x13, 0x0a, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+const GRAY_LUT_WW: [u8; 42] = [
+    0x40, 0x0a, 0x00, 0x00, 0x00, 0x01, 0x90, 0x14, 0x14, 0x00, 0x00, 0x01, 0x10, 0x14, 0x0a, 0x00,
+    0x00, 0x01, 0xa0, 0x13, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+const GRAY_LUT_BW: [u8; 42] = [
+    0x40, 0x0a, 0x00, 0x00, 0x00, 0x01, 0x90, 0x14, 0x14, 0x00, 0x00, 0x01, 0x00, 0x14, 0x0a, 0x00,
+    0x00, 0x01, 0x99, 0x0c, 0x01, 0x03, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+const GRAY_LUT_WB: [u8; 42] = [
+    0x40, 0x0a, 0x00, 0x00, 0x00, 0x01, 0x90, 0x14, 0x14, 0x00, 0x00, 0x01, 0x00, 0x14, 0x0a, 0x00,
+    0x00, 0x01, 0x99, 0x0b, 0x04, 0x04, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+const GRAY_LUT_BB: [u8; 42] = [
+    0x80, 0x0a, 0x00, 0x00, 0x00, 0x01, 0x90, 0x14, 0x14, 0x00, 0x00, 0x01, 0x20, 0x14, 0x0a, 0x00,
+    0x00, 0x01, 0x50, 0x13, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[derive(Copy, Clone)]
 enum DisplayCommand {
     PanelSetting = 0x00,
     _PowerSetting = 0x01,
@@ -206,11 +372,11 @@ enum DisplayCommand {
     _DataStop = 0x11,
     DisplayRefresh = 0x12,
     DataStartTransmission2 = 0x13,
-    _LUTForVCOM = 0x20,
-    _LUTWhiteToWhite = 0x21,
-    _LUTBlackToWhite = 0x22,
-    _LUTWhiteToBlack = 0x23,
-    _LUTBlackToBlack = 0x24,
+    LUTForVCOM = 0x20,
+    LUTWhiteToWhite = 0x21,
+    LUTBlackToWhite = 0x22,
+    LUTWhiteToBlack = 0x23,
+    LUTBlackToBlack = 0x24,
     _PllControl = 0x30,
     _TemperatureSensorCommand = 0x40,
     _TemperatureSensorSelection = 0x41,
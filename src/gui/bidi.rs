@@ -0,0 +1,485 @@
+//! Display of right-to-left text, such as Hebrew and Arabic.
+//!
+//! Fonts always render characters from left to right in the order in which
+//! they are stored. `to_visual()` converts a line of text from logical order
+//! into display order following the Unicode Bidirectional Algorithm (UAX #9)
+//! and replaces Arabic letters with their contextual presentation forms, so
+//! the result can be displayed with any text element:
+//!
+//! ```ignore
+//! let mut label = DynamicText::<64>::new("", &ARABIC);
+//! label.set_text(&bidi::to_visual::<64>("السعر: 12", Direction::Auto));
+//! ```
+//!
+//! The presentation forms (U+FE70 to U+FEFF) have to be included in the font,
+//! e.g., with `--chars 0x20-0x7e,0x600-0x6ff,0xfe70-0xfeff` for `epd-font`.
+//!
+//! Explicit embeddings, overrides and isolates (U+202A to U+202E and U+2066
+//! to U+2069) are not supported. The text is processed as a single line, so
+//! wrapped paragraphs need to be converted line by line.
+
+use heapless::{String, Vec};
+
+/// Base direction of a line of text.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Direction {
+    /// The direction is determined by the first strong character, defaulting
+    /// to left-to-right.
+    Auto,
+    LeftToRight,
+    RightToLeft,
+}
+
+/// Bidirectional character type.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Class {
+    Left,
+    Right,
+    ArabicLetter,
+    EuropeanNumber,
+    EuropeanSeparator,
+    EuropeanTerminator,
+    ArabicNumber,
+    CommonSeparator,
+    NonspacingMark,
+    SegmentSeparator,
+    Whitespace,
+    OtherNeutral,
+}
+
+fn class(c: char) -> Class {
+    match c as u32 {
+        0x30..=0x39 | 0xb2 | 0xb3 | 0xb9 | 0x6f0..=0x6f9 => Class::EuropeanNumber,
+        0x2b | 0x2d => Class::EuropeanSeparator,
+        0x23..=0x25 | 0xa2..=0xa5 | 0xb0 | 0xb1 | 0x66a | 0x2030..=0x2034 | 0x20a0..=0x20cf => {
+            Class::EuropeanTerminator
+        }
+        0x2c | 0x2e | 0x2f | 0x3a | 0xa0 => Class::CommonSeparator,
+        0x600..=0x605 | 0x660..=0x669 | 0x66b | 0x66c | 0x6dd => Class::ArabicNumber,
+        0x9..=0xd | 0x1c..=0x1f => Class::SegmentSeparator,
+        0x20 | 0x2000..=0x200a | 0x2028 | 0x205f | 0x3000 => Class::Whitespace,
+        0x200e => Class::Left,
+        0x200f => Class::Right,
+        0x61c => Class::ArabicLetter,
+        0x300..=0x36f
+        | 0x591..=0x5bd
+        | 0x5bf
+        | 0x5c1
+        | 0x5c2
+        | 0x5c4
+        | 0x5c5
+        | 0x5c7
+        | 0x610..=0x61a
+        | 0x64b..=0x65f
+        | 0x670
+        | 0x6d6..=0x6dc
+        | 0x6df..=0x6e4
+        | 0x6e7
+        | 0x6e8
+        | 0x6ea..=0x6ed => Class::NonspacingMark,
+        0x590..=0x5ff | 0x7c0..=0x85f | 0xfb1d..=0xfb4f | 0x10800..=0x10fff => Class::Right,
+        0x600..=0x7bf | 0x860..=0x8ff | 0xfb50..=0xfdff | 0xfe70..=0xfefe => Class::ArabicLetter,
+        0xaa | 0xb5 | 0xba => Class::Left,
+        0x0..=0x2f
+        | 0x3a..=0x40
+        | 0x5b..=0x60
+        | 0x7b..=0xbf
+        | 0xd7
+        | 0xf7
+        | 0x2010..=0x205e
+        | 0x2190..=0x2bff
+        | 0xfeff => Class::OtherNeutral,
+        _ => Class::Left,
+    }
+}
+
+/// Returns the character displayed in place of `c` in right-to-left text.
+fn mirror(c: char) -> char {
+    match c {
+        '(' => ')',
+        ')' => '(',
+        '<' => '>',
+        '>' => '<',
+        '[' => ']',
+        ']' => '[',
+        '{' => '}',
+        '}' => '{',
+        '«' => '»',
+        '»' => '«',
+        '‹' => '›',
+        '›' => '‹',
+        _ => c,
+    }
+}
+
+/// Converts a line of text into the order in which the characters are
+/// displayed from left to right.
+///
+/// Arabic letters are replaced with their contextual forms, and brackets in
+/// right-to-left runs are mirrored. Text which does not fit into `N` bytes
+/// after shaping is truncated at the end.
+pub fn to_visual<const N: usize>(text: &str, direction: Direction) -> String<N> {
+    let mut chars = shape::<N>(text);
+    let mut classes: Vec<Class, N> = chars.iter().map(|&c| class(c)).collect();
+    let paragraph_level = match direction {
+        Direction::Auto => classes
+            .iter()
+            .find_map(|class| match class {
+                Class::Left => Some(0),
+                Class::Right | Class::ArabicLetter => Some(1),
+                _ => None,
+            })
+            .unwrap_or(0),
+        Direction::LeftToRight => 0,
+        Direction::RightToLeft => 1,
+    };
+    resolve_weak_types(&mut classes, paragraph_level);
+    resolve_neutral_types(&mut classes, paragraph_level);
+
+    // Implicit levels (rules I1 and I2).
+    let mut levels: Vec<u8, N> = classes
+        .iter()
+        .map(|class| match (paragraph_level, class) {
+            (0, Class::Right) => 1,
+            (0, Class::EuropeanNumber) | (0, Class::ArabicNumber) => 2,
+            (0, _) => 0,
+            (_, Class::Left) | (_, Class::EuropeanNumber) | (_, Class::ArabicNumber) => 2,
+            (_, _) => 1,
+        })
+        .collect();
+
+    // Separators and trailing whitespace are reset to the paragraph level
+    // (rule L1).
+    let mut trailing = true;
+    for (&c, level) in chars.iter().zip(levels.iter_mut()).rev() {
+        match class(c) {
+            Class::SegmentSeparator => {
+                *level = paragraph_level;
+                trailing = true;
+            }
+            Class::Whitespace if trailing => *level = paragraph_level,
+            _ => trailing = false,
+        }
+    }
+
+    // Mirroring (rule L4) and reordering (rule L2).
+    for (c, level) in chars.iter_mut().zip(levels.iter()) {
+        if level % 2 == 1 {
+            *c = mirror(*c);
+        }
+    }
+    let highest = levels.iter().cloned().max().unwrap_or(0);
+    let lowest_odd = levels
+        .iter()
+        .filter(|level| *level % 2 == 1)
+        .cloned()
+        .min()
+        .unwrap_or(highest + 1);
+    for level in (lowest_odd..=highest).rev() {
+        let mut i = 0;
+        while i < levels.len() {
+            if levels[i] < level {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < levels.len() && levels[i] >= level {
+                i += 1;
+            }
+            chars[start..i].reverse();
+            levels[start..i].reverse();
+        }
+    }
+
+    let mut result = String::new();
+    for c in chars {
+        // The shaped text is limited to N bytes, so this cannot fail.
+        result.push(c).ok();
+    }
+    result
+}
+
+/// Resolves numbers, separators and nonspacing marks (rules W1 to W7).
+fn resolve_weak_types(classes: &mut [Class], paragraph_level: u8) {
+    let sos = if paragraph_level == 0 {
+        Class::Left
+    } else {
+        Class::Right
+    };
+    let mut previous = sos;
+    for class in classes.iter_mut() {
+        if *class == Class::NonspacingMark {
+            *class = previous;
+        } else {
+            previous = *class;
+        }
+    }
+    let mut strong = sos;
+    for class in classes.iter_mut() {
+        match *class {
+            Class::Left | Class::Right | Class::ArabicLetter => strong = *class,
+            Class::EuropeanNumber if strong == Class::ArabicLetter => *class = Class::ArabicNumber,
+            _ => {}
+        }
+    }
+    for class in classes.iter_mut() {
+        if *class == Class::ArabicLetter {
+            *class = Class::Right;
+        }
+    }
+    for i in 1..classes.len().saturating_sub(1) {
+        let (before, after) = (classes[i - 1], classes[i + 1]);
+        match classes[i] {
+            Class::EuropeanSeparator
+                if before == Class::EuropeanNumber && after == Class::EuropeanNumber =>
+            {
+                classes[i] = Class::EuropeanNumber
+            }
+            Class::CommonSeparator
+                if before == after
+                    && (before == Class::EuropeanNumber || before == Class::ArabicNumber) =>
+            {
+                classes[i] = before
+            }
+            _ => {}
+        }
+    }
+    let mut i = 0;
+    while i < classes.len() {
+        if classes[i] != Class::EuropeanTerminator {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < classes.len() && classes[i] == Class::EuropeanTerminator {
+            i += 1;
+        }
+        let adjacent = (start > 0 && classes[start - 1] == Class::EuropeanNumber)
+            || (i < classes.len() && classes[i] == Class::EuropeanNumber);
+        if adjacent {
+            for class in &mut classes[start..i] {
+                *class = Class::EuropeanNumber;
+            }
+        }
+    }
+    for class in classes.iter_mut() {
+        match *class {
+            Class::EuropeanSeparator | Class::EuropeanTerminator | Class::CommonSeparator => {
+                *class = Class::OtherNeutral
+            }
+            _ => {}
+        }
+    }
+    let mut strong = sos;
+    for class in classes.iter_mut() {
+        match *class {
+            Class::Left | Class::Right => strong = *class,
+            Class::EuropeanNumber if strong == Class::Left => *class = Class::Left,
+            _ => {}
+        }
+    }
+}
+
+/// Resolves whitespace and other neutral characters (rules N1 and N2).
+fn resolve_neutral_types(classes: &mut [Class], paragraph_level: u8) {
+    let embedding = if paragraph_level == 0 {
+        Class::Left
+    } else {
+        Class::Right
+    };
+    let is_neutral = |class: Class| {
+        class == Class::Whitespace
+            || class == Class::SegmentSeparator
+            || class == Class::OtherNeutral
+    };
+    // Numbers are treated as right-to-left characters.
+    let direction = |class: Class| {
+        if class == Class::Left {
+            Class::Left
+        } else {
+            Class::Right
+        }
+    };
+    let mut i = 0;
+    while i < classes.len() {
+        if !is_neutral(classes[i]) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < classes.len() && is_neutral(classes[i]) {
+            i += 1;
+        }
+        let before = if start == 0 {
+            embedding
+        } else {
+            direction(classes[start - 1])
+        };
+        let after = if i == classes.len() {
+            embedding
+        } else {
+            direction(classes[i])
+        };
+        let resolved = if before == after { before } else { embedding };
+        for class in &mut classes[start..i] {
+            *class = resolved;
+        }
+    }
+}
+
+/// Joining behavior of Arabic characters.
+#[derive(Copy, Clone, PartialEq)]
+enum Joining {
+    /// Joins with the previous and the following character.
+    Dual,
+    /// Only joins with the previous character.
+    Right,
+    /// Joins on both sides without changing its form (tatweel, ZWJ).
+    Causing,
+    /// Is skipped when determining the neighbors of a character (marks).
+    Transparent,
+    None,
+}
+
+fn joining(c: char) -> Joining {
+    match c as u32 {
+        0x622..=0x625 | 0x627 | 0x629 | 0x62f..=0x632 | 0x648 | 0x649 => Joining::Right,
+        0x626 | 0x628 | 0x62a..=0x62e | 0x633..=0x63a | 0x641..=0x647 | 0x64a => Joining::Dual,
+        0x640 | 0x200d => Joining::Causing,
+        0x610..=0x61a
+        | 0x64b..=0x65f
+        | 0x670
+        | 0x6d6..=0x6dc
+        | 0x6df..=0x6e4
+        | 0x6e7
+        | 0x6e8
+        | 0x6ea..=0x6ed => Joining::Transparent,
+        _ => Joining::None,
+    }
+}
+
+/// Returns the isolated presentation form of an Arabic letter. The final,
+/// initial and medial forms of dual-joining letters follow it.
+fn isolated_form(c: char) -> Option<u32> {
+    let c = c as u32;
+    Some(match c {
+        0x621 => 0xfe80,
+        0x622..=0x625 => 0xfe81 + (c - 0x622) * 2,
+        0x626 => 0xfe89,
+        0x627 => 0xfe8d,
+        0x628 => 0xfe8f,
+        0x629 => 0xfe93,
+        0x62a..=0x62e => 0xfe95 + (c - 0x62a) * 4,
+        0x62f..=0x632 => 0xfea9 + (c - 0x62f) * 2,
+        0x633..=0x63a => 0xfeb1 + (c - 0x633) * 4,
+        0x641..=0x647 => 0xfed1 + (c - 0x641) * 4,
+        0x648 => 0xfeed,
+        0x649 => 0xfeef,
+        0x64a => 0xfef1,
+        _ => return None,
+    })
+}
+
+/// Returns the isolated form of the ligature of lam and an alef.
+fn lam_alef(alef: char) -> Option<u32> {
+    match alef {
+        '\u{622}' => Some(0xfef5),
+        '\u{623}' => Some(0xfef7),
+        '\u{625}' => Some(0xfef9),
+        '\u{627}' => Some(0xfefb),
+        _ => None,
+    }
+}
+
+/// Replaces Arabic letters with their contextual forms and lam-alef
+/// ligatures. The result is limited to `N` bytes.
+fn shape<const N: usize>(text: &str) -> Vec<char, N> {
+    let chars: Vec<char, N> = text.chars().take(N).collect();
+    let mut shaped = Vec::new();
+    let mut bytes = 0;
+    let mut joins_next = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let class = joining(c);
+        let joins_previous = joins_next && class != Joining::None;
+        if class != Joining::Transparent {
+            joins_next = false;
+        }
+        let next = chars[i + 1..]
+            .iter()
+            .find(|&&c| joining(c) != Joining::Transparent)
+            .map(|&c| joining(c));
+        let (form, length) = match chars.get(i + 1).cloned().and_then(lam_alef) {
+            Some(ligature) if c == '\u{644}' => (ligature + joins_previous as u32, 2),
+            _ => {
+                if class == Joining::Dual || class == Joining::Causing {
+                    joins_next = next.map(|next| next != Joining::None).unwrap_or(false);
+                }
+                let form = isolated_form(c).map(|isolated| match (joins_previous, joins_next) {
+                    (false, false) => isolated,
+                    (true, false) => isolated + 1,
+                    (false, true) => isolated + 2,
+                    (true, true) => isolated + 3,
+                });
+                (form.unwrap_or(c as u32), 1)
+            }
+        };
+        let form = core::char::from_u32(form).unwrap_or(c);
+        bytes += form.len_utf8();
+        if bytes > N || shaped.push(form).is_err() {
+            break;
+        }
+        i += length;
+    }
+    shaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_visual, Direction};
+
+    fn visual(text: &str, direction: Direction) -> std::string::String {
+        std::string::String::from(to_visual::<64>(text, direction).as_str())
+    }
+
+    #[test]
+    fn test_reordering() {
+        assert_eq!(visual("abc", Direction::Auto), "abc");
+        assert_eq!(visual("שלום", Direction::Auto), "םולש");
+        // Numbers keep their order, and neutrals take the direction of the
+        // surrounding text.
+        assert_eq!(visual("abc אבג 123", Direction::Auto), "abc 123 גבא");
+        assert_eq!(visual("abc אבג", Direction::RightToLeft), "גבא abc");
+        // Brackets are mirrored in right-to-left text.
+        assert_eq!(visual("שלום (abc)", Direction::Auto), "(abc) םולש");
+        // Trailing whitespace stays at the end.
+        assert_eq!(visual("אב ", Direction::LeftToRight), "בא ");
+        assert_eq!(to_visual::<4>("abcdef", Direction::Auto).as_str(), "abcd");
+    }
+
+    #[test]
+    fn test_arabic_shaping() {
+        // Beh (initial), yeh (medial), teh (final).
+        assert_eq!(
+            visual("\u{628}\u{64a}\u{62a}", Direction::Auto),
+            "\u{fe96}\u{fef4}\u{fe91}"
+        );
+        // Seen (initial), lam-alef ligature (final), meem (isolated, as alef
+        // does not join the following letter).
+        assert_eq!(
+            visual("\u{633}\u{644}\u{627}\u{645}", Direction::Auto),
+            "\u{fee1}\u{fefc}\u{feb3}"
+        );
+        // Numbers after Arabic letters are Arabic numbers.
+        assert_eq!(
+            visual("\u{639}\u{62f}\u{62f} 12", Direction::Auto),
+            "12 \u{fea9}\u{feaa}\u{fecb}"
+        );
+        // Marks do not break the joining.
+        assert_eq!(
+            visual("\u{628}\u{64e}\u{62a}", Direction::Auto),
+            "\u{fe96}\u{64e}\u{fe91}"
+        );
+    }
+}
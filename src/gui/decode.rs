@@ -0,0 +1,612 @@
+//! Decoders for standard image files, e.g., images downloaded into a buffer
+//! at runtime.
+//!
+//! The images are decoded row by row while rendering, so no framebuffer or
+//! conversion step is needed. Supported are binary PBM files (`P4`),
+//! uncompressed BMP files with 1 or 24 bits per pixel and QOI files. Color
+//! images are converted to luminance and dithered, transparent pixels are
+//! drawn on a white background.
+
+use super::GUIElement;
+use crate::{BitOrder, ClipRect, ClipRow, Dither, Polarity, RowRenderer};
+
+use core::cell::RefCell;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    /// The file format or a feature of the file is not supported.
+    Unsupported,
+    /// The header is invalid or the data is truncated.
+    Invalid,
+}
+
+/// Image file which is decoded row by row.
+pub trait ImageDecoder {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+
+    /// Renders row `y` of the image at `offset`. Rows should be rendered from
+    /// top to bottom, as some formats have to decode all previous rows
+    /// otherwise. `y` has to be smaller than `height()`, nothing is drawn for
+    /// other rows.
+    fn render_row(
+        &mut self,
+        row: &mut RowRenderer,
+        clip: &ClipRow,
+        y: u32,
+        offset: i32,
+        dither: &mut Dither,
+    );
+}
+
+/// Converts a color to luminance, with transparent pixels drawn on white.
+fn luminance(r: u8, g: u8, b: u8, a: u8) -> u8 {
+    let value = (r as u32 * 77 + g as u32 * 150 + b as u32 * 29) >> 8;
+    ((value * a as u32 + 255 * (255 - a as u32)) / 255) as u8
+}
+
+/// Binary PBM file (`P4`).
+pub struct Pbm<'a> {
+    data: &'a [u8],
+    width: u32,
+    height: u32,
+}
+
+impl<'a> Pbm<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Pbm<'a>, DecodeError> {
+        match data.get(..2) {
+            Some(b"P4") => {}
+            Some(b"P1") => return Err(DecodeError::Unsupported),
+            _ => return Err(DecodeError::Invalid),
+        }
+        let mut pos = 2;
+        let mut fields = [0u32; 2];
+        for field in fields.iter_mut() {
+            // Whitespace and comments.
+            loop {
+                match data.get(pos) {
+                    Some(b'#') => {
+                        while data.get(pos).ok_or(DecodeError::Invalid)? != &b'\n' {
+                            pos += 1;
+                        }
+                    }
+                    Some(c) if c.is_ascii_whitespace() => pos += 1,
+                    _ => break,
+                }
+            }
+            let start = pos;
+            while let Some(c) = data.get(pos).filter(|c| c.is_ascii_digit()) {
+                *field = field
+                    .checked_mul(10)
+                    .and_then(|value| value.checked_add((c - b'0') as u32))
+                    .ok_or(DecodeError::Invalid)?;
+                pos += 1;
+            }
+            if pos == start {
+                return Err(DecodeError::Invalid);
+            }
+        }
+        // A single whitespace character separates the header from the data.
+        match data.get(pos) {
+            Some(c) if c.is_ascii_whitespace() => pos += 1,
+            _ => return Err(DecodeError::Invalid),
+        }
+        let [width, height] = fields;
+        let size = (width as usize)
+            .div_ceil(8)
+            .checked_mul(height as usize)
+            .ok_or(DecodeError::Invalid)?;
+        let data = pos
+            .checked_add(size)
+            .and_then(|end| data.get(pos..end))
+            .ok_or(DecodeError::Invalid)?;
+        Ok(Pbm {
+            data,
+            width,
+            height,
+        })
+    }
+}
+
+impl<'a> ImageDecoder for Pbm<'a> {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn render_row(
+        &mut self,
+        row: &mut RowRenderer,
+        clip: &ClipRow,
+        y: u32,
+        offset: i32,
+        _dither: &mut Dither,
+    ) {
+        if y >= self.height {
+            return;
+        }
+        let stride = (self.width as usize).div_ceil(8);
+        let start = y as usize * stride;
+        row.render_bitmap(
+            clip,
+            offset,
+            offset + self.width as i32,
+            &self.data[start..start + stride],
+            BitOrder::MsbFirst,
+            Polarity::OneIsBlack,
+        );
+    }
+}
+
+/// Uncompressed BMP file with 1 or 24 bits per pixel.
+pub struct Bmp<'a> {
+    /// Pixel data, starting with the bottom row unless `top_down` is set.
+    data: &'a [u8],
+    width: u32,
+    height: u32,
+    top_down: bool,
+    bits: u16,
+    /// Polarity of the palette of 1-bit images.
+    polarity: Polarity,
+}
+
+impl<'a> Bmp<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Bmp<'a>, DecodeError> {
+        let u16_at = |pos: usize| -> Result<u16, DecodeError> {
+            let bytes = data.get(pos..pos + 2).ok_or(DecodeError::Invalid)?;
+            Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+        };
+        let u32_at = |pos: usize| -> Result<u32, DecodeError> {
+            let bytes = data.get(pos..pos + 4).ok_or(DecodeError::Invalid)?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+        if data.get(..2) != Some(b"BM") {
+            return Err(DecodeError::Invalid);
+        }
+        let pixels = u32_at(10)? as usize;
+        let header_size = u32_at(14)? as usize;
+        // The older BITMAPCOREHEADER is not supported.
+        if header_size < 40 {
+            return Err(DecodeError::Unsupported);
+        }
+        let width = u32_at(18)? as i32;
+        let height = u32_at(22)? as i32;
+        let bits = u16_at(28)?;
+        let compression = u32_at(30)?;
+        if compression != 0 || (bits != 1 && bits != 24) {
+            return Err(DecodeError::Unsupported);
+        }
+        if width < 0 || height == i32::MIN {
+            return Err(DecodeError::Invalid);
+        }
+        let (width, top_down, height) = (width as u32, height < 0, height.unsigned_abs());
+
+        let polarity = if bits == 1 {
+            // The palette follows the header with four bytes (BGRx) per
+            // color.
+            let color = |index: usize| -> Result<u8, DecodeError> {
+                let entry = header_size
+                    .checked_add(14 + index * 4)
+                    .and_then(|start| data.get(start..start.checked_add(3)?))
+                    .ok_or(DecodeError::Invalid)?;
+                Ok(luminance(entry[2], entry[1], entry[0], 255))
+            };
+            if color(1)? < color(0)? {
+                Polarity::OneIsBlack
+            } else {
+                Polarity::OneIsWhite
+            }
+        } else {
+            Polarity::OneIsWhite
+        };
+
+        let stride = (width as usize)
+            .checked_mul(bits as usize)
+            .ok_or(DecodeError::Invalid)?
+            .div_ceil(32)
+            * 4;
+        let data = stride
+            .checked_mul(height as usize)
+            .and_then(|size| data.get(pixels..pixels.checked_add(size)?))
+            .ok_or(DecodeError::Invalid)?;
+        Ok(Bmp {
+            data,
+            width,
+            height,
+            top_down,
+            bits,
+            polarity,
+        })
+    }
+}
+
+impl<'a> ImageDecoder for Bmp<'a> {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn render_row(
+        &mut self,
+        row: &mut RowRenderer,
+        clip: &ClipRow,
+        y: u32,
+        offset: i32,
+        dither: &mut Dither,
+    ) {
+        if y >= self.height {
+            return;
+        }
+        let stride = (self.width as usize * self.bits as usize).div_ceil(32) * 4;
+        let index = if self.top_down {
+            y
+        } else {
+            self.height - 1 - y
+        };
+        let data = &self.data[index as usize * stride..(index as usize + 1) * stride];
+        let right = offset + self.width as i32;
+        if self.bits == 1 {
+            row.render_bitmap(clip, offset, right, data, BitOrder::MsbFirst, self.polarity);
+        } else {
+            let pixels = data
+                .chunks_exact(3)
+                .map(|bgr| luminance(bgr[2], bgr[1], bgr[0], 255));
+            row.render_gray_pixels(clip, offset, right, pixels, dither);
+        }
+    }
+}
+
+/// QOI file ("Quite OK Image Format").
+///
+/// The format can only be decoded sequentially, so the decoder keeps its
+/// state between rows. Rendering a row above the last rendered row restarts
+/// decoding at the beginning of the image.
+pub struct Qoi<'a> {
+    data: &'a [u8],
+    width: u32,
+    height: u32,
+    /// Position of the next operation in `data`.
+    pos: usize,
+    /// Next row to be decoded.
+    y: u32,
+    /// Previously seen pixels (RGBA).
+    index: [[u8; 4]; 64],
+    pixel: [u8; 4],
+    /// Number of remaining repetitions of `pixel`.
+    run: u8,
+}
+
+const QOI_HEADER_SIZE: usize = 14;
+/// Maximum number of pixels allowed by the specification.
+const QOI_PIXELS_MAX: u64 = 400_000_000;
+
+impl<'a> Qoi<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Qoi<'a>, DecodeError> {
+        if data.len() < QOI_HEADER_SIZE || &data[..4] != b"qoif" {
+            return Err(DecodeError::Invalid);
+        }
+        let width = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        let height = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+        if width as u64 * height as u64 > QOI_PIXELS_MAX {
+            return Err(DecodeError::Invalid);
+        }
+        let mut image = Qoi {
+            data,
+            width,
+            height,
+            pos: 0,
+            y: 0,
+            index: [[0; 4]; 64],
+            pixel: [0; 4],
+            run: 0,
+        };
+        image.restart();
+        Ok(image)
+    }
+
+    fn restart(&mut self) {
+        self.pos = QOI_HEADER_SIZE;
+        self.y = 0;
+        self.index = [[0; 4]; 64];
+        self.pixel = [0, 0, 0, 255];
+        self.run = 0;
+    }
+
+    fn byte(&mut self) -> u8 {
+        // Truncated images continue with the last pixel.
+        let byte = self.data.get(self.pos).copied().unwrap_or(0xc0);
+        self.pos += 1;
+        byte
+    }
+
+    /// Returns the next pixel (RGBA).
+    fn next_pixel(&mut self) -> [u8; 4] {
+        if self.run > 0 {
+            self.run -= 1;
+            return self.pixel;
+        }
+        let op = self.byte();
+        let [r, g, b, a] = self.pixel;
+        self.pixel = match op {
+            0xfe => [self.byte(), self.byte(), self.byte(), a],
+            0xff => [self.byte(), self.byte(), self.byte(), self.byte()],
+            _ => match op >> 6 {
+                0 => self.index[op as usize],
+                1 => [
+                    r.wrapping_add((op >> 4) & 3).wrapping_sub(2),
+                    g.wrapping_add((op >> 2) & 3).wrapping_sub(2),
+                    b.wrapping_add(op & 3).wrapping_sub(2),
+                    a,
+                ],
+                2 => {
+                    let dg = (op & 0x3f).wrapping_sub(32);
+                    let next = self.byte();
+                    [
+                        r.wrapping_add(dg).wrapping_add(next >> 4).wrapping_sub(8),
+                        g.wrapping_add(dg),
+                        b.wrapping_add(dg).wrapping_add(next & 0xf).wrapping_sub(8),
+                        a,
+                    ]
+                }
+                _ => {
+                    self.run = op & 0x3f;
+                    self.pixel
+                }
+            },
+        };
+        let [r, g, b, a] = self.pixel;
+        let hash = (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64;
+        self.index[hash] = self.pixel;
+        self.pixel
+    }
+}
+
+impl<'a> ImageDecoder for Qoi<'a> {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn render_row(
+        &mut self,
+        row: &mut RowRenderer,
+        clip: &ClipRow,
+        y: u32,
+        offset: i32,
+        dither: &mut Dither,
+    ) {
+        if y >= self.height {
+            return;
+        }
+        if y < self.y {
+            self.restart();
+        }
+        while self.y < y {
+            for _ in 0..self.width {
+                self.next_pixel();
+            }
+            self.y += 1;
+        }
+        let width = self.width;
+        let pixels = (0..width).map(|_| {
+            let [r, g, b, a] = self.next_pixel();
+            luminance(r, g, b, a)
+        });
+        row.render_gray_pixels(clip, offset, offset + width as i32, pixels, dither);
+        self.y += 1;
+    }
+}
+
+/// Image file in any of the supported formats.
+// The state of the QOI decoder is large, but there is no heap to box it.
+#[allow(clippy::large_enum_variant)]
+pub enum AnyImage<'a> {
+    Pbm(Pbm<'a>),
+    Bmp(Bmp<'a>),
+    Qoi(Qoi<'a>),
+}
+
+impl<'a> AnyImage<'a> {
+    /// Detects the format of the file from its header.
+    pub fn new(data: &'a [u8]) -> Result<AnyImage<'a>, DecodeError> {
+        match data.get(..2) {
+            Some(b"P4") | Some(b"P1") => Pbm::new(data).map(AnyImage::Pbm),
+            Some(b"BM") => Bmp::new(data).map(AnyImage::Bmp),
+            Some(b"qo") => Qoi::new(data).map(AnyImage::Qoi),
+            _ => Err(DecodeError::Unsupported),
+        }
+    }
+
+    fn decoder(&self) -> &dyn ImageDecoder {
+        match self {
+            AnyImage::Pbm(image) => image,
+            AnyImage::Bmp(image) => image,
+            AnyImage::Qoi(image) => image,
+        }
+    }
+}
+
+impl<'a> ImageDecoder for AnyImage<'a> {
+    fn width(&self) -> u32 {
+        self.decoder().width()
+    }
+
+    fn height(&self) -> u32 {
+        self.decoder().height()
+    }
+
+    fn render_row(
+        &mut self,
+        row: &mut RowRenderer,
+        clip: &ClipRow,
+        y: u32,
+        offset: i32,
+        dither: &mut Dither,
+    ) {
+        match self {
+            AnyImage::Pbm(image) => image.render_row(row, clip, y, offset, dither),
+            AnyImage::Bmp(image) => image.render_row(row, clip, y, offset, dither),
+            AnyImage::Qoi(image) => image.render_row(row, clip, y, offset, dither),
+        }
+    }
+}
+
+/// Element displaying an image file, e.g., `AnyImage`.
+pub struct DecodedImage<'a, D: ImageDecoder = AnyImage<'a>> {
+    decoder: RefCell<D>,
+    dither: RefCell<Dither<'a>>,
+}
+
+impl<'a, D: ImageDecoder> DecodedImage<'a, D> {
+    /// Creates the element. `dither` is only used for grayscale and color
+    /// images.
+    pub fn new(decoder: D, dither: Dither<'a>) -> DecodedImage<'a, D> {
+        DecodedImage {
+            decoder: RefCell::new(decoder),
+            dither: RefCell::new(dither),
+        }
+    }
+}
+
+impl<'a, D: ImageDecoder> GUIElement for DecodedImage<'a, D> {
+    fn resize(&mut self, _width: u32, _height: u32) {
+        // Ignore, as the image dictates the size.
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        let decoder = self.decoder.borrow();
+        (decoder.width(), decoder.height())
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.min_size()
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        let mut decoder = self.decoder.borrow_mut();
+        if y < 0 || y as u32 >= decoder.height() {
+            return;
+        }
+        decoder.render_row(
+            row,
+            &clip.row(),
+            y as u32,
+            offset,
+            &mut self.dither.borrow_mut(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnyImage, DecodeError, DecodedImage, ImageDecoder};
+    use crate::gui::GUIElement;
+    use crate::{ClipRect, Dither, RowRenderer};
+
+    fn render_rows(image: &DecodedImage, rows: &[i32]) -> std::vec::Vec<u8> {
+        rows.iter()
+            .map(|&y| {
+                let mut buffer = [0u8];
+                let mut renderer = RowRenderer::new(&mut buffer, 8);
+                image.render_row(&mut renderer, &ClipRect::new(0, 0, 8, 8), y, 0);
+                buffer[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_formats() {
+        // 3x2 pixels, black in the top left and bottom right corners.
+        let pbm = b"P4\n# comment\n3 2\n\x80\x20";
+        let bmp: &[u8] = &[
+            b'B', b'M', 0, 0, 0, 0, 0, 0, 0, 0, 62, 0, 0, 0, // File header.
+            40, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // Bitmap header.
+            0xff, 0xff, 0xff, 0, 0, 0, 0, 0, // Palette with white as color 0.
+            0x20, 0, 0, 0, 0x80, 0, 0, 0, // Bottom row first.
+        ];
+        let mut bmp24 = bmp[..54].to_vec();
+        bmp24[10] = 54;
+        bmp24[28] = 24;
+        // Negative height, i.e., top row first.
+        bmp24[22..26].copy_from_slice(&(-2i32).to_le_bytes());
+        bmp24.extend_from_slice(&[0, 0, 0, 255, 255, 255, 255, 255, 255, 0, 0, 0]);
+        bmp24.extend_from_slice(&[255, 255, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0]);
+        let qoi: &[u8] = &[
+            b'q', b'o', b'i', b'f', 0, 0, 0, 3, 0, 0, 0, 2, 4, 0, // Header.
+            0xfe, 0, 0, 0, // Black.
+            0xfe, 255, 255, 255,  // White.
+            0xc2, // Run of 3 white pixels.
+            0x35, // Index of black.
+            0, 0, 0, 0, 0, 0, 0, 1, // End marker.
+        ];
+        for data in [&pbm[..], bmp, &bmp24, qoi].iter() {
+            let image = DecodedImage::new(AnyImage::new(data).unwrap(), Dither::Threshold);
+            assert_eq!(image.min_size(), (3, 2));
+            assert_eq!(render_rows(&image, &[0, 1]), [0x60, 0xc0]);
+            // Rendering out of order restarts QOI decoding.
+            assert_eq!(render_rows(&image, &[1, 0, 0]), [0xc0, 0x60, 0x60]);
+        }
+
+        assert!(matches!(
+            AnyImage::new(b"P1\n1 1\n0"),
+            Err(DecodeError::Unsupported)
+        ));
+        // Truncated and zero-size headers, and truncated data.
+        for data in [
+            &b"P4\n8 2\n\x00"[..],
+            b"P4 0 0",
+            b"P4 8",
+            b"P4\n# 1 1",
+            b"P4\n1 1x\x80",
+        ]
+        .iter()
+        {
+            assert!(matches!(AnyImage::new(data), Err(DecodeError::Invalid)));
+        }
+        assert_eq!(AnyImage::new(b"P4 0 0\n").unwrap().width(), 0);
+        for length in [2, 20, 54, 61, 69].iter() {
+            assert!(matches!(
+                AnyImage::new(&bmp[..*length]),
+                Err(DecodeError::Invalid)
+            ));
+        }
+        // Header size and width which overflow the offsets.
+        let mut invalid = bmp.to_vec();
+        invalid[14..18].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(AnyImage::new(&invalid), Err(DecodeError::Invalid)));
+        let mut invalid = bmp24.clone();
+        invalid[18..22].copy_from_slice(&i32::MAX.to_le_bytes());
+        assert!(matches!(AnyImage::new(&invalid), Err(DecodeError::Invalid)));
+        let mut invalid = qoi.to_vec();
+        invalid[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(AnyImage::new(&invalid), Err(DecodeError::Invalid)));
+        assert!(matches!(
+            AnyImage::new(&qoi[..13]),
+            Err(DecodeError::Invalid)
+        ));
+        let image = AnyImage::new(&bmp24).unwrap();
+        assert_eq!((image.width(), image.height()), (3, 2));
+
+        // Rows below the image are ignored.
+        for data in [&pbm[..], bmp, &bmp24, qoi].iter() {
+            let mut image = AnyImage::new(data).unwrap();
+            let mut buffer = [0xffu8];
+            let mut renderer = RowRenderer::new(&mut buffer, 8);
+            let clip = renderer.full_row();
+            for y in [2, u32::MAX].iter() {
+                image.render_row(&mut renderer, &clip, *y, 0, &mut Dither::Threshold);
+            }
+            assert_eq!(buffer, [0xff]);
+        }
+    }
+}
@@ -0,0 +1,503 @@
+//! Fonts and images which are rendered directly from external storage, e.g.,
+//! to update artwork without reflashing the firmware.
+//!
+//! The assets are stored in a container created by `epd-image` and
+//! `epd-font`. All values are little-endian:
+//!
+//! * Container: `CONTAINER_MAGIC`, `u16` version (`CONTAINER_VERSION`), `u16`
+//!   number of entries, followed by the entries. Every entry has 28 bytes: the
+//!   name (16 bytes, padded with zeros), the kind (`u8`, `ENTRY_IMAGE` or
+//!   `ENTRY_FONT`), 3 reserved bytes, and the `u32` offset and length of the
+//!   asset relative to the start of the container.
+//! * Image: format (`u8`, `IMAGE_BITMAP` or `IMAGE_RLE`), a reserved byte,
+//!   `u16` width and height, two reserved bytes and the image data. Bitmaps
+//!   have rows padded to whole bytes with the leftmost pixel in the most
+//!   significant bit and set bits black; RLE data is stored as for
+//!   `RLEImage`.
+//! * Font: `u16` ascender, descender, number of glyphs, number of character
+//!   ranges, number of kerning pairs and index of the replacement glyph
+//!   (`0xffff` for none). The ranges follow with 12 bytes each (`u32` first
+//!   and last character, `u16` index of the glyph of the first character, two
+//!   reserved bytes), then the glyphs with 16 bytes each (`u32` offset of the
+//!   RLE image relative to the font, `u16` width and height, `i16` left and
+//!   top, `u16` advance, two reserved bytes) and the kerning pairs with 8
+//!   bytes each (`u16` left and right glyph, `i16` offset, two reserved
+//!   bytes). Ranges and kerning pairs are sorted.
+//!
+//! All elements share the reader, which is usually a `BlockCache`:
+//!
+//! ```ignore
+//! let flash = RefCell::new(BlockCache::<_, 8, 256>::new(Storage(spi_flash)));
+//! let assets = Container::open(&flash, 0)?;
+//! let logo = assets.image("logo")?;
+//! let title = ExternalText::new("Hello", assets.font("sans")?);
+//! ```
+//!
+//! Read errors during rendering cannot be reported, so the affected rows are
+//! left empty. Invalid values, e.g., from an asset written for a different
+//! version of the firmware, are treated like missing glyphs or read errors.
+//!
+//! `ExternalFont` is not a `TextFont`, as glyphs cannot be borrowed from
+//! external storage. It can therefore only be displayed with `ExternalText`,
+//! not with `Paragraph`, `Overflow`, `RichText` or `FontChain`.
+
+use super::image::{RowImage, RunCollector};
+use super::GUIElement;
+use crate::storage::BlockRead;
+use crate::{ClipRect, ClipRow, RowRenderer};
+
+use core::cell::RefCell;
+use core::cmp::max;
+use core::convert::TryFrom;
+
+pub const CONTAINER_MAGIC: [u8; 4] = *b"EPDC";
+pub const CONTAINER_VERSION: u16 = 1;
+pub const ENTRY_IMAGE: u8 = 0;
+pub const ENTRY_FONT: u8 = 1;
+pub const IMAGE_BITMAP: u8 = 0;
+pub const IMAGE_RLE: u8 = 1;
+
+const ENTRY_SIZE: u32 = 28;
+const RANGE_SIZE: u32 = 12;
+const GLYPH_SIZE: u32 = 16;
+const KERNING_SIZE: u32 = 8;
+
+#[derive(Debug, PartialEq)]
+pub enum AssetError<E> {
+    Read(E),
+    /// The data is not a valid container or asset.
+    InvalidFormat,
+    /// The container has no asset with the name and kind.
+    NotFound,
+}
+
+fn read<R: BlockRead, const N: usize>(
+    reader: &RefCell<R>,
+    offset: u32,
+) -> Result<[u8; N], R::Error> {
+    let mut buffer = [0u8; N];
+    reader.borrow_mut().read(offset, &mut buffer)?;
+    Ok(buffer)
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+/// Directory of the assets in external storage.
+pub struct Container<'a, R: BlockRead> {
+    reader: &'a RefCell<R>,
+    offset: u32,
+    entries: u16,
+}
+
+impl<'a, R: BlockRead> Container<'a, R> {
+    /// Reads the header of the container at `offset`.
+    pub fn open(reader: &'a RefCell<R>, offset: u32) -> Result<Self, AssetError<R::Error>> {
+        let header: [u8; 8] = read(reader, offset).map_err(AssetError::Read)?;
+        if header[..4] != CONTAINER_MAGIC || u16_at(&header, 4) != CONTAINER_VERSION {
+            return Err(AssetError::InvalidFormat);
+        }
+        Ok(Container {
+            reader,
+            offset,
+            entries: u16_at(&header, 6),
+        })
+    }
+
+    /// Returns the offset of an asset in the storage.
+    fn find(&self, name: &str, kind: u8) -> Result<u32, AssetError<R::Error>> {
+        for i in 0..self.entries as u32 {
+            let entry: [u8; ENTRY_SIZE as usize] =
+                read(self.reader, self.offset.wrapping_add(8 + i * ENTRY_SIZE))
+                    .map_err(AssetError::Read)?;
+            let length = entry[..16].iter().position(|&b| b == 0).unwrap_or(16);
+            if &entry[..length] == name.as_bytes() && entry[16] == kind {
+                return self
+                    .offset
+                    .checked_add(u32_at(&entry, 20))
+                    .ok_or(AssetError::InvalidFormat);
+            }
+        }
+        Err(AssetError::NotFound)
+    }
+
+    pub fn image(&self, name: &str) -> Result<ExternalImage<'a, R>, AssetError<R::Error>> {
+        let offset = self.find(name, ENTRY_IMAGE)?;
+        let header: [u8; 8] = read(self.reader, offset).map_err(AssetError::Read)?;
+        if header[0] != IMAGE_BITMAP && header[0] != IMAGE_RLE {
+            return Err(AssetError::InvalidFormat);
+        }
+        Ok(ExternalImage {
+            reader: self.reader,
+            offset: offset.checked_add(8).ok_or(AssetError::InvalidFormat)?,
+            format: header[0],
+            width: u16_at(&header, 2),
+            height: u16_at(&header, 4),
+        })
+    }
+
+    pub fn font(&self, name: &str) -> Result<ExternalFont<'a, R>, AssetError<R::Error>> {
+        let offset = self.find(name, ENTRY_FONT)?;
+        let header: [u8; 12] = read(self.reader, offset).map_err(AssetError::Read)?;
+        let replacement = u16_at(&header, 10);
+        Ok(ExternalFont {
+            reader: self.reader,
+            offset,
+            ascender: u16_at(&header, 0),
+            descender: u16_at(&header, 2),
+            glyphs: u16_at(&header, 4),
+            ranges: u16_at(&header, 6),
+            kerning: u16_at(&header, 8),
+            replacement: if replacement == 0xffff {
+                None
+            } else {
+                Some(replacement)
+            },
+        })
+    }
+}
+
+/// Image which is read from external storage row by row while rendering.
+pub struct ExternalImage<'a, R: BlockRead> {
+    reader: &'a RefCell<R>,
+    /// Offset of the image data in the storage.
+    offset: u32,
+    format: u8,
+    width: u16,
+    height: u16,
+}
+
+impl<'a, R: BlockRead> Clone for ExternalImage<'a, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, R: BlockRead> Copy for ExternalImage<'a, R> {}
+
+impl<'a, R: BlockRead> ExternalImage<'a, R> {
+    fn bitmap_runs(&self, y: u32, run: &mut dyn FnMut(u32, u32)) -> Result<(), R::Error> {
+        let width = self.width as u32;
+        let stride = width.div_ceil(8);
+        let mut runs = RunCollector::new(run);
+        let mut buffer = [0u8; 16];
+        let mut pos = 0;
+        while pos < stride {
+            let chunk = &mut buffer[..(stride - pos).min(16) as usize];
+            self.reader
+                .borrow_mut()
+                .read(self.offset.wrapping_add(y * stride + pos), chunk)?;
+            for (i, byte) in chunk.iter().enumerate() {
+                let bits = (width - (pos + i as u32) * 8).min(8);
+                for bit in 0..bits {
+                    runs.push(byte & (0x80 >> bit) != 0);
+                }
+            }
+            pos += chunk.len() as u32;
+        }
+        runs.finish();
+        Ok(())
+    }
+
+    fn rle_runs(&self, y: u32, run: &mut dyn FnMut(u32, u32)) -> Result<(), R::Error> {
+        let table: [u8; 4] = read(self.reader, self.offset.wrapping_add(y * 2))?;
+        let (start, end) = (u16_at(&table, 0) as u32, u16_at(&table, 2) as u32);
+        let mut x = 0;
+        let mut buffer = [0u8; 16];
+        let mut index = start;
+        while index < end {
+            let count = (end - index).min(8);
+            let chunk = &mut buffer[..count as usize * 2];
+            self.reader
+                .borrow_mut()
+                .read(self.offset.wrapping_add(index * 2), chunk)?;
+            for i in 0..count as usize {
+                let entry = u16_at(chunk, i * 2);
+                let length = (entry & 0x7fff) as u32;
+                if entry & 0x8000 != 0 {
+                    run(x, x + length);
+                }
+                x += length;
+            }
+            index += count;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, R: BlockRead> RowImage for ExternalImage<'a, R> {
+    fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    fn height(&self) -> u32 {
+        self.height as u32
+    }
+
+    fn black_runs(&self, y: u32, run: &mut dyn FnMut(u32, u32)) {
+        let result = match self.format {
+            IMAGE_BITMAP => self.bitmap_runs(y, run),
+            _ => self.rle_runs(y, run),
+        };
+        // Errors cannot be reported while rendering.
+        result.ok();
+    }
+}
+
+impl<'a, R: BlockRead> GUIElement for ExternalImage<'a, R> {
+    fn resize(&mut self, _width: u32, _height: u32) {
+        // Ignore, as the image dictates the size.
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.min_size()
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        self.render_row_transparent(row, &clip.row(), y, offset);
+    }
+}
+
+/// Glyph of an `ExternalFont`.
+struct ExternalGlyph<'a, R: BlockRead> {
+    image: ExternalImage<'a, R>,
+    image_left: i16,
+    image_top: i16,
+    advance: u16,
+}
+
+/// Font which is read from external storage while rendering.
+///
+/// The font provides the same methods as `TextFont` and is displayed with
+/// `ExternalText`.
+pub struct ExternalFont<'a, R: BlockRead> {
+    reader: &'a RefCell<R>,
+    offset: u32,
+    ascender: u16,
+    descender: u16,
+    glyphs: u16,
+    ranges: u16,
+    kerning: u16,
+    replacement: Option<u16>,
+}
+
+impl<'a, R: BlockRead> Clone for ExternalFont<'a, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, R: BlockRead> Copy for ExternalFont<'a, R> {}
+
+impl<'a, R: BlockRead> ExternalFont<'a, R> {
+    pub fn ascender(&self) -> u16 {
+        self.ascender
+    }
+
+    pub fn descender(&self) -> u16 {
+        self.descender
+    }
+
+    /// Returns the height of a line, which does not fit into a `u16` for
+    /// invalid fonts.
+    fn height(&self) -> u32 {
+        self.ascender as u32 + self.descender as u32
+    }
+
+    fn ranges_offset(&self) -> u32 {
+        self.offset.wrapping_add(12)
+    }
+
+    fn glyphs_offset(&self) -> u32 {
+        self.ranges_offset()
+            .wrapping_add(self.ranges as u32 * RANGE_SIZE)
+    }
+
+    fn kerning_offset(&self) -> u32 {
+        self.glyphs_offset()
+            .wrapping_add(self.glyphs as u32 * GLYPH_SIZE)
+    }
+
+    /// Returns the index of the glyph of a character, without the
+    /// replacement glyph.
+    fn lookup(&self, c: char) -> Result<Option<u16>, R::Error> {
+        let c = c as u32;
+        let (mut low, mut high) = (0, self.ranges as u32);
+        while low < high {
+            let middle = (low + high) / 2;
+            let range: [u8; RANGE_SIZE as usize] = read(
+                self.reader,
+                self.ranges_offset().wrapping_add(middle * RANGE_SIZE),
+            )?;
+            let (first, last) = (u32_at(&range, 0), u32_at(&range, 4));
+            if c < first {
+                high = middle;
+            } else if c > last {
+                low = middle + 1;
+            } else {
+                // Indices beyond the glyph table are treated as missing.
+                let index = u16_at(&range, 8) as u32 + (c - first);
+                return Ok(u16::try_from(index).ok().filter(|&i| i < self.glyphs));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns whether a character can be displayed without the replacement
+    /// glyph.
+    pub fn has_glyph(&self, c: char) -> bool {
+        matches!(self.lookup(c), Ok(Some(_)))
+    }
+
+    fn glyph(&self, c: char) -> Option<(u16, ExternalGlyph<'a, R>)> {
+        let index = self.lookup(c).ok()?.or(self.replacement)?;
+        if index >= self.glyphs {
+            return None;
+        }
+        let glyph: [u8; GLYPH_SIZE as usize] = read(
+            self.reader,
+            self.glyphs_offset().wrapping_add(index as u32 * GLYPH_SIZE),
+        )
+        .ok()?;
+        let image = ExternalImage {
+            reader: self.reader,
+            offset: self.offset.checked_add(u32_at(&glyph, 0))?,
+            format: IMAGE_RLE,
+            width: u16_at(&glyph, 4),
+            height: u16_at(&glyph, 6),
+        };
+        Some((
+            index,
+            ExternalGlyph {
+                image,
+                image_left: u16_at(&glyph, 8) as i16,
+                image_top: u16_at(&glyph, 10) as i16,
+                advance: u16_at(&glyph, 12),
+            },
+        ))
+    }
+
+    /// Returns the horizontal adjustment between two glyphs.
+    pub fn kerning(&self, left: u16, right: u16) -> i32 {
+        let (mut low, mut high) = (0, self.kerning as u32);
+        while low < high {
+            let middle = (low + high) / 2;
+            let pair: [u8; KERNING_SIZE as usize] = match read(
+                self.reader,
+                self.kerning_offset().wrapping_add(middle * KERNING_SIZE),
+            ) {
+                Ok(pair) => pair,
+                Err(_) => return 0,
+            };
+            match (u16_at(&pair, 0), u16_at(&pair, 2)).cmp(&(left, right)) {
+                core::cmp::Ordering::Less => low = middle + 1,
+                core::cmp::Ordering::Greater => high = middle,
+                core::cmp::Ordering::Equal => return u16_at(&pair, 4) as i16 as i32,
+            }
+        }
+        0
+    }
+
+    /// Returns the glyphs of the text together with their pen positions.
+    fn layout<'b>(
+        &'b self,
+        text: &'b str,
+        offset: i32,
+    ) -> impl Iterator<Item = (ExternalGlyph<'a, R>, i32)> + 'b {
+        let mut pos = offset;
+        let mut previous = None;
+        text.chars()
+            .filter_map(move |c| self.glyph(c))
+            .map(move |(index, glyph)| {
+                if let Some(previous) = previous {
+                    pos += self.kerning(previous, index);
+                }
+                previous = Some(index);
+                let glyph_pos = pos;
+                pos += glyph.advance as i32;
+                (glyph, glyph_pos)
+            })
+    }
+
+    pub fn get_text_size(&self, text: &str) -> (u32, u32) {
+        let width = self
+            .layout(text, 0)
+            .map(|(glyph, pos)| pos + glyph.advance as i32)
+            .last()
+            .unwrap_or(0);
+        (max(width, 0) as u32, self.height())
+    }
+
+    pub fn render_row(
+        &self,
+        row: &mut RowRenderer,
+        clip: &ClipRow,
+        text: &str,
+        y: i32,
+        offset: i32,
+    ) {
+        if y < 0 || y as u32 >= self.height() {
+            return;
+        }
+        let (clip_left, clip_right) = clip.get();
+        for (glyph, pos) in self.layout(text, offset) {
+            let image_left = pos + glyph.image_left as i32;
+            // Only glyphs within the clipping area are read.
+            if image_left < clip_right && image_left + glyph.image.width as i32 > clip_left {
+                let image_y = y - self.ascender as i32 + glyph.image_top as i32;
+                glyph
+                    .image
+                    .render_row_transparent(row, clip, image_y, image_left);
+            }
+        }
+    }
+}
+
+/// Single line of text displayed with an `ExternalFont`.
+pub struct ExternalText<'a, R: BlockRead> {
+    text: &'a str,
+    font: ExternalFont<'a, R>,
+    width: u32,
+}
+
+impl<'a, R: BlockRead> ExternalText<'a, R> {
+    pub fn new(text: &'a str, font: ExternalFont<'a, R>) -> ExternalText<'a, R> {
+        ExternalText {
+            text,
+            font,
+            width: font.get_text_size(text).0,
+        }
+    }
+
+    pub fn set_text(&mut self, text: &'a str) {
+        self.text = text;
+        self.width = self.font.get_text_size(text).0;
+    }
+}
+
+impl<'a, R: BlockRead> GUIElement for ExternalText<'a, R> {
+    fn resize(&mut self, _width: u32, _height: u32) {
+        // Ignore, as the font dictates the size of the text.
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        (self.width, self.font.height())
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.min_size()
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        self.font.render_row(row, &clip.row(), self.text, y, offset);
+    }
+}
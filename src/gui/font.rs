@@ -1,72 +1,482 @@
-use super::image::RLEImage;
-use crate::{ClipRow, RowRenderer};
+use super::image::{HeatshrinkImage, NibbleImage, PackedImage, RLEImage, RowImage};
+use crate::{BandRenderer, ClipRow, Color, RowRenderer};
 
-pub struct Font {
-    pub ascender: u16,
-    pub descender: u16,
-    pub glyphs: &'static [Glyph],
-    pub get_glyph_index: fn(c: char) -> Option<usize>,
-}
+use core::cmp::{max, min};
 
-impl Font {
-    pub fn get_text_size(&self, text: &str) -> (u32, u32) {
-        let mut width = 0;
-        for c in text.chars() {
-            let index = (self.get_glyph_index)(c);
-            if index.is_none() {
-                continue;
-            }
-            let glyph = &self.glyphs[index.unwrap()];
-            width += glyph.advance;
-        }
-        (width, (self.ascender + self.descender) as u32)
+/// Font or combination of fonts which is used to measure and render text.
+///
+/// All glyphs of a line share a common baseline at `ascender()` rows below
+/// the top of the line.
+pub trait TextFont {
+    fn ascender(&self) -> u16;
+    fn descender(&self) -> u16;
+    /// Returns the font and the index of the glyph which displays a
+    /// character.
+    fn glyph(&self, c: char) -> Option<(&Font, usize)>;
+    /// Returns whether a character can be displayed without the replacement
+    /// glyph.
+    fn has_glyph(&self, c: char) -> bool;
+    /// Returns the factor by which the glyph images are enlarged.
+    fn scale(&self) -> u32 {
+        1
+    }
+
+    fn get_text_size(&self, text: &str) -> (u32, u32) {
+        let width = layout(self, text, 0)
+            .map(|(_, glyph, pos)| pos + (glyph.advance * self.scale()) as i32)
+            .last()
+            .unwrap_or(0);
+        (
+            max(width, 0) as u32,
+            (self.ascender() + self.descender()) as u32,
+        )
     }
 
-    pub fn render_row(
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRow, text: &str, y: i32, offset: i32) {
+        self.render_row_color(row, clip, text, y, offset, Color::Black);
+    }
+
+    /// Renders a row of the text with the glyphs drawn in `color`.
+    fn render_row_color(
         &self,
         row: &mut RowRenderer,
         clip: &ClipRow,
         text: &str,
         y: i32,
         offset: i32,
+        color: Color,
     ) {
         // TODO: Do we need to fill the background?
         //row.fill(clip, 0, core::i32::MAX, Color::White);
-        if y < 1 || y > (self.ascender + self.descender) as i32 {
+        if y < 0 || y >= (self.ascender() + self.descender()) as i32 {
             return;
         }
-        let mut pos = offset;
         // TODO: Discard glyphs if they are definitely not shown.
-        for c in text.chars() {
-            let index = (self.get_glyph_index)(c);
-            if index.is_none() {
-                continue;
-            }
-            let glyph = &self.glyphs[index.unwrap()];
-            self.render_glyph_row(row, clip, glyph, y, pos);
-            pos += glyph.advance as i32;
+        let scale = self.scale();
+        for (_, glyph, pos) in layout(self, text, offset) {
+            let baseline_y = y - self.ascender() as i32;
+            render_glyph_row(row, clip, glyph, baseline_y, pos, scale, color);
         }
     }
 
-    fn render_glyph_row(
+    /// Renders `band.rows()` rows of the text starting at row `y`. Glyphs are
+    /// looked up once for the whole band, and glyphs outside of the clipping
+    /// area are skipped.
+    fn render_band(
         &self,
-        row: &mut RowRenderer,
+        band: &mut BandRenderer,
         clip: &ClipRow,
-        glyph: &Glyph,
+        text: &str,
         y: i32,
         offset: i32,
     ) {
-        let image_offset = offset + glyph.image_left as i32;
-        let image_y = y - self.ascender as i32 + glyph.image_top as i32;
-        glyph
-            .image
-            .render_row_transparent(row, clip, image_y, image_offset);
+        let first_row = max(y, 0);
+        let last_row = min(
+            y + band.rows() as i32,
+            (self.ascender() + self.descender()) as i32,
+        );
+        if first_row >= last_row {
+            return;
+        }
+        let (clip_left, clip_right) = clip.get();
+        let scale = self.scale();
+        for (_, glyph, pos) in layout(self, text, offset) {
+            let image_left = pos + glyph.image_left as i32 * scale as i32;
+            let image_right = image_left + (glyph.image.width() * scale) as i32;
+            if image_left < clip_right && image_right > clip_left {
+                for row_y in first_row..last_row {
+                    let mut row = band.row((row_y - y) as u32);
+                    let baseline_y = row_y - self.ascender() as i32;
+                    render_glyph_row(&mut row, clip, glyph, baseline_y, pos, scale, Color::Black);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the glyphs of the text together with their fonts and pen
+/// positions. Kerning is only applied between glyphs of the same font. Pen
+/// positions include the scale of the font.
+pub(crate) fn layout<'a, F: TextFont + ?Sized>(
+    font: &'a F,
+    text: &'a str,
+    offset: i32,
+) -> impl Iterator<Item = (&'a Font, &'a Glyph, i32)> {
+    let scale = font.scale() as i32;
+    let mut pos = offset;
+    let mut previous: Option<(&Font, usize)> = None;
+    text.chars()
+        .filter_map(move |c| font.glyph(c))
+        .map(move |(font, index)| {
+            if let Some((previous_font, previous)) = previous {
+                if core::ptr::eq(previous_font, font) {
+                    pos += font.kerning(previous, index) * scale;
+                }
+            }
+            previous = Some((font, index));
+            let glyph = &font.glyphs[index];
+            let glyph_pos = pos;
+            pos += glyph.advance as i32 * scale;
+            (font, glyph, glyph_pos)
+        })
+}
+
+/// Renders a row of a glyph enlarged by `scale` in the specified color.
+/// `baseline_y` is the row relative to the baseline.
+fn render_glyph_row(
+    row: &mut RowRenderer,
+    clip: &ClipRow,
+    glyph: &Glyph,
+    baseline_y: i32,
+    offset: i32,
+    scale: u32,
+    color: Color,
+) {
+    let image_offset = offset + glyph.image_left as i32 * scale as i32;
+    let image_y = baseline_y + glyph.image_top as i32 * scale as i32;
+    glyph
+        .image
+        .render_row_scaled(row, clip, image_y, image_offset, scale, color);
+}
+
+pub struct Font {
+    pub ascender: u16,
+    pub descender: u16,
+    pub glyphs: &'static [Glyph],
+    pub get_glyph_index: fn(c: char) -> Option<usize>,
+    /// Kerning pairs sorted by the left and then the right glyph index.
+    /// Ligatures are not supported, every character is displayed with its
+    /// own glyph.
+    pub kerning: &'static [KerningPair],
+    /// Index of the glyph which is displayed for characters missing from the
+    /// font. Missing characters are skipped if this is `None`.
+    pub replacement: Option<usize>,
+}
+
+impl Font {
+    /// Returns the horizontal adjustment between two glyphs.
+    pub fn kerning(&self, left: usize, right: usize) -> i32 {
+        if self.kerning.is_empty() {
+            return 0;
+        }
+        self.kerning
+            .binary_search_by(|pair| (pair.left as usize, pair.right as usize).cmp(&(left, right)))
+            .map(|index| self.kerning[index].offset as i32)
+            .unwrap_or(0)
+    }
+}
+
+impl TextFont for Font {
+    fn ascender(&self) -> u16 {
+        self.ascender
+    }
+
+    fn descender(&self) -> u16 {
+        self.descender
+    }
+
+    fn glyph(&self, c: char) -> Option<(&Font, usize)> {
+        (self.get_glyph_index)(c)
+            .or(self.replacement)
+            .map(|index| (self, index))
+    }
+
+    fn has_glyph(&self, c: char) -> bool {
+        (self.get_glyph_index)(c).is_some()
+    }
+}
+
+/// List of fonts which are tried in order for every character, e.g., a Latin
+/// font followed by a Cyrillic font and a symbol font.
+///
+/// Characters missing from all fonts are displayed with the replacement glyph
+/// of the first font which has one. The line height is large enough for all
+/// fonts.
+pub struct FontChain {
+    fonts: &'static [&'static Font],
+    ascender: u16,
+    descender: u16,
+}
+
+impl FontChain {
+    pub const fn new(fonts: &'static [&'static Font]) -> FontChain {
+        let mut ascender = 0;
+        let mut descender = 0;
+        let mut i = 0;
+        while i < fonts.len() {
+            if fonts[i].ascender > ascender {
+                ascender = fonts[i].ascender;
+            }
+            if fonts[i].descender > descender {
+                descender = fonts[i].descender;
+            }
+            i += 1;
+        }
+        FontChain {
+            fonts,
+            ascender,
+            descender,
+        }
+    }
+}
+
+impl TextFont for FontChain {
+    fn ascender(&self) -> u16 {
+        self.ascender
+    }
+
+    fn descender(&self) -> u16 {
+        self.descender
+    }
+
+    fn glyph(&self, c: char) -> Option<(&Font, usize)> {
+        self.fonts
+            .iter()
+            .find_map(|font| (font.get_glyph_index)(c).map(|index| (*font, index)))
+            .or_else(|| {
+                self.fonts
+                    .iter()
+                    .find_map(|font| font.replacement.map(|index| (*font, index)))
+            })
+    }
+
+    fn has_glyph(&self, c: char) -> bool {
+        self.fonts.iter().any(|font| font.has_glyph(c))
+    }
+}
+
+/// Font which renders the glyphs of another font enlarged by an integer
+/// factor, e.g., for large numerals of a clock. The glyph images are scaled
+/// with nearest-neighbor sampling, so no additional font data is required.
+///
+/// ```ignore
+/// static CLOCK_FONT: ScaledFont = ScaledFont::new(&DIGITS, 3);
+/// ```
+pub struct ScaledFont<F: TextFont + ?Sized + 'static = Font> {
+    font: &'static F,
+    scale: u16,
+}
+
+impl<F: TextFont + ?Sized> ScaledFont<F> {
+    pub const fn new(font: &'static F, scale: u16) -> ScaledFont<F> {
+        assert!(scale != 0, "scale must not be zero");
+        ScaledFont { font, scale }
+    }
+}
+
+impl<F: TextFont + ?Sized> TextFont for ScaledFont<F> {
+    fn ascender(&self) -> u16 {
+        self.font.ascender() * self.scale
+    }
+
+    fn descender(&self) -> u16 {
+        self.font.descender() * self.scale
+    }
+
+    fn glyph(&self, c: char) -> Option<(&Font, usize)> {
+        self.font.glyph(c)
+    }
+
+    fn has_glyph(&self, c: char) -> bool {
+        self.font.has_glyph(c)
+    }
+
+    fn scale(&self) -> u32 {
+        self.font.scale() * self.scale as u32
+    }
+}
+
+/// Spacing adjustment for a pair of glyphs, such as "AV".
+#[derive(Copy, Clone, Debug)]
+pub struct KerningPair {
+    /// Index of the left glyph.
+    pub left: u16,
+    /// Index of the right glyph.
+    pub right: u16,
+    /// Change of the advance of the left glyph in pixels.
+    pub offset: i16,
+}
+
+/// Image of a glyph in one of the supported storage formats.
+pub enum GlyphImage {
+    Rle(RLEImage),
+    Nibble(NibbleImage),
+    Packed(PackedImage),
+    Heatshrink(HeatshrinkImage),
+}
+
+impl GlyphImage {
+    fn image(&self) -> &dyn RowImage {
+        match self {
+            GlyphImage::Rle(image) => image,
+            GlyphImage::Nibble(image) => image,
+            GlyphImage::Packed(image) => image,
+            GlyphImage::Heatshrink(image) => image,
+        }
+    }
+}
+
+impl RowImage for GlyphImage {
+    fn width(&self) -> u32 {
+        self.image().width()
+    }
+
+    fn height(&self) -> u32 {
+        self.image().height()
+    }
+
+    fn black_runs(&self, y: u32, run: &mut dyn FnMut(u32, u32)) {
+        self.image().black_runs(y, run)
+    }
+
+    fn all_black_runs(&self, run: &mut dyn FnMut(u32, u32, u32)) {
+        self.image().all_black_runs(run)
     }
 }
 
 pub struct Glyph {
-    pub image: RLEImage,
+    pub image: GlyphImage,
+    /// Horizontal distance from the pen position to the left of the image.
     pub image_left: i16,
+    /// Number of image rows above the baseline.
     pub image_top: i16,
     pub advance: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Font, FontChain, Glyph, GlyphImage, KerningPair, ScaledFont, TextFont};
+    use crate::gui::image::RLEImage;
+    use crate::{BandRenderer, ClipRect, RowRenderer};
+
+    static GLYPHS: [Glyph; 2] = [
+        Glyph {
+            image: GlyphImage::Rle(RLEImage {
+                data: &[2, 3, 3],
+                width: 3,
+                height: 1,
+            }),
+            image_left: 0,
+            image_top: 1,
+            advance: 4,
+        },
+        Glyph {
+            image: GlyphImage::Rle(RLEImage {
+                data: &[2, 3, 0x8002],
+                width: 2,
+                height: 1,
+            }),
+            image_left: 0,
+            image_top: 1,
+            advance: 3,
+        },
+    ];
+
+    static FONT: Font = Font {
+        ascender: 1,
+        descender: 0,
+        glyphs: &GLYPHS,
+        get_glyph_index: |c| match c {
+            'A' => Some(0),
+            'V' => Some(1),
+            _ => None,
+        },
+        kerning: &[KerningPair {
+            left: 0,
+            right: 1,
+            offset: -2,
+        }],
+        replacement: None,
+    };
+
+    static FALLBACK_GLYPHS: [Glyph; 1] = [Glyph {
+        image: GlyphImage::Rle(RLEImage {
+            data: &[3, 4, 5, 0x8001, 0x8001],
+            width: 1,
+            height: 2,
+        }),
+        image_left: 0,
+        image_top: 2,
+        advance: 2,
+    }];
+
+    static FALLBACK: Font = Font {
+        ascender: 2,
+        descender: 0,
+        glyphs: &FALLBACK_GLYPHS,
+        get_glyph_index: |c| if c == '|' { Some(0) } else { None },
+        kerning: &[],
+        replacement: Some(0),
+    };
+
+    static CHAIN: FontChain = FontChain::new(&[&FONT, &FALLBACK]);
+
+    static SCALED: ScaledFont = ScaledFont::new(&FONT, 2);
+
+    #[test]
+    fn test_kerning() {
+        assert_eq!(FONT.kerning(0, 1), -2);
+        assert_eq!(FONT.kerning(1, 0), 0);
+        assert_eq!(FONT.get_text_size("AVA").0, 9);
+        assert_eq!(FONT.get_text_size("VAV").0, 8);
+
+        let mut buffer = [0xffu8; 2];
+        let mut row = RowRenderer::new(&mut buffer, 16);
+        FONT.render_row(&mut row, &ClipRect::new(0, 0, 16, 1).row(), "VAV", 0, 0);
+        row.finish();
+        assert_eq!(buffer, [0x39, 0xff]);
+    }
+
+    #[test]
+    fn test_font_chain() {
+        assert_eq!((CHAIN.ascender(), CHAIN.descender()), (2, 0));
+        // No kerning between glyphs from different fonts, and the
+        // replacement glyph for unknown characters.
+        assert_eq!(CHAIN.get_text_size("A|V?").0, 11);
+        assert_eq!(FONT.get_text_size("A?V").0, 5);
+
+        let mut buffer = [0xffu8; 2];
+        let mut row = RowRenderer::new(&mut buffer, 16);
+        CHAIN.render_row(&mut row, &ClipRect::new(0, 0, 16, 2).row(), "V|V?", 1, 0);
+        row.finish();
+        assert_eq!(buffer, [0x29, 0x7f]);
+    }
+
+    #[test]
+    fn test_first_and_last_row() {
+        // The glyph reaches from the ascender down to the baseline, i.e., it
+        // covers both rows of the text, and nothing is drawn outside.
+        let clip = ClipRect::new(0, -1, 8, 3).row();
+        let rows: std::vec::Vec<u8> = (-1..3)
+            .map(|y| {
+                let mut buffer = [0xffu8];
+                let mut row = RowRenderer::new(&mut buffer, 8);
+                FALLBACK.render_row(&mut row, &clip, "|", y, 0);
+                row.finish();
+                buffer[0]
+            })
+            .collect();
+        assert_eq!(rows, [0xff, 0x7f, 0x7f, 0xff]);
+
+        let mut buffer = [0xffu8; 4];
+        let mut band = BandRenderer::new(&mut buffer, 8, 4);
+        band.set_y(-1);
+        FALLBACK.render_band(&mut band, &clip, "|", -1, 0);
+        band.finish();
+        assert_eq!(buffer, [0xff, 0x7f, 0x7f, 0xff]);
+    }
+
+    #[test]
+    fn test_scaled_font() {
+        // Advances and kerning are scaled as well.
+        assert_eq!(SCALED.get_text_size("AV"), (10, 2));
+
+        for y in 0..2 {
+            let mut buffer = [0xffu8; 2];
+            let mut row = RowRenderer::new(&mut buffer, 16);
+            SCALED.render_row(&mut row, &ClipRect::new(0, 0, 16, 2).row(), "AV", y, 1);
+            row.finish();
+            assert_eq!(buffer, [0xf8, 0x7f]);
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Support for drawing with `embedded-graphics`.
+//!
+//! `Canvas` replays a drawing function for every rendered row, so no
+//! framebuffer is required at the cost of drawing everything once per row.
+//! `BitmapCanvas` draws into a caller-provided framebuffer instead.
+
+use super::GUIElement;
+use crate::{BitOrder, ClipRect, ClipRow, Color, Polarity, RowRenderer};
+
+use core::convert::Infallible;
+use embedded_graphics_core::draw_target::DrawTarget;
+use embedded_graphics_core::geometry::{Dimensions, OriginDimensions, Point, Size};
+use embedded_graphics_core::pixelcolor::BinaryColor;
+use embedded_graphics_core::primitives::Rectangle;
+use embedded_graphics_core::Pixel;
+
+impl From<BinaryColor> for Color {
+    fn from(color: BinaryColor) -> Color {
+        match color {
+            BinaryColor::On => Color::Black,
+            BinaryColor::Off => Color::White,
+        }
+    }
+}
+
+/// Draw target which only draws the pixels of a single row and discards
+/// everything else.
+pub struct RowTarget<'a, 'b> {
+    row: &'a mut RowRenderer<'b>,
+    clip: &'a ClipRow,
+    y: i32,
+    offset: i32,
+    width: u32,
+    height: u32,
+}
+
+impl<'a, 'b> RowTarget<'a, 'b> {
+    /// Returns the row which is currently being rendered. Drawing functions
+    /// can use this to skip primitives which do not intersect the row.
+    pub fn row(&self) -> i32 {
+        self.y
+    }
+}
+
+impl<'a, 'b> OriginDimensions for RowTarget<'a, 'b> {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl<'a, 'b> DrawTarget for RowTarget<'a, 'b> {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.y == self.y && point.x >= 0 && point.x < self.width as i32 {
+                let x = self.offset + point.x;
+                self.row.fill(self.clip, x, x + 1, color.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        if !area.rows().contains(&self.y) {
+            return Ok(());
+        }
+        // Skip all rows above the current one.
+        let skip = (self.y - area.top_left.y) as usize * area.size.width as usize;
+        let y = self.y;
+        let row = colors
+            .into_iter()
+            .skip(skip)
+            .zip(area.columns())
+            .map(|(color, x)| Pixel(Point::new(x, y), color));
+        self.draw_iter(row)
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.bounding_box());
+        if area.rows().contains(&self.y) {
+            let columns = area.columns();
+            self.row.fill(
+                self.clip,
+                self.offset + columns.start,
+                self.offset + columns.end,
+                color.into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Element which renders the output of an `embedded-graphics` drawing
+/// function.
+///
+/// The function is called once for every row of the element with a draw
+/// target that discards all pixels outside of that row, so it should be cheap
+/// to call and has to produce the same output every time.
+pub struct Canvas<F>
+where
+    F: Fn(&mut RowTarget),
+{
+    draw: F,
+    min_width: u32,
+    min_height: u32,
+    width: u32,
+    height: u32,
+}
+
+impl<F> Canvas<F>
+where
+    F: Fn(&mut RowTarget),
+{
+    pub fn new(min_width: u32, min_height: u32, draw: F) -> Canvas<F> {
+        Canvas {
+            draw,
+            min_width,
+            min_height,
+            width: 0,
+            height: 0,
+        }
+    }
+}
+
+impl<F> GUIElement for Canvas<F>
+where
+    F: Fn(&mut RowTarget),
+{
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        (self.min_width, self.min_height)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        if y < 0 || y >= self.height as i32 {
+            return;
+        }
+        let clip = clip.row().clip(offset, offset + self.width as i32);
+        if clip.is_empty() {
+            return;
+        }
+        let mut target = RowTarget {
+            row,
+            clip: &clip,
+            y,
+            offset,
+            width: self.width,
+            height: self.height,
+        };
+        (self.draw)(&mut target);
+    }
+}
+
+/// Element which displays a framebuffer that can be drawn to with
+/// `embedded-graphics`.
+///
+/// The framebuffer uses one bit per pixel in the format of the display rows,
+/// i.e., with the leftmost pixel in the most significant bit and set bits
+/// representing white pixels, so rows can be copied without conversion.
+pub struct BitmapCanvas<'a> {
+    data: &'a mut [u8],
+    width: u16,
+    height: u16,
+    stride: u16,
+}
+
+impl<'a> BitmapCanvas<'a> {
+    pub fn new(data: &'a mut [u8], width: u16, height: u16) -> BitmapCanvas<'a> {
+        let stride = width.div_ceil(8);
+        assert!(data.len() >= stride as usize * height as usize);
+        let mut canvas = BitmapCanvas {
+            data,
+            width,
+            height,
+            stride,
+        };
+        canvas.clear(BinaryColor::Off).ok();
+        canvas
+    }
+
+    fn set_pixel(&mut self, x: i32, y: i32, color: BinaryColor) {
+        let index = y as usize * self.stride as usize + (x / 8) as usize;
+        match color {
+            BinaryColor::On => self.data[index] &= !(0x80 >> (x & 7)),
+            BinaryColor::Off => self.data[index] |= 0x80 >> (x & 7),
+        }
+    }
+}
+
+impl<'a> OriginDimensions for BitmapCanvas<'a> {
+    fn size(&self) -> Size {
+        Size::new(self.width as u32, self.height as u32)
+    }
+}
+
+impl<'a> DrawTarget for BitmapCanvas<'a> {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.x >= 0
+                && point.x < self.width as i32
+                && point.y >= 0
+                && point.y < self.height as i32
+            {
+                self.set_pixel(point.x, point.y, color);
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        let value = match color {
+            BinaryColor::On => 0x00,
+            BinaryColor::Off => 0xff,
+        };
+        for byte in self.data.iter_mut() {
+            *byte = value;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> GUIElement for BitmapCanvas<'a> {
+    fn resize(&mut self, _width: u32, _height: u32) {
+        // Ignore, as the framebuffer dictates the size.
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        if y < 0 || y >= self.height as i32 {
+            return;
+        }
+        let row_index = y as usize * self.stride as usize;
+        row.render_bitmap(
+            &clip.row(),
+            offset,
+            offset + self.width as i32,
+            &self.data[row_index..row_index + self.stride as usize],
+            BitOrder::MsbFirst,
+            Polarity::OneIsWhite,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BitmapCanvas, Canvas, RowTarget};
+    use crate::gui::GUIElement;
+    use crate::{ClipRect, RowRenderer};
+
+    use embedded_graphics_core::draw_target::DrawTarget;
+    use embedded_graphics_core::geometry::{Point, Size};
+    use embedded_graphics_core::pixelcolor::BinaryColor;
+    use embedded_graphics_core::primitives::Rectangle;
+    use embedded_graphics_core::Pixel;
+
+    fn draw(target: &mut RowTarget) {
+        let area = Rectangle::new(Point::new(2, 1), Size::new(10, 2));
+        target.fill_solid(&area, BinaryColor::On).ok();
+        target
+            .draw_iter([Pixel(Point::new(15, 2), BinaryColor::On)].iter().cloned())
+            .ok();
+    }
+
+    fn render(element: &dyn GUIElement, y: i32) -> [u8; 2] {
+        let mut buffer = [0xffu8; 2];
+        let mut renderer = RowRenderer::new(&mut buffer, 16);
+        let clip = ClipRect::new(0, y, 16, y + 1);
+        element.render_row(&mut renderer, &clip, y, 0);
+        renderer.finish();
+        buffer
+    }
+
+    #[test]
+    fn test_canvas() {
+        let mut canvas = Canvas::new(16, 4, draw);
+        canvas.resize(16, 4);
+        assert_eq!(render(&canvas, 0), [0xff, 0xff]);
+        assert_eq!(render(&canvas, 1), [0xc0, 0x0f]);
+        assert_eq!(render(&canvas, 2), [0xc0, 0x0e]);
+        assert_eq!(render(&canvas, 3), [0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_bitmap_canvas() {
+        let mut data = [0u8; 8];
+        let mut canvas = BitmapCanvas::new(&mut data, 16, 4);
+        let area = Rectangle::new(Point::new(2, 1), Size::new(10, 2));
+        canvas.fill_solid(&area, BinaryColor::On).ok();
+        canvas
+            .draw_iter([Pixel(Point::new(15, 2), BinaryColor::On)].iter().cloned())
+            .ok();
+        assert_eq!(render(&canvas, 0), [0xff, 0xff]);
+        assert_eq!(render(&canvas, 1), [0xc0, 0x0f]);
+        assert_eq!(render(&canvas, 2), [0xc0, 0x0e]);
+    }
+}
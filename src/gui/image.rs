@@ -1,13 +1,59 @@
-use crate::{ClipRow, Color, RowRenderer};
+use crate::{BitOrder, ClipRow, Color, Dither, Polarity, RowRenderer};
 
+use core::cmp::{max, min};
+
+/// Image with one bit per pixel. Every row starts at a multiple of `stride`
+/// bytes.
 pub struct BitmapImage {
     pub data: &'static [u8],
     pub width: u16,
     pub height: u16,
     pub stride: u16,
+    pub order: BitOrder,
+    pub polarity: Polarity,
 }
 
 impl BitmapImage {
+    /// Creates an image and checks that the stride and the amount of data
+    /// match the size. When used in a constant, invalid images fail to
+    /// compile.
+    pub const fn new(
+        data: &'static [u8],
+        width: u16,
+        height: u16,
+        stride: u16,
+        order: BitOrder,
+        polarity: Polarity,
+    ) -> BitmapImage {
+        assert!(stride as usize * 8 >= width as usize, "stride too small");
+        assert!(
+            data.len() >= stride as usize * height as usize,
+            "not enough image data"
+        );
+        BitmapImage {
+            data,
+            width,
+            height,
+            stride,
+            order,
+            polarity,
+        }
+    }
+
+    /// Creates an image with rows padded to whole bytes, in the format used
+    /// by PBM files and most tools which output C arrays (leftmost pixel in
+    /// the most significant bit, set bits black).
+    pub const fn new_msb_black(data: &'static [u8], width: u16, height: u16) -> BitmapImage {
+        Self::new(
+            data,
+            width,
+            height,
+            width.div_ceil(8),
+            BitOrder::MsbFirst,
+            Polarity::OneIsBlack,
+        )
+    }
+
     pub fn render_row_transparent(
         &self,
         row: &mut RowRenderer,
@@ -24,46 +70,661 @@ impl BitmapImage {
             offset,
             offset + self.width as i32,
             &self.data[row_index..row_index + self.stride as usize],
+            self.order,
+            self.polarity,
         );
     }
 }
 
-pub struct RLEImage {
-    pub data: &'static [u16],
+/// Image with eight bits of luminance per pixel, from 0 (black) to 255
+/// (white).
+pub struct GrayImage {
+    pub data: &'static [u8],
     pub width: u16,
     pub height: u16,
 }
 
-impl RLEImage {
-    pub fn render_row_transparent(
+impl GrayImage {
+    pub fn render_row(
         &self,
         row: &mut RowRenderer,
         clip: &ClipRow,
         y: i32,
         offset: i32,
+        dither: &mut Dither,
+    ) {
+        if y < 0 || y >= self.height as i32 {
+            return;
+        }
+        let row_index = y as usize * self.width as usize;
+        row.render_gray(
+            clip,
+            offset,
+            offset + self.width as i32,
+            &self.data[row_index..row_index + self.width as usize],
+            dither,
+        );
+    }
+}
+
+/// Monochrome image which is rendered row by row, implemented by the
+/// different storage formats.
+///
+/// Formats only have to list the runs of black pixels in a row. Rendering,
+/// scaling and access to columns are implemented on top of that.
+pub trait RowImage {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+
+    /// Calls `run(left, right)` for every run of black pixels in row `y`,
+    /// from left to right. `y` has to be smaller than the height.
+    fn black_runs(&self, y: u32, run: &mut dyn FnMut(u32, u32));
+
+    /// Calls `run(y, left, right)` for every run of black pixels of the
+    /// whole image, from top to bottom. Formats which have to decode the
+    /// previous rows to find a row override this to decode the image only
+    /// once.
+    fn all_black_runs(&self, run: &mut dyn FnMut(u32, u32, u32)) {
+        for y in 0..self.height() {
+            self.black_runs(y, &mut |left, right| run(y, left, right));
+        }
+    }
+
+    fn render_row_transparent(&self, row: &mut RowRenderer, clip: &ClipRow, y: i32, offset: i32) {
+        self.render_row_scaled(row, clip, y, offset, 1, Color::Black);
+    }
+
+    /// Renders a row of the image enlarged by `scale` in both directions,
+    /// i.e., every pixel of the image covers `scale` by `scale` pixels. `y`
+    /// is the row of the enlarged image. Black pixels are drawn in `color`.
+    fn render_row_scaled(
+        &self,
+        row: &mut RowRenderer,
+        clip: &ClipRow,
+        y: i32,
+        offset: i32,
+        scale: u32,
+        color: Color,
     ) {
         if y < 0 {
             return;
         }
-        if y >= self.height as i32 {
+        let y = y as u32 / scale;
+        if y >= self.height() {
             return;
         }
+        self.black_runs(y, &mut |left, right| {
+            row.fill(
+                clip,
+                offset + (left * scale) as i32,
+                offset + (right * scale) as i32,
+                color,
+            );
+        });
+    }
+
+    /// Returns whether the pixel at (`x`, `y`) is black.
+    fn is_black(&self, x: u32, y: u32) -> bool {
+        if x >= self.width() || y >= self.height() {
+            return false;
+        }
+        let mut black = false;
+        self.black_runs(y, &mut |left, right| {
+            black |= left <= x && x < right;
+        });
+        black
+    }
+
+    /// Renders column `x` of the image into a row, for example to display the
+    /// image rotated by 90 degrees. Image row 0 is placed at `offset`, and the
+    /// following rows to the right or, if `reverse` is set, to the left of it.
+    ///
+    /// The image is enlarged by `scale` in both directions, and `x` is the
+    /// column of the enlarged image.
+    fn render_column_transparent(
+        &self,
+        row: &mut RowRenderer,
+        clip: &ClipRow,
+        x: i32,
+        offset: i32,
+        reverse: bool,
+        scale: u32,
+    ) {
+        if x < 0 {
+            return;
+        }
+        let x = x as u32 / scale;
+        if x >= self.width() {
+            return;
+        }
+        let mut fill = |start: u32, end: u32| {
+            let (start, end) = ((start * scale) as i32, (end * scale) as i32);
+            let (left, right) = if reverse {
+                (offset - end + 1, offset - start + 1)
+            } else {
+                (offset + start, offset + end)
+            };
+            row.fill(clip, left, right, Color::Black);
+        };
+        // Rows in which the column is black, merged into vertical runs.
+        let mut rows: Option<(u32, u32)> = None;
+        self.all_black_runs(&mut |y, left, right| {
+            if x < left || x >= right {
+                return;
+            }
+            rows = match rows {
+                Some((start, end)) if end == y => Some((start, y + 1)),
+                Some((start, end)) => {
+                    fill(start, end);
+                    Some((y, y + 1))
+                }
+                None => Some((y, y + 1)),
+            };
+        });
+        if let Some((start, end)) = rows {
+            fill(start, end);
+        }
+    }
+}
+
+/// Collects the runs of black pixels from pixels which are passed from left
+/// to right.
+pub(crate) struct RunCollector<'a> {
+    run: &'a mut dyn FnMut(u32, u32),
+    start: Option<u32>,
+    x: u32,
+}
+
+impl<'a> RunCollector<'a> {
+    pub(crate) fn new(run: &'a mut dyn FnMut(u32, u32)) -> RunCollector<'a> {
+        RunCollector {
+            run,
+            start: None,
+            x: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, black: bool) {
+        match (self.start, black) {
+            (None, true) => self.start = Some(self.x),
+            (Some(start), false) => {
+                (self.run)(start, self.x);
+                self.start = None;
+            }
+            _ => {}
+        }
+        self.x += 1;
+    }
+
+    pub(crate) fn finish(self) {
+        if let Some(start) = self.start {
+            (self.run)(start, self.x);
+        }
+    }
+}
+
+impl RowImage for BitmapImage {
+    fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    fn height(&self) -> u32 {
+        self.height as u32
+    }
+
+    fn render_row_transparent(&self, row: &mut RowRenderer, clip: &ClipRow, y: i32, offset: i32) {
+        // Copies whole bytes where possible.
+        BitmapImage::render_row_transparent(self, row, clip, y, offset);
+    }
+
+    fn black_runs(&self, y: u32, run: &mut dyn FnMut(u32, u32)) {
+        let row = &self.data[y as usize * self.stride as usize..];
+        let mut runs = RunCollector::new(run);
+        for x in 0..self.width as usize {
+            let mask = match self.order {
+                BitOrder::MsbFirst => 0x80 >> (x & 7),
+                BitOrder::LsbFirst => 1 << (x & 7),
+            };
+            let set = row[x / 8] & mask != 0;
+            runs.push(set == (self.polarity == Polarity::OneIsBlack));
+        }
+        runs.finish();
+    }
+}
+
+/// Run-length encoded image with 16-bit runs.
+///
+/// The data starts with a table of `height + 1` offsets, so row `y` consists
+/// of the entries from `data[y]` to `data[y + 1]`. Every run has the length
+/// in the lower 15 bits and the most significant bit set for black pixels.
+pub struct RLEImage {
+    pub data: &'static [u16],
+    pub width: u16,
+    pub height: u16,
+}
+
+impl RowImage for RLEImage {
+    fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    fn height(&self) -> u32 {
+        self.height as u32
+    }
+
+    fn black_runs(&self, y: u32, run: &mut dyn FnMut(u32, u32)) {
         let line_start = self.data[y as usize] as usize;
         let line_end = self.data[y as usize + 1] as usize;
-        let line = &self.data[line_start..line_end];
+        let mut pos = 0;
+        for entry in &self.data[line_start..line_end] {
+            let length = (entry & 0x7fff) as u32;
+            if (entry >> 15) != 0u16 {
+                run(pos, pos + length);
+            }
+            pos += length;
+        }
+    }
+}
+
+/// Run-length encoded image with 4-bit runs, e.g., for small glyphs.
+///
+/// The pixels of all rows form a single sequence which starts with a run of
+/// white pixels. Every nibble (high nibble first) is the length of a run,
+/// after which the color changes, except for 15, which continues the run
+/// with the next nibble. Runs of white pixels at the end are omitted.
+///
+/// Rows are decoded from the start of the image, so the format is best
+/// suited for images with few pixels.
+pub struct NibbleImage {
+    pub data: &'static [u8],
+    pub width: u16,
+    pub height: u16,
+}
+
+impl RowImage for NibbleImage {
+    fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    fn height(&self) -> u32 {
+        self.height as u32
+    }
 
+    fn black_runs(&self, y: u32, run: &mut dyn FnMut(u32, u32)) {
+        let row_start = y * self.width as u32;
+        let row_end = row_start + self.width as u32;
         let mut pos = 0;
-        for run in line {
-            let length = (run & 0x7fff) as u32;
-            if (run >> 15) != 0u16 {
-                row.fill(
-                    clip,
-                    offset + pos,
-                    offset + pos + length as i32,
-                    Color::Black,
+        let mut black = false;
+        for i in 0..self.data.len() * 2 {
+            let length = if i % 2 == 0 {
+                self.data[i / 2] >> 4
+            } else {
+                self.data[i / 2] & 0xf
+            };
+            let end = pos + length as u32;
+            if black && end > row_start && pos < row_end {
+                run(
+                    max(pos, row_start) - row_start,
+                    min(end, row_end) - row_start,
                 );
             }
-            pos += length as i32;
+            pos = end;
+            if pos >= row_end {
+                break;
+            }
+            if length != 15 {
+                black = !black;
+            }
+        }
+    }
+
+    fn all_black_runs(&self, run: &mut dyn FnMut(u32, u32, u32)) {
+        let width = self.width as u32;
+        let end = width * self.height as u32;
+        let mut pos = 0;
+        let mut black = false;
+        for i in 0..self.data.len() * 2 {
+            if pos >= end {
+                break;
+            }
+            let length = if i % 2 == 0 {
+                self.data[i / 2] >> 4
+            } else {
+                self.data[i / 2] & 0xf
+            };
+            let run_end = min(pos + length as u32, end);
+            if black {
+                // Split the run at the ends of the rows.
+                while pos < run_end {
+                    let y = pos / width;
+                    let row_end = min(run_end, (y + 1) * width);
+                    run(y, pos - y * width, row_end - y * width);
+                    pos = row_end;
+                }
+            }
+            pos = run_end;
+            if length != 15 {
+                black = !black;
+            }
+        }
+    }
+}
+
+/// Image with one bit per pixel without padding between the rows, e.g., for
+/// glyphs which are cropped to their bounding box.
+///
+/// Pixel (`x`, `y`) is stored in bit `y * width + x`, counting from the most
+/// significant bit of the first byte. Set bits are black.
+pub struct PackedImage {
+    pub data: &'static [u8],
+    pub width: u16,
+    pub height: u16,
+}
+
+impl RowImage for PackedImage {
+    fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    fn height(&self) -> u32 {
+        self.height as u32
+    }
+
+    fn black_runs(&self, y: u32, run: &mut dyn FnMut(u32, u32)) {
+        let start = y as usize * self.width as usize;
+        let mut runs = RunCollector::new(run);
+        for bit in start..start + self.width as usize {
+            runs.push(self.data[bit / 8] & (0x80 >> (bit & 7)) != 0);
+        }
+        runs.finish();
+    }
+}
+
+/// Number of bits of the offsets of back-references in `HeatshrinkImage`
+/// data, i.e., the compressor uses a window of 256 bytes.
+pub const HEATSHRINK_WINDOW_BITS: u32 = 8;
+/// Number of bits of the lengths of back-references in `HeatshrinkImage`
+/// data.
+pub const HEATSHRINK_LOOKAHEAD_BITS: u32 = 4;
+
+/// Image compressed with Heatshrink (LZSS), which is decompressed row by row
+/// while rendering.
+///
+/// The uncompressed data has one bit per pixel with rows padded to whole
+/// bytes, the leftmost pixel in the most significant bit and set bits black.
+/// It is compressed with a window of `HEATSHRINK_WINDOW_BITS` and a lookahead
+/// of `HEATSHRINK_LOOKAHEAD_BITS` (`heatshrink -w 8 -l 4`).
+///
+/// Large images are split into blocks of `block_rows` rows which are
+/// compressed independently, so that rendering a row only requires
+/// decompressing the rows before it within the same block. Decompression
+/// needs a buffer of 256 bytes on the stack.
+pub struct HeatshrinkImage {
+    pub data: &'static [u8],
+    /// Offsets of the blocks within `data`. Empty if the whole image is a
+    /// single block.
+    pub blocks: &'static [u32],
+    pub block_rows: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl RowImage for HeatshrinkImage {
+    fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    fn height(&self) -> u32 {
+        self.height as u32
+    }
+
+    fn black_runs(&self, y: u32, run: &mut dyn FnMut(u32, u32)) {
+        let (block, first_row) = self.block(y);
+        let stride = (self.width as usize).div_ceil(8);
+        let row_start = (y - first_row) as usize * stride;
+        let width = self.width as u32;
+        let mut runs = RunCollector::new(run);
+        let mut pos = 0;
+        heatshrink_decompress(block, |byte| {
+            if pos >= row_start {
+                for bit in 0..8 {
+                    if runs.x < width {
+                        runs.push(byte & (0x80 >> bit) != 0);
+                    }
+                }
+            }
+            pos += 1;
+            pos < row_start + stride
+        });
+        runs.finish();
+    }
+
+    fn all_black_runs(&self, run: &mut dyn FnMut(u32, u32, u32)) {
+        let width = self.width as u32;
+        let stride = (self.width as usize).div_ceil(8);
+        if stride == 0 {
+            return;
+        }
+        let mut first_row = 0;
+        while first_row < self.height as u32 {
+            let (block, _) = self.block(first_row);
+            let rows = if self.blocks.is_empty() {
+                self.height as u32
+            } else {
+                min(self.block_rows as u32, self.height as u32 - first_row)
+            };
+            let (mut pos, mut x) = (0, 0);
+            let mut start = None;
+            heatshrink_decompress(block, |byte| {
+                let y = first_row + (pos / stride) as u32;
+                for bit in 0..8 {
+                    if x < width {
+                        match (start, byte & (0x80 >> bit) != 0) {
+                            (None, true) => start = Some(x),
+                            (Some(left), false) => {
+                                run(y, left, x);
+                                start = None;
+                            }
+                            _ => {}
+                        }
+                        x += 1;
+                    }
+                }
+                pos += 1;
+                if pos % stride == 0 {
+                    if let Some(left) = start.take() {
+                        run(y, left, x);
+                    }
+                    x = 0;
+                }
+                pos < rows as usize * stride
+            });
+            // Truncated data ends within a row.
+            if let Some(left) = start {
+                run(first_row + (pos / stride) as u32, left, x);
+            }
+            first_row += max(rows, 1);
+        }
+    }
+}
+
+impl HeatshrinkImage {
+    /// Returns the compressed data of the block containing row `y`, and the
+    /// first row of the block.
+    fn block(&self, y: u32) -> (&'static [u8], u32) {
+        if self.blocks.is_empty() {
+            return (self.data, 0);
+        }
+        let index = (y / self.block_rows as u32) as usize;
+        let start = self.blocks[index] as usize;
+        let end = self
+            .blocks
+            .get(index + 1)
+            .map(|&end| end as usize)
+            .unwrap_or(self.data.len());
+        (
+            &self.data[start..end],
+            index as u32 * self.block_rows as u32,
+        )
+    }
+}
+
+/// Reads bits from a byte slice, starting with the most significant bit.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn read(&mut self, bits: u32) -> Option<usize> {
+        if self.pos + bits as usize > self.data.len() * 8 {
+            return None;
+        }
+        let mut value = 0;
+        for _ in 0..bits {
+            let bit = (self.data[self.pos / 8] >> (7 - self.pos % 8)) & 1;
+            value = (value << 1) | bit as usize;
+            self.pos += 1;
+        }
+        Some(value)
+    }
+}
+
+/// Decompresses Heatshrink data and passes the bytes to `output` until it
+/// returns `false` or the data ends.
+fn heatshrink_decompress<F: FnMut(u8) -> bool>(data: &[u8], mut output: F) {
+    const WINDOW: usize = 1 << HEATSHRINK_WINDOW_BITS;
+    let mut window = [0u8; WINDOW];
+    let mut head = 0usize;
+    let mut reader = BitReader { data, pos: 0 };
+    // Incomplete commands at the end are padding.
+    while let Some(tag) = reader.read(1) {
+        let (distance, count) = if tag == 1 {
+            match reader.read(8) {
+                Some(byte) => {
+                    window[head % WINDOW] = byte as u8;
+                    (0, 1)
+                }
+                None => return,
+            }
+        } else {
+            match (
+                reader.read(HEATSHRINK_WINDOW_BITS),
+                reader.read(HEATSHRINK_LOOKAHEAD_BITS),
+            ) {
+                (Some(index), Some(count)) => (index + 1, count + 1),
+                _ => return,
+            }
+        };
+        for _ in 0..count {
+            let byte = window[head.wrapping_sub(distance) % WINDOW];
+            window[head % WINDOW] = byte;
+            head += 1;
+            if !output(byte) {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{heatshrink_decompress, HeatshrinkImage, NibbleImage, PackedImage, RowImage};
+    use crate::RowRenderer;
+    use std::vec::Vec;
+
+    /// Returns the runs of black pixels of all rows, once decoded row by row
+    /// and once for the whole image.
+    fn runs<I: RowImage + ?Sized>(image: &I) -> Vec<(u32, u32, u32)> {
+        let mut rows = Vec::new();
+        for y in 0..image.height() {
+            image.black_runs(y, &mut |left, right| rows.push((y, left, right)));
         }
+        let mut all = Vec::new();
+        image.all_black_runs(&mut |y, left, right| all.push((y, left, right)));
+        assert_eq!(rows, all);
+        rows
+    }
+
+    fn render_column<I: RowImage + ?Sized>(image: &I, x: i32, offset: i32, reverse: bool) -> u8 {
+        let mut buffer = [0xffu8];
+        let mut row = RowRenderer::new(&mut buffer, 8);
+        let clip = row.full_row();
+        image.render_column_transparent(&mut row, &clip, x, offset, reverse, 1);
+        row.finish();
+        buffer[0]
+    }
+
+    #[test]
+    fn test_heatshrink_decompress() {
+        // Literal "a", followed by a back-reference with a distance of 1 and
+        // a length of 3.
+        let mut output = Vec::new();
+        heatshrink_decompress(&[0xb0, 0x80, 0x08], |byte| {
+            output.push(byte);
+            true
+        });
+        assert_eq!(output, b"aaaa");
+
+        // Decompression stops when the output returns false.
+        let mut count = 0;
+        heatshrink_decompress(&[0xb0, 0x80, 0x08], |_| {
+            count += 1;
+            count < 2
+        });
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_formats() {
+        // 1 white, 3 black and 2 white pixels, i.e., ".##" and "#..".
+        let nibble = NibbleImage {
+            data: &[0x13, 0x20],
+            width: 3,
+            height: 2,
+        };
+        let packed = PackedImage {
+            data: &[0x70],
+            width: 3,
+            height: 2,
+        };
+        for image in [&nibble as &dyn RowImage, &packed].iter() {
+            assert_eq!(runs(*image), [(0, 1, 3), (1, 0, 1)]);
+            assert_eq!(render_column(*image, 0, 0, false), 0xbf);
+            assert_eq!(render_column(*image, 2, 7, true), 0xfe);
+        }
+
+        // A run of 15 pixels continues with the next nibble.
+        let long = NibbleImage {
+            data: &[0x0f, 0x20],
+            width: 20,
+            height: 1,
+        };
+        assert_eq!(runs(&long), [(0, 0, 15), (0, 15, 17)]);
+
+        // Every row is "aaaa"[y], i.e., 0x61.
+        let heatshrink = HeatshrinkImage {
+            data: &[0xb0, 0x80, 0x08],
+            blocks: &[],
+            block_rows: 0,
+            width: 8,
+            height: 4,
+        };
+        let expected: Vec<_> = (0..4).flat_map(|y| [(y, 1, 3), (y, 7, 8)]).collect();
+        assert_eq!(runs(&heatshrink), expected);
+        assert_eq!(render_column(&heatshrink, 0, 0, false), 0xff);
+        assert_eq!(render_column(&heatshrink, 1, 0, false), 0x0f);
+        assert_eq!(render_column(&heatshrink, 2, 7, true), 0xf0);
+
+        // Two blocks, the second one only partly used.
+        let blocks = HeatshrinkImage {
+            data: &[0xb0, 0x80, 0x08, 0xb0, 0x80, 0x08],
+            blocks: &[0, 3],
+            block_rows: 4,
+            width: 8,
+            height: 6,
+        };
+        let expected: Vec<_> = (0..6).flat_map(|y| [(y, 1, 3), (y, 7, 8)]).collect();
+        assert_eq!(runs(&blocks), expected);
     }
 }
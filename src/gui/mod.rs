@@ -1,16 +1,135 @@
-use crate::{ClipRow, Color, Display, PartialRefresh, RowRenderer};
+use crate::{
+    pixel_level, set_pixel_level, BandRenderer, ClipRect, Color, Display, Dither, GrayscaleDisplay,
+    Mirror, PartialRefresh, Pattern, RedDisplay, RedPlane, RowRenderer,
+};
 
-use core::cmp::max;
+use core::cell::RefCell;
+use core::cmp::{max, min};
 
+pub mod bidi;
+pub mod decode;
+pub mod external;
 pub mod font;
+#[cfg(feature = "graphics")]
+pub mod graphics;
 pub mod image;
+pub mod shapes;
+pub mod text;
+
+use self::font::TextFont;
+use self::image::RowImage;
+use self::text::{Overflow, TextLine};
 
 pub trait GUIElement {
     fn resize(&mut self, width: u32, height: u32);
     fn min_size(&self) -> (u32, u32);
     fn size(&self) -> (u32, u32);
 
-    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRow, y: i32, offset: i32);
+    /// Returns the minimum height of the element if it is resized to the
+    /// specified width. Elements whose height depends on their width, such as
+    /// wrapped text, override this method.
+    fn min_height_for_width(&self, _width: u32) -> u32 {
+        self.min_size().1
+    }
+
+    /// Returns the size the element would like to have, which can be larger
+    /// than the minimum size, e.g., for text which can be truncated.
+    fn preferred_size(&self) -> (u32, u32) {
+        self.min_size()
+    }
+
+    /// Renders row `y` of the element, with `offset` being the absolute
+    /// position of the left edge of the element. The element must not draw
+    /// outside of `clip`. Containers only pass rows within `clip` to their
+    /// children.
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32);
+
+    /// Renders `band.rows()` consecutive rows starting at row `y`.
+    ///
+    /// The default implementation calls `render_row()` for every row.
+    /// Elements can override this method to reduce the overhead of
+    /// traversing the element tree and of preparing their content once per
+    /// row.
+    fn render_band(&self, band: &mut BandRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        for i in 0..band.rows() {
+            let mut row = band.row(i);
+            if clip.contains_row(row.y()) {
+                self.render_row(&mut row, clip, y + i as i32, offset);
+            }
+        }
+    }
+}
+
+/// Renders a row of a child element if the row is within the clipping
+/// rectangle of the child.
+fn render_child_row<Element>(
+    element: &Element,
+    row: &mut RowRenderer,
+    clip: &ClipRect,
+    y: i32,
+    offset: i32,
+) where
+    Element: GUIElement,
+{
+    if clip.contains_row(row.y()) {
+        element.render_row(row, clip, y, offset);
+    }
+}
+
+/// Renders the rows of a band which are within the clipping rectangle of a
+/// child element.
+fn render_child_band<Element>(
+    element: &Element,
+    band: &mut BandRenderer,
+    clip: &ClipRect,
+    y: i32,
+    offset: i32,
+) where
+    Element: GUIElement,
+{
+    let (_, top, _, bottom) = clip.get();
+    let rows = band.rows() as i32;
+    let first = min(max(top - band.y(), 0), rows);
+    let last = min(max(bottom - band.y(), first), rows);
+    if first == last || clip.is_empty() {
+        return;
+    }
+    let (_, mut rest) = band.split_at(first as u32);
+    let (mut clipped, _) = rest.split_at((last - first) as u32);
+    element.render_band(&mut clipped, clip, y + first, offset);
+}
+
+/// Orientation of the GUI relative to the native orientation of the display.
+///
+/// The rotation specifies the clockwise rotation of the rendered image on the
+/// panel, so `Rotate90` maps the top edge of the GUI to the right edge of the
+/// display.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Rotation {
+    Rotate0,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Rotation {
+    fn is_transposed(self) -> bool {
+        match self {
+            Rotation::Rotate0 | Rotation::Rotate180 => false,
+            Rotation::Rotate90 | Rotation::Rotate270 => true,
+        }
+    }
+
+    /// Returns whether the x and y axes are flipped after the (optional)
+    /// transposition of the image.
+    fn flips(self) -> (bool, bool) {
+        match self {
+            Rotation::Rotate0 => (false, false),
+            Rotation::Rotate90 => (true, false),
+            Rotation::Rotate180 => (true, true),
+            Rotation::Rotate270 => (false, true),
+        }
+    }
 }
 
 pub struct Layout<Root>
@@ -20,40 +139,155 @@ where
     root: Root,
     width: u32,
     height: u32,
+    rotation: Rotation,
+    mirror: Mirror,
 }
 
 impl<Root> Layout<Root>
 where
     Root: GUIElement,
 {
-    pub fn new(width: u32, height: u32, mut root: Root) -> Layout<Root> {
+    pub fn new(width: u32, height: u32, root: Root) -> Layout<Root> {
+        Self::new_rotated(width, height, Rotation::Rotate0, root)
+    }
+
+    /// Creates a layout for a display with the specified native size.
+    ///
+    /// The element tree is laid out and rendered in logical coordinates, so
+    /// for 90 and 270 degree rotations the root element is resized to
+    /// `height` x `width` pixels.
+    pub fn new_rotated(
+        width: u32,
+        height: u32,
+        rotation: Rotation,
+        mut root: Root,
+    ) -> Layout<Root> {
         // TODO: Derive width/height from display constants!
-        root.resize(width, height);
-        return Layout {
-            root: root,
-            width: width,
-            height: height,
+        let (logical_width, logical_height) = if rotation.is_transposed() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        root.resize(logical_width, logical_height);
+        Layout {
+            root,
+            width,
+            height,
+            rotation,
+            mirror: Mirror::None,
+        }
+    }
+
+    pub fn root(&self) -> &Root {
+        &self.root
+    }
+
+    /// Returns the root element. Note that the layout is not recalculated, so
+    /// `relayout()` has to be called if the modifications change the size of
+    /// any elements.
+    pub fn root_mut(&mut self) -> &mut Root {
+        &mut self.root
+    }
+
+    /// Recalculates the layout after the size of elements has changed, e.g.,
+    /// after `DynamicText::set_text()`.
+    pub fn relayout(&mut self) {
+        let (logical_width, logical_height) = if self.rotation.is_transposed() {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
         };
+        self.root.resize(logical_width, logical_height);
     }
 
+    /// Mirrors the rendered image in software. The mirroring is applied after
+    /// rotation, i.e., along the axes of the display. Drivers which support
+    /// mirroring in hardware should be preferred.
+    pub fn set_mirror(&mut self, mirror: Mirror) {
+        self.mirror = mirror;
+    }
+
+    /// Renders the whole GUI to the display.
+    ///
+    /// If the row buffer is large enough to hold multiple rows, the rows are
+    /// rendered in bands to reduce the number of traversals of the element
+    /// tree.
+    ///
+    /// For 90 and 270 degree rotations, the row buffer has to be large enough
+    /// to hold one logical row (i.e., `(height + 7) / 8` bytes) and at least
+    /// one native row. Any additional space is used to render multiple native
+    /// rows at once.
     pub fn render<DisplayType>(&self, display: &mut DisplayType, row_buffer: &mut [u8])
     where
         DisplayType: Display,
     {
-        for i in 0..self.height {
-            // Draw white background.
-            for i in 0..row_buffer.len() {
-                row_buffer[i] = 0xff;
+        self.render_rows(
+            0,
+            0,
+            self.width,
+            self.height,
+            false,
+            None,
+            row_buffer,
+            |row| display.draw_row(row),
+        );
+    }
+
+    /// Renders the whole GUI with four gray levels. The row buffer has to be
+    /// twice as large as for `render()`.
+    ///
+    /// As the display expects the frame as two separate bit planes, the GUI
+    /// is rendered twice.
+    pub fn render_gray<DisplayType>(&self, display: &mut DisplayType, row_buffer: &mut [u8])
+    where
+        DisplayType: GrayscaleDisplay,
+    {
+        for plane in 0..2 {
+            if plane != 0 {
+                display.next_gray_plane();
             }
-            // Draw the row.
-            let mut row_renderer = RowRenderer::new(row_buffer, self.width);
-            let clip = row_renderer.full_row();
-            self.root.render_row(&mut row_renderer, &clip, i as i32, 0);
-            row_renderer.finish();
-            display.draw_row(row_buffer);
+            self.render_rows(
+                0,
+                0,
+                self.width,
+                self.height,
+                true,
+                None,
+                row_buffer,
+                |row| display.draw_gray_row(row),
+            );
         }
     }
 
+    /// Renders the whole GUI for a display with black, white and red pixels.
+    /// The row buffer has the same size as for `render()`.
+    ///
+    /// As the display expects the frame as two separate bit planes, the GUI
+    /// is rendered twice.
+    pub fn render_red<DisplayType>(&self, display: &mut DisplayType, row_buffer: &mut [u8])
+    where
+        DisplayType: RedDisplay,
+    {
+        for plane in [RedPlane::BlackWhite, RedPlane::Red].iter() {
+            if *plane == RedPlane::Red {
+                display.next_red_plane();
+            }
+            self.render_rows(
+                0,
+                0,
+                self.width,
+                self.height,
+                false,
+                Some(*plane),
+                row_buffer,
+                |row| display.draw_row(row),
+            );
+        }
+    }
+
+    /// Renders part of the GUI to the display. The coordinates are specified
+    /// in native display coordinates. See `render()` for the requirements
+    /// regarding the size of the row buffer.
     pub fn render_partial<DisplayType>(
         &self,
         display: &mut DisplayType,
@@ -66,22 +300,143 @@ where
         DisplayType: Display + PartialRefresh,
     {
         // TODO: Check whether right/bottom are smaller than width/height?
-        for i in top..bottom {
-            // Draw white background.
-            for i in 0..row_buffer.len() {
-                row_buffer[i] = 0xff;
+        self.render_rows(left, top, right, bottom, false, None, row_buffer, |row| {
+            display.draw_partial_row(row)
+        });
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render_rows<F>(
+        &self,
+        left: u32,
+        top: u32,
+        right: u32,
+        bottom: u32,
+        gray: bool,
+        red: Option<RedPlane>,
+        buffer: &mut [u8],
+        mut draw_row: F,
+    ) where
+        F: FnMut(&[u8]),
+    {
+        let (flip_x, flip_y) = self.rotation.flips();
+        let flip_x = flip_x != self.mirror.is_horizontal();
+        let flip_y = flip_y != self.mirror.is_vertical();
+        let bits = if gray { 2 } else { 1 };
+        let stride = (self.width * bits).div_ceil(8) as usize;
+        if !self.rotation.is_transposed() {
+            let (clip_left, clip_right) = flip_span(left, right, self.width, flip_x);
+            assert!(buffer.len() >= stride, "row buffer too small");
+            let band_rows = (buffer.len() / stride) as u32;
+            let mut band_top = top;
+            while band_top < bottom {
+                let band_bottom = min(band_top + band_rows, bottom);
+                let rows = band_bottom - band_top;
+                let band_buffer = &mut buffer[..rows as usize * stride];
+                // Draw white background.
+                for byte in band_buffer.iter_mut() {
+                    *byte = 0xff;
+                }
+                // Draw the rows.
+                let (y, _) = flip_span(band_top, band_bottom, self.height, flip_y);
+                let mut band = match (gray, red) {
+                    (true, _) => BandRenderer::new_gray(band_buffer, self.width, rows),
+                    (false, Some(plane)) => {
+                        BandRenderer::new_red(band_buffer, self.width, rows, plane)
+                    }
+                    (false, None) => BandRenderer::new(band_buffer, self.width, rows),
+                };
+                band.set_y(y);
+                let clip = ClipRect::new(clip_left, 0, clip_right, self.height as i32);
+                self.root.render_band(&mut band, &clip, y, 0);
+                if flip_x {
+                    for i in 0..rows {
+                        band.row(i).mirror();
+                    }
+                }
+                band.finish();
+                if flip_y {
+                    for row in band_buffer.chunks(stride).rev() {
+                        draw_row(row);
+                    }
+                } else {
+                    for row in band_buffer.chunks(stride) {
+                        draw_row(row);
+                    }
+                }
+                band_top = band_bottom;
             }
-            // Draw the row.
-            let mut row_renderer = RowRenderer::new(row_buffer, DisplayType::WIDTH);
-            let clip = row_renderer.full_row().clip(left as i32, right as i32);
-            self.root.render_row(&mut row_renderer, &clip, i as i32, 0);
-            row_renderer.finish();
+        } else {
+            // Every native row is a column of the logical image. We render
+            // the logical rows one after another, clipped to a band of
+            // columns, and transpose them into a band of native rows.
+            let logical_stride = (self.height * bits).div_ceil(8) as usize;
+            assert!(
+                buffer.len() >= logical_stride + stride,
+                "row buffer too small for rotated rendering"
+            );
+            let (logical_row, band) = buffer.split_at_mut(logical_stride);
+            let band_rows = (band.len() / stride) as u32;
+            let (first_row, last_row) = flip_span(left, right, self.width, flip_x);
+            let mut band_top = top;
+            while band_top < bottom {
+                let band_bottom = min(band_top + band_rows, bottom);
+                let band_size = (band_bottom - band_top) as usize * stride;
+                for byte in band[..band_size].iter_mut() {
+                    *byte = 0xff;
+                }
+                let (clip_left, clip_right) = flip_span(band_top, band_bottom, self.height, flip_y);
+                for y in first_row..last_row {
+                    for byte in logical_row.iter_mut() {
+                        *byte = 0xff;
+                    }
+                    let mut row_renderer = match (gray, red) {
+                        (true, _) => RowRenderer::new_gray(logical_row, self.height),
+                        (false, Some(plane)) => {
+                            RowRenderer::new_red(logical_row, self.height, plane)
+                        }
+                        (false, None) => RowRenderer::new(logical_row, self.height),
+                    };
+                    row_renderer.set_y(y);
+                    let clip = ClipRect::new(clip_left, 0, clip_right, self.width as i32);
+                    self.root.render_row(&mut row_renderer, &clip, y, 0);
+                    row_renderer.finish();
 
-            display.draw_partial_row(row_buffer);
+                    let native_x = if flip_x { self.width as i32 - 1 - y } else { y };
+                    let white = (1 << bits) - 1;
+                    for x in clip_left..clip_right {
+                        let level = pixel_level(logical_row, x as usize, gray);
+                        if level != white {
+                            let native_y = if flip_y {
+                                self.height as i32 - 1 - x
+                            } else {
+                                x
+                            };
+                            let start = (native_y - band_top as i32) as usize * stride;
+                            let row = &mut band[start..start + stride];
+                            set_pixel_level(row, native_x as usize, gray, level);
+                        }
+                    }
+                }
+                for row in band[..band_size].chunks(stride) {
+                    draw_row(row);
+                }
+                band_top = band_bottom;
+            }
         }
     }
 }
 
+/// Converts the span `[start, end)` on an axis with the specified size into
+/// the corresponding span on the flipped axis if necessary.
+fn flip_span(start: u32, end: u32, size: u32, flip: bool) -> (i32, i32) {
+    if flip {
+        (size as i32 - end as i32, size as i32 - start as i32)
+    } else {
+        (start as i32, end as i32)
+    }
+}
+
 enum HorizontalSplitMode {
     ExpandLeft(u32),
     ExpandRight(u32),
@@ -123,6 +478,19 @@ where
             height: 0,
         }
     }
+
+    /// Returns the split position and the clipping rectangles of the children
+    /// for an element whose top left corner is at (`offset`, `top`).
+    fn child_clips(&self, clip: &ClipRect, top: i32, offset: i32) -> (i32, ClipRect, ClipRect) {
+        let split_at = match self.mode {
+            HorizontalSplitMode::ExpandLeft(split_at) => self.width - split_at,
+            HorizontalSplitMode::ExpandRight(split_at) => split_at,
+        } as i32;
+        let bottom = top + self.height as i32;
+        let clip_left = clip.clip(offset, top, offset + split_at, bottom);
+        let clip_right = clip.clip(offset + split_at, top, offset + self.width as i32, bottom);
+        (split_at, clip_left, clip_right)
+    }
 }
 
 impl<Left, Right> GUIElement for HorizontalSplit<Left, Right>
@@ -151,25 +519,32 @@ where
         )
     }
 
+    fn min_height_for_width(&self, width: u32) -> u32 {
+        let width = max(width, self.min_size().0);
+        let (left_width, right_width) = match self.mode {
+            HorizontalSplitMode::ExpandLeft(split_at) => (width - split_at, split_at),
+            HorizontalSplitMode::ExpandRight(split_at) => (split_at, width - split_at),
+        };
+        max(
+            self.left.min_height_for_width(left_width),
+            self.right.min_height_for_width(right_width),
+        )
+    }
+
     fn size(&self) -> (u32, u32) {
         (self.width, self.height)
     }
 
-    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRow, y: i32, offset: i32) {
-        let clip = clip.clip(offset, offset + self.width as i32);
-        let split_at = match self.mode {
-            HorizontalSplitMode::ExpandLeft(split_at) => self.width - split_at,
-            HorizontalSplitMode::ExpandRight(split_at) => split_at,
-        } as i32;
-        let clip_left = clip.clip(offset, offset + split_at);
-        if !clip_left.is_empty() {
-            self.left.render_row(row, &clip_left, y, offset);
-        }
-        let clip_right = clip.clip(offset + split_at, offset + self.width as i32);
-        if !clip_left.is_empty() {
-            self.right
-                .render_row(row, &clip_right, y, offset + split_at);
-        }
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        let (split_at, clip_left, clip_right) = self.child_clips(clip, row.y() - y, offset);
+        render_child_row(&self.left, row, &clip_left, y, offset);
+        render_child_row(&self.right, row, &clip_right, y, offset + split_at);
+    }
+
+    fn render_band(&self, band: &mut BandRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        let (split_at, clip_left, clip_right) = self.child_clips(clip, band.y() - y, offset);
+        render_child_band(&self.left, band, &clip_left, y, offset);
+        render_child_band(&self.right, band, &clip_right, y, offset + split_at);
     }
 }
 
@@ -214,6 +589,19 @@ where
             height: 0,
         }
     }
+
+    /// Returns the split position and the clipping rectangles of the children
+    /// for an element whose top left corner is at (`offset`, `top`).
+    fn child_clips(&self, clip: &ClipRect, top: i32, offset: i32) -> (i32, ClipRect, ClipRect) {
+        let split_at = match self.mode {
+            VerticalSplitMode::ExpandTop(split_at) => self.height - split_at,
+            VerticalSplitMode::ExpandBottom(split_at) => split_at,
+        } as i32;
+        let right = offset + self.width as i32;
+        let clip_top = clip.clip(offset, top, right, top + split_at);
+        let clip_bottom = clip.clip(offset, top + split_at, right, top + self.height as i32);
+        (split_at, clip_top, clip_bottom)
+    }
 }
 
 impl<Top, Bottom> GUIElement for VerticalSplit<Top, Bottom>
@@ -223,7 +611,7 @@ where
 {
     fn resize(&mut self, width: u32, height: u32) {
         self.width = width;
-        self.height = max(height, self.min_size().1);
+        self.height = max(height, self.min_height_for_width(width));
         let (top_height, bottom_height) = match self.mode {
             VerticalSplitMode::ExpandTop(split_at) => (self.height - split_at, split_at),
             VerticalSplitMode::ExpandBottom(split_at) => (split_at, self.height - split_at),
@@ -242,21 +630,35 @@ where
         )
     }
 
+    fn min_height_for_width(&self, width: u32) -> u32 {
+        match self.mode {
+            VerticalSplitMode::ExpandTop(split_at) => {
+                self.top.min_height_for_width(width) + split_at
+            }
+            VerticalSplitMode::ExpandBottom(split_at) => {
+                split_at + self.bottom.min_height_for_width(width)
+            }
+        }
+    }
+
     fn size(&self) -> (u32, u32) {
         (self.width, self.height)
     }
 
-    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRow, y: i32, offset: i32) {
-        let split_at = match self.mode {
-            VerticalSplitMode::ExpandTop(split_at) => self.height - split_at,
-            VerticalSplitMode::ExpandBottom(split_at) => split_at,
-        } as i32;
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        let (split_at, clip_top, clip_bottom) = self.child_clips(clip, row.y() - y, offset);
         if y < split_at {
-            self.top.render_row(row, clip, y, offset);
+            render_child_row(&self.top, row, &clip_top, y, offset);
         } else {
-            self.bottom.render_row(row, clip, y - split_at, offset);
+            render_child_row(&self.bottom, row, &clip_bottom, y - split_at, offset);
         }
     }
+
+    fn render_band(&self, band: &mut BandRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        let (split_at, clip_top, clip_bottom) = self.child_clips(clip, band.y() - y, offset);
+        render_child_band(&self.top, band, &clip_top, y, offset);
+        render_child_band(&self.bottom, band, &clip_bottom, y - split_at, offset);
+    }
 }
 
 pub enum HorizontalAlign {
@@ -299,6 +701,30 @@ where
             height: 0,
         }
     }
+
+    fn element_offset(&self) -> (i32, i32) {
+        let (element_width, element_height) = self.element.size();
+        let x_offset = match self.horizontal {
+            HorizontalAlign::Left => 0,
+            HorizontalAlign::Center => (self.width as i32 - element_width as i32) / 2,
+            HorizontalAlign::Right => self.width as i32 - element_width as i32,
+        };
+        let y_offset = match self.vertical {
+            VerticalAlign::Top => 0,
+            VerticalAlign::Center => (self.height as i32 - element_height as i32) / 2,
+            VerticalAlign::Bottom => self.height as i32 - element_height as i32,
+        };
+        (x_offset, y_offset)
+    }
+
+    /// Returns the clipping rectangle of the element for an aligning element
+    /// whose top left corner is at (`offset`, `top`).
+    fn element_clip(&self, clip: &ClipRect, top: i32, offset: i32) -> ClipRect {
+        let (x_offset, y_offset) = self.element_offset();
+        let (width, height) = self.element.size();
+        let (left, top) = (offset + x_offset, top + y_offset);
+        clip.clip(left, top, left + width as i32, top + height as i32)
+    }
 }
 
 impl<Element> GUIElement for Align<Element>
@@ -308,8 +734,16 @@ where
     fn resize(&mut self, width: u32, height: u32) {
         self.width = width;
         self.height = height;
-        self.element
-            .resize(self.element.min_size().0, self.element.min_size().1);
+        // The element gets its preferred size as far as space is available,
+        // but never less than its minimum size.
+        let (min_width, _) = self.element.min_size();
+        let (preferred_width, preferred_height) = self.element.preferred_size();
+        let element_width = max(min_width, min(preferred_width, width));
+        let element_height = max(
+            self.element.min_height_for_width(element_width),
+            min(preferred_height, height),
+        );
+        self.element.resize(element_width, element_height);
     }
 
     fn min_size(&self) -> (u32, u32) {
@@ -320,20 +754,158 @@ where
         (self.width, self.height)
     }
 
-    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRow, y: i32, offset: i32) {
-        let (element_width, element_height) = self.element.size();
-        let x_offset = match self.horizontal {
-            HorizontalAlign::Left => 0,
-            HorizontalAlign::Center => (self.width as i32 - element_width as i32) / 2,
-            HorizontalAlign::Right => (self.width as i32 - element_width as i32),
-        };
-        let y_offset = match self.vertical {
-            VerticalAlign::Top => 0,
-            VerticalAlign::Center => (self.height as i32 - element_height as i32) / 2,
-            VerticalAlign::Bottom => (self.height as i32 - element_height as i32),
-        };
-        self.element
-            .render_row(row, clip, y as i32 + y_offset, offset as i32 + x_offset);
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        let (x_offset, y_offset) = self.element_offset();
+        let clip = self.element_clip(clip, row.y() - y, offset);
+        render_child_row(&self.element, row, &clip, y - y_offset, offset + x_offset);
+    }
+
+    fn render_band(&self, band: &mut BandRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        let (x_offset, y_offset) = self.element_offset();
+        let clip = self.element_clip(clip, band.y() - y, offset);
+        render_child_band(&self.element, band, &clip, y - y_offset, offset + x_offset);
+    }
+}
+
+/// Element which is drawn with its pixels inverted, e.g., to highlight a
+/// selected entry.
+pub struct Invert<Element>
+where
+    Element: GUIElement,
+{
+    element: Element,
+}
+
+impl<Element> Invert<Element>
+where
+    Element: GUIElement,
+{
+    pub fn new(element: Element) -> Invert<Element> {
+        Invert { element }
+    }
+}
+
+impl<Element> GUIElement for Invert<Element>
+where
+    Element: GUIElement,
+{
+    fn resize(&mut self, width: u32, height: u32) {
+        self.element.resize(width, height);
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        self.element.min_size()
+    }
+
+    fn min_height_for_width(&self, width: u32) -> u32 {
+        self.element.min_height_for_width(width)
+    }
+
+    fn preferred_size(&self) -> (u32, u32) {
+        self.element.preferred_size()
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.element.size()
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        self.element.render_row(row, clip, y, offset);
+        let (width, height) = self.element.size();
+        if y >= 0 && y < height as i32 {
+            row.invert(&clip.row(), offset, offset + width as i32);
+        }
+    }
+
+    fn render_band(&self, band: &mut BandRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        self.element.render_band(band, clip, y, offset);
+        let (width, height) = self.element.size();
+        for i in 0..band.rows() {
+            let row_y = y + i as i32;
+            if row_y >= 0 && row_y < height as i32 {
+                band.row(i)
+                    .invert(&clip.row(), offset, offset + width as i32);
+            }
+        }
+    }
+}
+
+/// Element which is covered with a pattern, e.g., to show a disabled state.
+pub struct Shade<Element>
+where
+    Element: GUIElement,
+{
+    element: Element,
+    pattern: Pattern,
+    color: Color,
+}
+
+impl<Element> Shade<Element>
+where
+    Element: GUIElement,
+{
+    pub fn new(element: Element, pattern: Pattern, color: Color) -> Shade<Element> {
+        Shade {
+            element,
+            pattern,
+            color,
+        }
+    }
+}
+
+impl<Element> GUIElement for Shade<Element>
+where
+    Element: GUIElement,
+{
+    fn resize(&mut self, width: u32, height: u32) {
+        self.element.resize(width, height);
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        self.element.min_size()
+    }
+
+    fn min_height_for_width(&self, width: u32) -> u32 {
+        self.element.min_height_for_width(width)
+    }
+
+    fn preferred_size(&self) -> (u32, u32) {
+        self.element.preferred_size()
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.element.size()
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        self.element.render_row(row, clip, y, offset);
+        let (width, height) = self.element.size();
+        if y >= 0 && y < height as i32 {
+            row.fill_pattern(
+                &clip.row(),
+                offset,
+                offset + width as i32,
+                &self.pattern,
+                self.color,
+            );
+        }
+    }
+
+    fn render_band(&self, band: &mut BandRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        self.element.render_band(band, clip, y, offset);
+        let (width, height) = self.element.size();
+        for i in 0..band.rows() {
+            let row_y = y + i as i32;
+            if row_y >= 0 && row_y < height as i32 {
+                band.row(i).fill_pattern(
+                    &clip.row(),
+                    offset,
+                    offset + width as i32,
+                    &self.pattern,
+                    self.color,
+                );
+            }
+        }
     }
 }
 
@@ -367,59 +939,281 @@ impl GUIElement for Fill {
         (self.width, self.height)
     }
 
-    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRow, _y: i32, offset: i32) {
-        row.fill(clip, offset, offset + self.width as i32, self.color);
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, _y: i32, offset: i32) {
+        row.fill(&clip.row(), offset, offset + self.width as i32, self.color);
     }
 }
 
-pub struct Text {
-    text: &'static str,
-    font: &'static font::Font,
+/// Area filled with a pattern in the foreground color on the background color.
+pub struct PatternFill {
+    pattern: Pattern,
+    foreground: Color,
+    background: Color,
     width: u32,
     height: u32,
 }
 
-impl Text {
-    pub fn new(text: &'static str, font: &'static font::Font) -> Text {
-        let (width, height) = font.get_text_size(text);
-        Text {
-            text: text,
-            font: font,
-            width: width,
-            height: height,
+impl PatternFill {
+    pub fn new(pattern: Pattern, foreground: Color, background: Color) -> PatternFill {
+        PatternFill {
+            pattern,
+            foreground,
+            background,
+            width: 0,
+            height: 0,
         }
     }
 }
 
-impl GUIElement for Text {
-    fn resize(&mut self, _width: u32, _height: u32) {
-        // Ignore, as the font dictates the size of the text.
+impl GUIElement for PatternFill {
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
     }
 
     fn min_size(&self) -> (u32, u32) {
+        (1, 1)
+    }
+
+    fn size(&self) -> (u32, u32) {
         (self.width, self.height)
     }
 
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, _y: i32, offset: i32) {
+        let clip = clip.row();
+        let right = offset + self.width as i32;
+        row.fill(&clip, offset, right, self.background);
+        row.fill_pattern(&clip, offset, right, &self.pattern, self.foreground);
+    }
+}
+
+/// Area filled with a dithered gray level.
+pub struct GrayFill<'a> {
+    luminance: u8,
+    dither: RefCell<Dither<'a>>,
+    width: u32,
+    height: u32,
+}
+
+impl<'a> GrayFill<'a> {
+    pub fn new(luminance: u8, dither: Dither<'a>) -> GrayFill<'a> {
+        GrayFill {
+            luminance,
+            dither: RefCell::new(dither),
+            width: 0,
+            height: 0,
+        }
+    }
+}
+
+impl<'a> GUIElement for GrayFill<'a> {
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        (1, 1)
+    }
+
     fn size(&self) -> (u32, u32) {
         (self.width, self.height)
     }
 
-    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRow, y: i32, offset: i32) {
-        self.font.render_row(row, clip, self.text, y, offset);
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, _y: i32, offset: i32) {
+        row.fill_gray(
+            &clip.row(),
+            offset,
+            offset + self.width as i32,
+            self.luminance,
+            &mut self.dither.borrow_mut(),
+        );
+    }
+}
+
+/// Single line of text rendered with a `Font` or another `TextFont`, such as
+/// a `FontChain`.
+///
+/// By default, the font dictates the size of the element and text which is
+/// wider than the space assigned to it extends beyond the element. This can
+/// be changed with `set_overflow()`.
+pub struct Text<F: TextFont + ?Sized + 'static = font::Font> {
+    text: &'static str,
+    line: TextLine<F>,
+}
+
+impl<F: TextFont + ?Sized> Text<F> {
+    pub fn new(text: &'static str, font: &'static F) -> Text<F> {
+        Text {
+            text: text,
+            line: TextLine::new(font, text),
+        }
+    }
+
+    pub fn set_overflow(&mut self, overflow: Overflow<F>) {
+        self.line.set_overflow(overflow, self.text);
+    }
+}
+
+impl<F: TextFont + ?Sized> GUIElement for Text<F> {
+    fn resize(&mut self, width: u32, _height: u32) {
+        self.line.resize(width, self.text);
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        self.line.min_size()
+    }
+
+    fn preferred_size(&self) -> (u32, u32) {
+        self.line.preferred_size()
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.line.size()
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        self.line.render_row(self.text, row, clip, y, offset);
+    }
+
+    fn render_band(&self, band: &mut BandRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        self.line.render_band(self.text, band, clip, y, offset);
+    }
+}
+
+/// Single line of text which can be changed at runtime, e.g., to display
+/// sensor readings or the time.
+///
+/// The text is stored in a string with a capacity of `N` bytes. Changing the
+/// text marks the element as dirty, so that the application can check
+/// whether the display needs to be refreshed.
+pub struct DynamicText<const N: usize, F: TextFont + ?Sized + 'static = font::Font> {
+    text: heapless::String<N>,
+    line: TextLine<F>,
+    dirty: bool,
+}
+
+impl<const N: usize, F: TextFont + ?Sized> DynamicText<N, F> {
+    pub fn new(text: &str, font: &'static F) -> DynamicText<N, F> {
+        let mut element = DynamicText {
+            text: heapless::String::new(),
+            line: TextLine::new(font, ""),
+            dirty: true,
+        };
+        element.set_text(text);
+        element
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the text and measures it again. Text which does not fit into
+    /// the capacity of the element is cut off at the last complete character.
+    ///
+    /// The width of the element changes with the text, so `Layout::relayout()`
+    /// has to be called before the containing layout is rendered again.
+    pub fn set_text(&mut self, text: &str) {
+        let mut length = min(text.len(), N);
+        while !text.is_char_boundary(length) {
+            length -= 1;
+        }
+        let text = &text[..length];
+        if self.text != text {
+            self.text.clear();
+            self.text.push_str(text).ok();
+            self.dirty = true;
+        }
+        self.line.update(&self.text);
+    }
+
+    pub fn set_overflow(&mut self, overflow: Overflow<F>) {
+        self.line.set_overflow(overflow, &self.text);
+    }
+
+    /// Returns whether the text has changed since the last call to
+    /// `clear_dirty()`.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+}
+
+impl<const N: usize, F: TextFont + ?Sized> GUIElement for DynamicText<N, F> {
+    fn resize(&mut self, width: u32, _height: u32) {
+        self.line.resize(width, &self.text);
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        self.line.min_size()
+    }
+
+    fn preferred_size(&self) -> (u32, u32) {
+        self.line.preferred_size()
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.line.size()
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        self.line.render_row(&self.text, row, clip, y, offset);
+    }
+
+    fn render_band(&self, band: &mut BandRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        self.line.render_band(&self.text, band, clip, y, offset);
     }
 }
 
-pub struct Image {
-    image: &'static image::BitmapImage,
+/// Black and white image in any of the formats in `image`.
+pub struct Image<I: RowImage + 'static = image::BitmapImage> {
+    image: &'static I,
 }
 
-impl Image {
-    pub fn new(image: &'static image::BitmapImage) -> Image {
+impl<I: RowImage> Image<I> {
+    pub fn new(image: &'static I) -> Image<I> {
         Image { image: image }
     }
 }
 
-impl GUIElement for Image {
+impl<I: RowImage> GUIElement for Image<I> {
+    fn resize(&mut self, _width: u32, _height: u32) {
+        // Ignore, as the image dictates the size.
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        (self.image.width(), self.image.height())
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.image.width(), self.image.height())
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        self.image
+            .render_row_transparent(row, &clip.row(), y, offset);
+    }
+}
+
+/// Grayscale image which is dithered during rendering.
+pub struct DitheredImage<'a> {
+    image: &'static image::GrayImage,
+    dither: RefCell<Dither<'a>>,
+}
+
+impl<'a> DitheredImage<'a> {
+    pub fn new(image: &'static image::GrayImage, dither: Dither<'a>) -> DitheredImage<'a> {
+        DitheredImage {
+            image,
+            dither: RefCell::new(dither),
+        }
+    }
+}
+
+impl<'a> GUIElement for DitheredImage<'a> {
     fn resize(&mut self, _width: u32, _height: u32) {
         // Ignore, as the image dictates the size.
     }
@@ -432,7 +1226,232 @@ impl GUIElement for Image {
         (self.image.width as u32, self.image.height as u32)
     }
 
-    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRow, y: i32, offset: i32) {
-        self.image.render_row_transparent(row, clip, y, offset);
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        self.image
+            .render_row(row, &clip.row(), y, offset, &mut self.dither.borrow_mut());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::font::{Font, Glyph, GlyphImage};
+    use super::image::RLEImage;
+    use super::{
+        Align, DynamicText, Fill, GUIElement, HorizontalAlign, HorizontalSplit, Layout, Rotation,
+        Text, VerticalAlign, VerticalSplit,
+    };
+    use crate::{Color, Display, GrayscaleDisplay, Mirror, RedDisplay, TestDisplay};
+
+    static TEST_GLYPHS: [Glyph; 1] = [Glyph {
+        image: GlyphImage::Rle(RLEImage {
+            data: &[4, 5, 7, 8, 0x8003, 0x0001, 0x8001, 0x8003],
+            width: 3,
+            height: 3,
+        }),
+        image_left: 0,
+        image_top: 2,
+        advance: 4,
+    }];
+
+    static TEST_FONT: Font = Font {
+        ascender: 3,
+        descender: 1,
+        glyphs: &TEST_GLYPHS,
+        get_glyph_index: |c| if c == 'o' { Some(0) } else { None },
+        kerning: &[],
+        replacement: None,
+    };
+
+    fn render_rotated(rotation: Rotation, row_buffer: &mut [u8]) -> TestDisplay {
+        render_oriented(rotation, Mirror::None, row_buffer)
+    }
+
+    fn render_oriented(rotation: Rotation, mirror: Mirror, row_buffer: &mut [u8]) -> TestDisplay {
+        // Black bar along the top edge of the GUI.
+        let root =
+            VerticalSplit::expand_bottom(10, Fill::new(Color::Black), Fill::new(Color::White));
+        let mut layout =
+            Layout::new_rotated(TestDisplay::WIDTH, TestDisplay::HEIGHT, rotation, root);
+        layout.set_mirror(mirror);
+        let mut display = TestDisplay::new();
+        layout.render(&mut display, row_buffer);
+        assert_eq!(display.frame.len(), (320 / 8) * 240);
+        display
+    }
+
+    #[test]
+    fn test_layout_rotation() {
+        let display = render_rotated(Rotation::Rotate0, &mut [0u8; 40]);
+        assert!(display.frame[..400].iter().all(|&b| b == 0x00));
+        assert!(display.frame[400..].iter().all(|&b| b == 0xff));
+
+        let display = render_rotated(Rotation::Rotate180, &mut [0u8; 40]);
+        assert!(display.frame[..230 * 40].iter().all(|&b| b == 0xff));
+        assert!(display.frame[230 * 40..].iter().all(|&b| b == 0x00));
+
+        // One logical row and three native rows.
+        let display = render_rotated(Rotation::Rotate90, &mut [0u8; 30 + 120]);
+        for row in display.frame.chunks(40) {
+            assert!(row[..38].iter().all(|&b| b == 0xff));
+            assert_eq!(row[38], 0xfc);
+            assert_eq!(row[39], 0x00);
+        }
+
+        let display = render_rotated(Rotation::Rotate270, &mut [0u8; 30 + 40]);
+        for row in display.frame.chunks(40) {
+            assert_eq!(row[0], 0x00);
+            assert_eq!(row[1], 0x3f);
+            assert!(row[2..].iter().all(|&b| b == 0xff));
+        }
+    }
+
+    #[test]
+    fn test_layout_mirror() {
+        let display = render_oriented(Rotation::Rotate0, Mirror::Vertical, &mut [0u8; 40]);
+        assert!(display.frame[..230 * 40].iter().all(|&b| b == 0xff));
+        assert!(display.frame[230 * 40..].iter().all(|&b| b == 0x00));
+
+        let display = render_oriented(Rotation::Rotate180, Mirror::Both, &mut [0u8; 40]);
+        assert!(display.frame[..400].iter().all(|&b| b == 0x00));
+        assert!(display.frame[400..].iter().all(|&b| b == 0xff));
+
+        // Rotating by 90 degrees and mirroring horizontally moves the bar to
+        // the left edge.
+        let display = render_oriented(Rotation::Rotate90, Mirror::Horizontal, &mut [0u8; 70]);
+        for row in display.frame.chunks(40) {
+            assert_eq!(row[0], 0x00);
+            assert_eq!(row[1], 0x3f);
+            assert!(row[2..].iter().all(|&b| b == 0xff));
+        }
+    }
+
+    #[test]
+    fn test_layout_gray() {
+        let root = VerticalSplit::expand_bottom(
+            10,
+            Fill::new(Color::Gray(1)),
+            HorizontalSplit::expand_left(160, Fill::new(Color::Gray(2)), Fill::new(Color::White)),
+        );
+        let layout = Layout::new(TestDisplay::WIDTH, TestDisplay::HEIGHT, root);
+        let mut display = TestDisplay::new();
+        display.start_gray_frame().ok().unwrap();
+        layout.render_gray(&mut display, &mut [0u8; 80 * 4]);
+        display.end_gray_frame();
+        assert_eq!(display.planes, 2);
+        // Both planes contain the same 2-bit rows.
+        let (first, second) = display.frame.split_at(80 * 240);
+        assert!(first == second);
+        assert!(first[..800].iter().all(|&b| b == 0x55));
+        for row in first[800..].chunks(80) {
+            assert!(row[..40].iter().all(|&b| b == 0xaa));
+            assert!(row[40..].iter().all(|&b| b == 0xff));
+        }
+    }
+
+    #[test]
+    fn test_layout_red() {
+        let root = VerticalSplit::expand_bottom(
+            10,
+            Fill::new(Color::Red),
+            HorizontalSplit::expand_left(160, Fill::new(Color::Black), Fill::new(Color::White)),
+        );
+        let layout = Layout::new(TestDisplay::WIDTH, TestDisplay::HEIGHT, root);
+        let mut display = TestDisplay::new();
+        display.start_red_frame().ok().unwrap();
+        layout.render_red(&mut display, &mut [0u8; 40 * 4]);
+        display.end_red_frame();
+        assert_eq!(display.planes, 2);
+        let (black_white, red) = display.frame.split_at(40 * 240);
+        // Red pixels are white in the black/white plane.
+        assert!(black_white[..400].iter().all(|&b| b == 0xff));
+        assert!(red[..400].iter().all(|&b| b == 0x00));
+        for row in black_white[400..].chunks(40) {
+            assert!(row[..20].iter().all(|&b| b == 0x00));
+            assert!(row[20..].iter().all(|&b| b == 0xff));
+        }
+        assert!(red[400..].iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn test_layout_clip() {
+        // Fill ignores the row, so the aligned 1x1 fill relies on clipping to
+        // not draw the whole column.
+        let root = Align::new(
+            HorizontalAlign::Center,
+            VerticalAlign::Top,
+            Fill::new(Color::Black),
+        );
+        let layout = Layout::new(TestDisplay::WIDTH, TestDisplay::HEIGHT, root);
+        for rows in [1, 8].iter() {
+            let mut display = TestDisplay::new();
+            let mut buffer = std::vec![0u8; 40 * rows];
+            layout.render(&mut display, &mut buffer);
+            assert_eq!(display.frame[19], 0xfe);
+            assert_eq!(display.frame.iter().filter(|&&b| b != 0xff).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_layout_bands() {
+        let text = || {
+            Align::new(
+                HorizontalAlign::Center,
+                VerticalAlign::Bottom,
+                Text::new("ooo", &TEST_FONT),
+            )
+        };
+        let root = VerticalSplit::expand_bottom(
+            37,
+            HorizontalSplit::expand_left(100, text(), Fill::new(Color::Black)),
+            text(),
+        );
+        let layout = Layout::new(TestDisplay::WIDTH, TestDisplay::HEIGHT, root);
+        let mut display = TestDisplay::new();
+        layout.render(&mut display, &mut [0u8; 40]);
+        let expected = display.frame;
+        assert!(expected[..36 * 40].iter().any(|&b| b != 0xff));
+        assert!(expected[37 * 40..].iter().any(|&b| b != 0xff));
+
+        for rows in [2, 7, 64].iter() {
+            let mut display = TestDisplay::new();
+            let mut buffer = std::vec![0u8; 40 * rows];
+            layout.render(&mut display, &mut buffer);
+            assert!(display.frame == expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "row buffer too small")]
+    fn test_layout_small_buffer() {
+        let layout = Layout::new(
+            TestDisplay::WIDTH,
+            TestDisplay::HEIGHT,
+            Fill::new(Color::Black),
+        );
+        layout.render(&mut TestDisplay::new(), &mut [0u8; 39]);
+    }
+
+    #[test]
+    fn test_dynamic_text() {
+        let mut text = DynamicText::<4>::new("oo", &TEST_FONT);
+        assert_eq!(text.size(), (8, 4));
+        assert!(text.is_dirty());
+        text.clear_dirty();
+        text.set_text("oo");
+        assert!(!text.is_dirty());
+        text.set_text("oooéo");
+        assert_eq!(text.text(), "ooo");
+        assert_eq!(text.size(), (12, 4));
+        assert!(text.is_dirty());
+
+        fn render<E: GUIElement>(element: E) -> TestDisplay {
+            let element = Align::new(HorizontalAlign::Center, VerticalAlign::Center, element);
+            let layout = Layout::new(TestDisplay::WIDTH, TestDisplay::HEIGHT, element);
+            let mut display = TestDisplay::new();
+            layout.render(&mut display, &mut [0u8; 40]);
+            display
+        }
+        let expected = render(Text::new("ooo", &TEST_FONT));
+        assert!(render(text).frame == expected.frame);
     }
 }
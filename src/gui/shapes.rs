@@ -0,0 +1,687 @@
+//! Geometric shapes.
+//!
+//! The shapes do not use any intermediate buffer. Instead, the spans covered
+//! in a row are computed analytically whenever the row is rendered. A pixel
+//! belongs to a shape if its center lies inside the shape.
+
+use super::GUIElement;
+use crate::{ClipRect, ClipRow, Color, RowRenderer};
+
+use core::cmp::max;
+
+/// Fixed-point precision of the polygon rasterizer (8 fractional bits).
+const ONE: i64 = 256;
+const HALF: i64 = ONE / 2;
+
+/// Sine from 0 to 90 degrees in steps of one degree, scaled by 16384.
+const SINE: [i16; 91] = [
+    0, 286, 572, 857, 1143, 1428, 1713, 1997, 2280, 2563, 2845, 3126, 3406, 3686, 3964, 4240, 4516,
+    4790, 5063, 5334, 5604, 5872, 6138, 6402, 6664, 6924, 7182, 7438, 7692, 7943, 8192, 8438, 8682,
+    8923, 9162, 9397, 9630, 9860, 10087, 10311, 10531, 10749, 10963, 11174, 11381, 11585, 11786,
+    11982, 12176, 12365, 12551, 12733, 12911, 13085, 13255, 13421, 13583, 13741, 13894, 14044,
+    14189, 14330, 14466, 14598, 14726, 14849, 14968, 15082, 15191, 15296, 15396, 15491, 15582,
+    15668, 15749, 15826, 15897, 15964, 16026, 16083, 16135, 16182, 16225, 16262, 16294, 16322,
+    16344, 16362, 16374, 16382, 16384,
+];
+
+/// Drawing style of a shape.
+#[derive(Copy, Clone)]
+pub enum Style {
+    Filled(Color),
+    /// Outline with the specified width in pixels, drawn inside the shape.
+    Outline(Color, u32),
+}
+
+/// Rectangle filling the whole element, optionally with rounded corners.
+pub struct Rectangle {
+    style: Style,
+    radius: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Rectangle {
+    pub fn new(style: Style) -> Rectangle {
+        Self::rounded(0, style)
+    }
+
+    pub fn rounded(radius: u32, style: Style) -> Rectangle {
+        Rectangle {
+            style,
+            radius,
+            width: 0,
+            height: 0,
+        }
+    }
+}
+
+impl GUIElement for Rectangle {
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        (1, 1)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        let clip = clip.row().clip(offset, offset + self.width as i32);
+        let outer = rounded_rect_span(self.width, self.height, self.radius, y);
+        render_style_row(row, &clip, offset, self.style, outer, |stroke| {
+            rounded_rect_span(
+                self.width.saturating_sub(2 * stroke),
+                self.height.saturating_sub(2 * stroke),
+                self.radius.saturating_sub(stroke),
+                y - stroke as i32,
+            )
+            .map(|(left, right)| (left + stroke as i32, right + stroke as i32))
+        });
+    }
+}
+
+/// Ellipse filling the whole element.
+pub struct Ellipse {
+    style: Style,
+    width: u32,
+    height: u32,
+}
+
+impl Ellipse {
+    pub fn new(style: Style) -> Ellipse {
+        Ellipse {
+            style,
+            width: 0,
+            height: 0,
+        }
+    }
+}
+
+impl GUIElement for Ellipse {
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        (1, 1)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        let clip = clip.row().clip(offset, offset + self.width as i32);
+        render_ellipse_row(row, &clip, offset, self.width, self.height, y, self.style);
+    }
+}
+
+/// Circle with a fixed diameter.
+pub struct Circle {
+    style: Style,
+    diameter: u32,
+}
+
+impl Circle {
+    pub fn new(diameter: u32, style: Style) -> Circle {
+        Circle { style, diameter }
+    }
+}
+
+impl GUIElement for Circle {
+    fn resize(&mut self, _width: u32, _height: u32) {
+        // Ignore, as the diameter is fixed.
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        (self.diameter, self.diameter)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.diameter, self.diameter)
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        let clip = clip.row().clip(offset, offset + self.diameter as i32);
+        render_ellipse_row(
+            row,
+            &clip,
+            offset,
+            self.diameter,
+            self.diameter,
+            y,
+            self.style,
+        );
+    }
+}
+
+/// Arc of a circle with a fixed diameter.
+///
+/// Angles are specified in degrees, starting at the right (three o'clock)
+/// and increasing clockwise. The arc is drawn from `start` to
+/// `start + sweep`.
+pub struct Arc {
+    diameter: u32,
+    line_width: u32,
+    color: Color,
+    start: (i64, i64),
+    end: (i64, i64),
+    sweep: i32,
+}
+
+impl Arc {
+    pub fn new(diameter: u32, start: i32, sweep: i32, line_width: u32, color: Color) -> Arc {
+        // Normalize negative sweeps so that the arc always runs clockwise.
+        let (start, sweep) = if sweep < 0 {
+            (start + sweep, -sweep)
+        } else {
+            (start, sweep)
+        };
+        Arc {
+            diameter,
+            line_width,
+            color,
+            start: direction(start),
+            end: direction(start + sweep),
+            sweep,
+        }
+    }
+
+    /// Returns whether the point (relative to the center) is within the
+    /// angular range of the arc.
+    fn contains(&self, x: i64, y: i64) -> bool {
+        let cross = |a: (i64, i64), b: (i64, i64)| a.0 * b.1 - a.1 * b.0;
+        let after_start = cross(self.start, (x, y)) >= 0;
+        let before_end = cross((x, y), self.end) >= 0;
+        if self.sweep >= 360 {
+            true
+        } else if self.sweep <= 180 {
+            after_start && before_end
+        } else {
+            after_start || before_end
+        }
+    }
+}
+
+impl GUIElement for Arc {
+    fn resize(&mut self, _width: u32, _height: u32) {
+        // Ignore, as the diameter is fixed.
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        (self.diameter, self.diameter)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.diameter, self.diameter)
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        let clip = clip.row().clip(offset, offset + self.diameter as i32);
+        let d = self.diameter;
+        let outer = ellipse_span(d, d, y);
+        // Coordinates relative to the center, scaled by two.
+        let center_y = (2 * y + 1 - d as i32) as i64;
+        let mut fill_sector = |left: i32, right: i32| {
+            let mut run_start = None;
+            for x in left..=right {
+                let inside = x < right && self.contains((2 * x + 1 - d as i32) as i64, center_y);
+                match (inside, run_start) {
+                    (true, None) => run_start = Some(x),
+                    (false, Some(start)) => {
+                        row.fill(&clip, offset + start, offset + x, self.color);
+                        run_start = None;
+                    }
+                    _ => {}
+                }
+            }
+        };
+        for_style_spans(
+            Style::Outline(self.color, self.line_width),
+            outer,
+            |stroke| {
+                ellipse_span(
+                    d.saturating_sub(2 * stroke),
+                    d.saturating_sub(2 * stroke),
+                    y - stroke as i32,
+                )
+                .map(|(left, right)| (left + stroke as i32, right + stroke as i32))
+            },
+            &mut fill_sector,
+        );
+    }
+}
+
+/// Straight line between the centers of two pixels, with square caps.
+///
+/// The line is clipped to the element, so the coordinates should leave
+/// enough space for half of the line width.
+pub struct Line {
+    from: (i32, i32),
+    to: (i32, i32),
+    line_width: u32,
+    color: Color,
+    width: u32,
+    height: u32,
+}
+
+impl Line {
+    pub fn new(from: (i32, i32), to: (i32, i32), line_width: u32, color: Color) -> Line {
+        Line {
+            from,
+            to,
+            line_width,
+            color,
+            width: 0,
+            height: 0,
+        }
+    }
+}
+
+impl GUIElement for Line {
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        // Lines entirely outside of the element do not need any space.
+        (
+            max(0, self.from.0.max(self.to.0) + 1) as u32,
+            max(0, self.from.1.max(self.to.1) + 1) as u32,
+        )
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        if y < 0 || y >= self.height as i32 {
+            return;
+        }
+        let clip = clip.row().clip(offset, offset + self.width as i32);
+        let quad = line_quad(self.from, self.to, self.line_width);
+        polygon_spans(
+            quad.len(),
+            |i| quad[i],
+            y,
+            |left, right| {
+                row.fill(&clip, offset + left, offset + right, self.color);
+            },
+        );
+    }
+}
+
+/// Polygon with an arbitrary number of corners.
+///
+/// The corners are specified in pixel corner coordinates, so the polygon
+/// `(0, 0), (4, 0), (4, 4), (0, 4)` covers 4x4 pixels. Filled polygons may be
+/// concave or self-intersecting and use the non-zero winding rule. Outlines
+/// are drawn centered on the edges.
+pub struct Polygon<'a> {
+    points: &'a [(i32, i32)],
+    style: Style,
+    width: u32,
+    height: u32,
+}
+
+impl<'a> Polygon<'a> {
+    pub fn new(points: &'a [(i32, i32)], style: Style) -> Polygon<'a> {
+        Polygon {
+            points,
+            style,
+            width: 0,
+            height: 0,
+        }
+    }
+}
+
+impl<'a> GUIElement for Polygon<'a> {
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        let width = self.points.iter().map(|p| p.0).max().unwrap_or(0);
+        let height = self.points.iter().map(|p| p.1).max().unwrap_or(0);
+        (max(0, width) as u32, max(0, height) as u32)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        if y < 0 || y >= self.height as i32 {
+            return;
+        }
+        let clip = clip.row().clip(offset, offset + self.width as i32);
+        match self.style {
+            Style::Filled(color) => {
+                let points = self.points;
+                let corner = |i: usize| (points[i].0 as i64 * ONE, points[i].1 as i64 * ONE);
+                polygon_spans(points.len(), corner, y, |left, right| {
+                    row.fill(&clip, offset + left, offset + right, color);
+                });
+            }
+            Style::Outline(color, line_width) => {
+                for i in 0..self.points.len() {
+                    let next = self.points[(i + 1) % self.points.len()];
+                    let quad = line_quad_fixed(
+                        (self.points[i].0 as i64 * ONE, self.points[i].1 as i64 * ONE),
+                        (next.0 as i64 * ONE, next.1 as i64 * ONE),
+                        line_width,
+                    );
+                    polygon_spans(
+                        quad.len(),
+                        |i| quad[i],
+                        y,
+                        |left, right| {
+                            row.fill(&clip, offset + left, offset + right, color);
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn render_ellipse_row(
+    row: &mut RowRenderer,
+    clip: &ClipRow,
+    offset: i32,
+    width: u32,
+    height: u32,
+    y: i32,
+    style: Style,
+) {
+    let outer = ellipse_span(width, height, y);
+    render_style_row(row, clip, offset, style, outer, |stroke| {
+        ellipse_span(
+            width.saturating_sub(2 * stroke),
+            height.saturating_sub(2 * stroke),
+            y - stroke as i32,
+        )
+        .map(|(left, right)| (left + stroke as i32, right + stroke as i32))
+    });
+}
+
+/// Fills the span of a shape in a row. For outlines, `inner` returns the span
+/// of the shape shrunk by the outline width, which is left empty.
+fn render_style_row<F>(
+    row: &mut RowRenderer,
+    clip: &ClipRow,
+    offset: i32,
+    style: Style,
+    outer: Option<(i32, i32)>,
+    inner: F,
+) where
+    F: Fn(u32) -> Option<(i32, i32)>,
+{
+    let color = match style {
+        Style::Filled(color) | Style::Outline(color, _) => color,
+    };
+    for_style_spans(style, outer, inner, |left, right| {
+        row.fill(clip, offset + left, offset + right, color);
+    });
+}
+
+fn for_style_spans<F, G>(style: Style, outer: Option<(i32, i32)>, inner: F, mut span: G)
+where
+    F: Fn(u32) -> Option<(i32, i32)>,
+    G: FnMut(i32, i32),
+{
+    let (left, right) = match outer {
+        Some(outer) => outer,
+        None => return,
+    };
+    match style {
+        Style::Filled(_) => span(left, right),
+        Style::Outline(_, stroke) => match inner(stroke) {
+            Some((inner_left, inner_right)) if inner_left < inner_right => {
+                span(left, inner_left);
+                span(inner_right, right);
+            }
+            _ => span(left, right),
+        },
+    }
+}
+
+/// Returns the pixels of row `y` whose centers lie within an ellipse filling
+/// a `width` x `height` rectangle.
+fn ellipse_span(width: u32, height: u32, y: i32) -> Option<(i32, i32)> {
+    if width == 0 || height == 0 || y < 0 || y >= height as i32 {
+        return None;
+    }
+    // With coordinates relative to the center and scaled by two, a pixel is
+    // inside if (x / width)^2 + (y / height)^2 <= 1.
+    let (w, h) = (width as u64, height as u64);
+    let dy = (2 * y as i64 + 1 - height as i64).unsigned_abs();
+    let max_dx = isqrt(w * w * (h * h - dy * dy) / (h * h)) as i32;
+    let left = (width as i32 - 1 - max_dx + 1).div_euclid(2);
+    let right = (width as i32 - 1 + max_dx).div_euclid(2) + 1;
+    if left < right {
+        Some((left, right))
+    } else {
+        None
+    }
+}
+
+/// Returns the pixels of row `y` within a rectangle with rounded corners.
+fn rounded_rect_span(width: u32, height: u32, radius: u32, y: i32) -> Option<(i32, i32)> {
+    if width == 0 || height == 0 || y < 0 || y >= height as i32 {
+        return None;
+    }
+    let radius = radius.min(width / 2).min(height / 2) as i32;
+    let corner_y = if y < radius {
+        y
+    } else if y >= height as i32 - radius {
+        y - (height as i32 - 2 * radius)
+    } else {
+        return Some((0, width as i32));
+    };
+    let diameter = 2 * radius as u32;
+    ellipse_span(diameter, diameter, corner_y).map(|(left, _)| (left, width as i32 - left))
+}
+
+/// Returns the corners of a line with square caps in fixed-point coordinates.
+fn line_quad(from: (i32, i32), to: (i32, i32), line_width: u32) -> [(i64, i64); 4] {
+    let center = |p: (i32, i32)| (p.0 as i64 * ONE + HALF, p.1 as i64 * ONE + HALF);
+    line_quad_fixed(center(from), center(to), line_width)
+}
+
+fn line_quad_fixed(from: (i64, i64), to: (i64, i64), line_width: u32) -> [(i64, i64); 4] {
+    let half_width = line_width as i64 * HALF;
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length = isqrt((dx * dx + dy * dy) as u64) as i64;
+    // Vector along the line and normal vector, both with half the line width.
+    let (along, normal) = if length == 0 {
+        ((half_width, 0), (0, half_width))
+    } else {
+        (
+            (dx * half_width / length, dy * half_width / length),
+            (-dy * half_width / length, dx * half_width / length),
+        )
+    };
+    [
+        (from.0 - along.0 + normal.0, from.1 - along.1 + normal.1),
+        (to.0 + along.0 + normal.0, to.1 + along.1 + normal.1),
+        (to.0 + along.0 - normal.0, to.1 + along.1 - normal.1),
+        (from.0 - along.0 - normal.0, from.1 - along.1 - normal.1),
+    ]
+}
+
+/// Calls `span` for all pixel spans of row `y` inside a polygon with
+/// fixed-point corners, using the non-zero winding rule.
+///
+/// The crossings of the row with the edges are visited in order by repeatedly
+/// searching for the next one, so no memory is needed for sorting.
+fn polygon_spans<P, F>(count: usize, corner: P, y: i32, mut span: F)
+where
+    P: Fn(usize) -> (i64, i64),
+    F: FnMut(i32, i32),
+{
+    let center_y = y as i64 * ONE + HALF;
+    let crossing = |i: usize| {
+        let (x0, y0) = corner(i);
+        let (x1, y1) = corner((i + 1) % count);
+        if (y0 <= center_y && center_y < y1) || (y1 <= center_y && center_y < y0) {
+            let x = x0 + (center_y - y0) * (x1 - x0) / (y1 - y0);
+            Some((x, if y1 > y0 { 1 } else { -1 }))
+        } else {
+            None
+        }
+    };
+    // First pixel whose center is at or right of the fixed-point coordinate.
+    let pixel = |x: i64| (x - HALF + ONE - 1).div_euclid(ONE) as i32;
+
+    let mut previous: Option<(i64, usize)> = None;
+    let mut winding = 0;
+    let mut span_start = 0;
+    loop {
+        let mut next: Option<(i64, usize, i32)> = None;
+        for i in 0..count {
+            if let Some((x, direction)) = crossing(i) {
+                let after_previous = match previous {
+                    Some(previous) => (x, i) > previous,
+                    None => true,
+                };
+                let before_next = match next {
+                    Some((next_x, next_i, _)) => (x, i) < (next_x, next_i),
+                    None => true,
+                };
+                if after_previous && before_next {
+                    next = Some((x, i, direction));
+                }
+            }
+        }
+        let (x, i, direction) = match next {
+            Some(next) => next,
+            None => break,
+        };
+        if winding == 0 {
+            span_start = x;
+        }
+        winding += direction;
+        if winding == 0 {
+            span(pixel(span_start), pixel(x));
+        }
+        previous = Some((x, i));
+    }
+}
+
+/// Returns the direction vector for an angle in degrees, scaled by 16384.
+fn direction(angle: i32) -> (i64, i64) {
+    let sin = |angle: i32| {
+        let angle = angle.rem_euclid(360);
+        let value = match angle {
+            0..=90 => SINE[angle as usize],
+            91..=180 => SINE[(180 - angle) as usize],
+            181..=270 => -SINE[(angle - 180) as usize],
+            _ => -SINE[(360 - angle) as usize],
+        };
+        value as i64
+    };
+    (sin(angle + 90), sin(angle))
+}
+
+fn isqrt(value: u64) -> u64 {
+    if value < 2 {
+        return value;
+    }
+    let mut x = value;
+    let mut y = x.div_ceil(2);
+    while y < x {
+        x = y;
+        y = (x + value / x) / 2;
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Arc, Circle, Line, Polygon, Rectangle, Style};
+    use crate::gui::GUIElement;
+    use crate::{ClipRect, Color, RowRenderer};
+    use std::vec::Vec;
+
+    /// Renders the element on a black background and returns the rows, with
+    /// set bits marking white pixels.
+    fn render<E: GUIElement>(element: &mut E, width: u32, height: u32) -> Vec<u16> {
+        element.resize(width, height);
+        (0..height as i32)
+            .map(|y| {
+                let mut buffer = [0u8; 2];
+                let mut row = RowRenderer::new(&mut buffer, 16);
+                let clip = ClipRect::new(0, 0, 16, height as i32);
+                element.render_row(&mut row, &clip, y, 0);
+                u16::from_be_bytes(buffer)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rectangle() {
+        let rows = render(
+            &mut Rectangle::rounded(2, Style::Outline(Color::White, 1)),
+            8,
+            6,
+        );
+        assert_eq!(rows, [0x7e00, 0x8100, 0x8100, 0x8100, 0x8100, 0x7e00]);
+
+        let rows = render(&mut Rectangle::new(Style::Filled(Color::White)), 4, 2);
+        assert_eq!(rows, [0xf000, 0xf000]);
+    }
+
+    #[test]
+    fn test_circle() {
+        let rows = render(&mut Circle::new(6, Style::Filled(Color::White)), 6, 6);
+        assert_eq!(rows, [0x7800, 0xfc00, 0xfc00, 0xfc00, 0xfc00, 0x7800]);
+
+        // Right half of a ring.
+        let rows = render(&mut Arc::new(6, -90, 180, 1, Color::White), 6, 6);
+        assert_eq!(rows, [0x1800, 0x0c00, 0x0400, 0x0400, 0x0c00, 0x1800]);
+    }
+
+    #[test]
+    fn test_line() {
+        let rows = render(&mut Line::new((0, 0), (7, 7), 1, Color::White), 8, 8);
+        for (y, row) in rows.iter().enumerate() {
+            assert_eq!(*row, 0x8000 >> y);
+        }
+
+        let rows = render(&mut Line::new((1, 2), (6, 2), 3, Color::White), 8, 5);
+        assert_eq!(rows, [0x0000, 0xff00, 0xff00, 0xff00, 0x0000]);
+
+        let line = Line::new((-5, -3), (-1, 4), 1, Color::White);
+        assert_eq!(line.min_size(), (0, 5));
+    }
+
+    #[test]
+    fn test_polygon() {
+        // Concave "U" shape.
+        static POINTS: [(i32, i32); 8] = [
+            (0, 0),
+            (2, 0),
+            (2, 2),
+            (4, 2),
+            (4, 0),
+            (6, 0),
+            (6, 4),
+            (0, 4),
+        ];
+        let rows = render(
+            &mut Polygon::new(&POINTS, Style::Filled(Color::White)),
+            6,
+            4,
+        );
+        assert_eq!(rows, [0xcc00, 0xcc00, 0xfc00, 0xfc00]);
+    }
+}
@@ -0,0 +1,1033 @@
+//! Multi-line text.
+
+use super::font::{layout, Font, TextFont};
+use super::image::RowImage;
+use super::GUIElement;
+use crate::{BandRenderer, ClipRect, ClipRow, Color, RowRenderer};
+
+use core::cell::Cell;
+use core::cmp::{max, min};
+
+/// Horizontal alignment of the lines of a paragraph.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+    /// Stretches the spaces so that the lines fill the whole width. The last
+    /// line of every paragraph is aligned to the left.
+    Justify,
+}
+
+/// Line of wrapped text.
+struct Line {
+    /// Byte range of the line without trailing spaces.
+    start: usize,
+    end: usize,
+    width: i32,
+    /// Whether the line ends with a newline or the end of the text.
+    last: bool,
+}
+
+/// Iterator which breaks text into lines that fit into a given width.
+///
+/// Lines are broken at the last space before the text becomes too wide, or
+/// within a word if the word alone does not fit.
+struct Lines<'a, F: TextFont + ?Sized> {
+    font: &'a F,
+    text: &'a str,
+    width: i32,
+    pos: Option<usize>,
+}
+
+impl<'a, F: TextFont + ?Sized> Lines<'a, F> {
+    fn new(font: &'a F, text: &'a str, width: u32) -> Lines<'a, F> {
+        Self::starting_at(font, text, width, 0)
+    }
+
+    /// Continues wrapping at `start`, which has to be the start of a line.
+    fn starting_at(font: &'a F, text: &'a str, width: u32, start: usize) -> Lines<'a, F> {
+        Lines {
+            font,
+            text,
+            width: width as i32,
+            pos: Some(start),
+        }
+    }
+}
+
+impl<'a, F: TextFont + ?Sized> Iterator for Lines<'a, F> {
+    type Item = Line;
+
+    fn next(&mut self) -> Option<Line> {
+        let start = self.pos?;
+        let rest = &self.text[start..];
+        let scale = self.font.scale() as i32;
+        let mut pen = 0;
+        let mut previous: Option<(&Font, usize)> = None;
+        let mut space = None;
+        let mut line_break = None;
+        for (i, c) in rest.char_indices() {
+            if c == '\n' {
+                line_break = Some((i, i + 1, true));
+                break;
+            }
+            if c == ' ' {
+                space = Some((i, i + 1));
+            }
+            if let Some((font, index)) = self.font.glyph(c) {
+                if let Some((previous_font, previous_index)) = previous {
+                    if core::ptr::eq(previous_font, font) {
+                        pen += font.kerning(previous_index, index) * scale;
+                    }
+                }
+                previous = Some((font, index));
+                pen += font.glyphs[index].advance as i32 * scale;
+            }
+            // Spaces at the end of a line may extend beyond the width.
+            if c != ' ' && pen > self.width && i != 0 {
+                let (end, next) = space.unwrap_or((i, i));
+                // The next line starts with the following word, without
+                // any of the spaces.
+                let spaces = rest[next..].len() - rest[next..].trim_start_matches(' ').len();
+                line_break = Some((end, next + spaces, false));
+                break;
+            }
+        }
+        let (end, last) = match line_break {
+            Some((end, next, last)) => {
+                self.pos = Some(start + next);
+                (end, last)
+            }
+            None => {
+                self.pos = None;
+                (rest.len(), true)
+            }
+        };
+        let line = rest[..end].trim_end_matches(' ');
+        Some(Line {
+            start,
+            end: start + line.len(),
+            width: self.font.get_text_size(line).0 as i32,
+            last,
+        })
+    }
+}
+
+/// Text which is wrapped at word boundaries to fit the width of the element.
+///
+/// Newlines in the text start a new paragraph. The text can be any string
+/// type, e.g., `&'static str` or `heapless::String<N>`.
+///
+/// The height required for the text depends on the width of the element, so
+/// containers should assign the width and use `min_height_for_width()` to
+/// determine the height.
+pub struct Paragraph<T: AsRef<str>, F: TextFont + ?Sized + 'static = Font> {
+    text: T,
+    font: &'static F,
+    align: TextAlign,
+    line_spacing: i32,
+    width: u32,
+    height: u32,
+    /// Index and start of the most recently rendered line, so that rendering
+    /// the following rows does not wrap the text from the beginning again.
+    cursor: Cell<(usize, usize)>,
+}
+
+impl<T: AsRef<str>, F: TextFont + ?Sized> Paragraph<T, F> {
+    pub fn new(text: T, font: &'static F) -> Paragraph<T, F> {
+        Paragraph {
+            text,
+            font,
+            align: TextAlign::Left,
+            line_spacing: 0,
+            width: 0,
+            height: 0,
+            cursor: Cell::new((0, 0)),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        self.text.as_ref()
+    }
+
+    pub fn set_text(&mut self, text: T) {
+        self.text = text;
+        self.cursor.set((0, 0));
+    }
+
+    pub fn set_align(&mut self, align: TextAlign) {
+        self.align = align;
+    }
+
+    /// Sets the number of additional pixels between two lines. Negative
+    /// values move the lines closer together.
+    pub fn set_line_spacing(&mut self, line_spacing: i32) {
+        self.line_spacing = line_spacing;
+    }
+
+    fn font_height(&self) -> i32 {
+        (self.font.ascender() + self.font.descender()) as i32
+    }
+
+    fn line_height(&self) -> i32 {
+        max(self.font_height() + self.line_spacing, 1)
+    }
+
+    /// Returns the lines starting with line `index`.
+    fn lines_from(&self, index: usize) -> Lines<'_, F> {
+        let (mut current, start) = match self.cursor.get() {
+            (current, start) if current <= index => (current, start),
+            _ => (0, 0),
+        };
+        let mut lines = Lines::starting_at(self.font, self.text.as_ref(), self.width, start);
+        while current < index && lines.next().is_some() {
+            current += 1;
+        }
+        if let Some(start) = lines.pos {
+            self.cursor.set((current, start));
+        }
+        lines
+    }
+
+    /// Calls `draw` with the parts of a line and their horizontal positions.
+    fn draw_line<D>(&self, line: &Line, offset: i32, mut draw: D)
+    where
+        D: FnMut(&str, i32),
+    {
+        let text = &self.text.as_ref()[line.start..line.end];
+        let free = self.width as i32 - line.width;
+        match self.align {
+            TextAlign::Left => draw(text, offset),
+            TextAlign::Center => draw(text, offset + free / 2),
+            TextAlign::Right => draw(text, offset + free),
+            TextAlign::Justify => {
+                let gaps = text.matches(' ').count() as i32;
+                if line.last || gaps == 0 || free <= 0 {
+                    draw(text, offset);
+                    return;
+                }
+                let space = self.font.get_text_size(" ").0 as i32;
+                let mut pos = offset;
+                for (i, word) in text.split(' ').enumerate() {
+                    draw(word, pos);
+                    let i = i as i32;
+                    let extra = free * (i + 1) / gaps - free * i / gaps;
+                    pos += self.font.get_text_size(word).0 as i32 + space + extra;
+                }
+            }
+        }
+    }
+}
+
+impl<T: AsRef<str>, F: TextFont + ?Sized> GUIElement for Paragraph<T, F> {
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.cursor.set((0, 0));
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        (0, self.min_height_for_width(self.width))
+    }
+
+    fn min_height_for_width(&self, width: u32) -> u32 {
+        let lines = Lines::new(self.font, self.text.as_ref(), width).count() as i32;
+        max(lines * self.line_height() - self.line_spacing, 0) as u32
+    }
+
+    fn preferred_size(&self) -> (u32, u32) {
+        // Width without wrapping.
+        let width = Lines::new(self.font, self.text.as_ref(), i32::MAX as u32)
+            .map(|line| line.width)
+            .max()
+            .unwrap_or(0) as u32;
+        (width, self.min_height_for_width(width))
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        if y < 0 || y >= self.height as i32 {
+            return;
+        }
+        let line_y = y % self.line_height();
+        if line_y >= self.font_height() {
+            return;
+        }
+        let line = match self.lines_from((y / self.line_height()) as usize).next() {
+            Some(line) => line,
+            None => return,
+        };
+        let clip = clip.row();
+        self.draw_line(&line, offset, |text, x| {
+            self.font.render_row(row, &clip, text, line_y, x)
+        });
+    }
+
+    fn render_band(&self, band: &mut BandRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        let bottom = min(y + band.rows() as i32, self.height as i32);
+        let clip = clip.row();
+        // Skip the lines above the band.
+        let first_line = max(y, 0) / self.line_height();
+        let mut line_top = first_line * self.line_height();
+        for line in self.lines_from(first_line as usize) {
+            if line_top >= bottom {
+                break;
+            }
+            // Rows of the band which show the line.
+            let first = max(line_top, y);
+            let last = min(
+                line_top + min(self.font_height(), self.line_height()),
+                bottom,
+            );
+            if first < last {
+                let (_, mut rest) = band.split_at((first - y) as u32);
+                let (mut line_band, _) = rest.split_at((last - first) as u32);
+                self.draw_line(&line, offset, |text, x| {
+                    self.font
+                        .render_band(&mut line_band, &clip, text, first - line_top, x)
+                });
+            }
+            line_top += self.line_height();
+        }
+    }
+}
+
+/// Policy for single lines of text which are wider than the width assigned to
+/// the element by `GUIElement::resize()`.
+pub enum Overflow<F: TextFont + ?Sized + 'static = Font> {
+    /// The text extends beyond the element.
+    Visible,
+    /// The text is cut off at the right edge of the element.
+    Clip,
+    /// The end of the text is replaced with an ellipsis.
+    Ellipsis,
+    /// The middle of the text is replaced with an ellipsis, so that both the
+    /// beginning and the end remain visible.
+    EllipsisMiddle,
+    /// The text is rendered with a smaller font, and clipped if it still does
+    /// not fit. The baseline stays in place.
+    Shrink(&'static F),
+}
+
+/// Visible parts of a line after applying the overflow policy.
+#[derive(Copy, Clone)]
+enum Fit {
+    /// The whole text in the normal font.
+    Full,
+    /// The whole text in the font of `Overflow::Shrink`.
+    Shrunk,
+    /// The text up to `head` and from `tail` with an ellipsis in between.
+    Ellipsis { head: usize, tail: usize },
+}
+
+/// Returns the largest number below `count` for which `fits` returns true,
+/// assuming that it also returns true for all smaller numbers.
+fn max_fitting<P: Fn(usize) -> bool>(count: usize, fits: P) -> Option<usize> {
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if fits(middle) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    low.checked_sub(1)
+}
+
+/// Layout of a single line of text, shared by `Text` and `DynamicText`.
+pub(crate) struct TextLine<F: TextFont + ?Sized + 'static> {
+    font: &'static F,
+    overflow: Overflow<F>,
+    fit: Fit,
+    natural_width: u32,
+    max_width: Option<u32>,
+    width: u32,
+}
+
+impl<F: TextFont + ?Sized> TextLine<F> {
+    pub(crate) fn new(font: &'static F, text: &str) -> TextLine<F> {
+        let mut line = TextLine {
+            font,
+            overflow: Overflow::Visible,
+            fit: Fit::Full,
+            natural_width: 0,
+            max_width: None,
+            width: 0,
+        };
+        line.update(text);
+        line
+    }
+
+    pub(crate) fn set_overflow(&mut self, overflow: Overflow<F>, text: &str) {
+        self.overflow = overflow;
+        self.update(text);
+    }
+
+    pub(crate) fn resize(&mut self, width: u32, text: &str) {
+        self.max_width = Some(width);
+        self.update(text);
+    }
+
+    /// Measures the text again and applies the overflow policy.
+    pub(crate) fn update(&mut self, text: &str) {
+        self.natural_width = self.font.get_text_size(text).0;
+        self.fit = Fit::Full;
+        self.width = self.natural_width;
+        let max_width = match (&self.overflow, self.max_width) {
+            (Overflow::Visible, _) | (_, None) => return,
+            (_, Some(max_width)) => max_width,
+        };
+        if self.natural_width <= max_width {
+            return;
+        }
+        let font = self.font;
+        let width = |text: &str| font.get_text_size(text).0;
+        let ellipsis_width = width(self.ellipsis());
+        let count = text.chars().count();
+        let split = |i: usize| {
+            text.char_indices()
+                .nth(i)
+                .map(|(index, _)| index)
+                .unwrap_or(text.len())
+        };
+        match self.overflow {
+            Overflow::Visible | Overflow::Clip => {}
+            Overflow::Shrink(small_font) => {
+                self.fit = Fit::Shrunk;
+                self.width = small_font.get_text_size(text).0;
+            }
+            Overflow::Ellipsis => {
+                let head = |kept: usize| text[..split(kept)].trim_end_matches(' ');
+                let head = max_fitting(count, |kept| {
+                    width(head(kept)) + ellipsis_width <= max_width
+                })
+                .map_or("", head);
+                self.fit = Fit::Ellipsis {
+                    head: head.len(),
+                    tail: text.len(),
+                };
+                self.width = width(head) + ellipsis_width;
+            }
+            Overflow::EllipsisMiddle => {
+                // Remove characters from the middle until the text fits.
+                let parts = |kept: usize| (split(kept.div_ceil(2)), split(count - kept / 2));
+                let (head, tail) = max_fitting(count, |kept| {
+                    let (head, tail) = parts(kept);
+                    width(&text[..head]) + ellipsis_width + width(&text[tail..]) <= max_width
+                })
+                .map_or((0, text.len()), parts);
+                self.fit = Fit::Ellipsis { head, tail };
+                self.width = width(&text[..head]) + ellipsis_width + width(&text[tail..]);
+            }
+        }
+        self.width = min(self.width, max_width);
+    }
+
+    fn ellipsis(&self) -> &'static str {
+        if self.font.has_glyph('\u{2026}') {
+            "\u{2026}"
+        } else {
+            "..."
+        }
+    }
+
+    pub(crate) fn min_size(&self) -> (u32, u32) {
+        match self.overflow {
+            Overflow::Visible => self.preferred_size(),
+            _ => (0, self.height()),
+        }
+    }
+
+    pub(crate) fn preferred_size(&self) -> (u32, u32) {
+        (self.natural_width, self.height())
+    }
+
+    pub(crate) fn size(&self) -> (u32, u32) {
+        (self.width, self.height())
+    }
+
+    fn height(&self) -> u32 {
+        (self.font.ascender() + self.font.descender()) as u32
+    }
+
+    /// Calls `draw` with the font, the vertical offset of the font, and the
+    /// parts of the text with their horizontal positions.
+    fn draw<D>(&self, text: &str, clip: &ClipRect, offset: i32, mut draw: D)
+    where
+        D: FnMut(&F, &ClipRow, i32, &str, i32),
+    {
+        let clip = match self.overflow {
+            Overflow::Visible => clip.row(),
+            _ => clip.row().clip(offset, offset + self.width as i32),
+        };
+        match (self.fit, &self.overflow) {
+            (Fit::Shrunk, Overflow::Shrink(font)) => {
+                let y_offset = self.font.ascender() as i32 - font.ascender() as i32;
+                draw(font, &clip, y_offset, text, offset);
+            }
+            (Fit::Ellipsis { head, tail }, _) => {
+                let (head, tail) = (&text[..head], &text[tail..]);
+                let ellipsis = self.ellipsis();
+                let head_width = self.font.get_text_size(head).0 as i32;
+                let ellipsis_width = self.font.get_text_size(ellipsis).0 as i32;
+                draw(self.font, &clip, 0, head, offset);
+                draw(self.font, &clip, 0, ellipsis, offset + head_width);
+                draw(
+                    self.font,
+                    &clip,
+                    0,
+                    tail,
+                    offset + head_width + ellipsis_width,
+                );
+            }
+            _ => draw(self.font, &clip, 0, text, offset),
+        }
+    }
+
+    pub(crate) fn render_row(
+        &self,
+        text: &str,
+        row: &mut RowRenderer,
+        clip: &ClipRect,
+        y: i32,
+        offset: i32,
+    ) {
+        self.draw(text, clip, offset, |font, clip, y_offset, text, x| {
+            font.render_row(row, clip, text, y - y_offset, x)
+        });
+    }
+
+    pub(crate) fn render_band(
+        &self,
+        text: &str,
+        band: &mut BandRenderer,
+        clip: &ClipRect,
+        y: i32,
+        offset: i32,
+    ) {
+        self.draw(text, clip, offset, |font, clip, y_offset, text, x| {
+            font.render_band(band, clip, text, y - y_offset, x)
+        });
+    }
+}
+
+/// Direction of `RotatedText`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextRotation {
+    /// Rotated clockwise, so that the text reads from top to bottom.
+    Rotate90,
+    /// Rotated counterclockwise, so that the text reads from bottom to top.
+    Rotate270,
+}
+
+/// Single line of text rotated by 90 or 270 degrees, e.g., for the labels of
+/// vertical axes.
+///
+/// Every rendered row corresponds to one column of the unrotated text, which
+/// is sampled from the glyph images.
+pub struct RotatedText<F: TextFont + ?Sized + 'static = Font> {
+    text: &'static str,
+    font: &'static F,
+    rotation: TextRotation,
+    length: u32,
+}
+
+impl<F: TextFont + ?Sized> RotatedText<F> {
+    pub fn new(text: &'static str, font: &'static F, rotation: TextRotation) -> RotatedText<F> {
+        RotatedText {
+            text,
+            font,
+            rotation,
+            length: font.get_text_size(text).0,
+        }
+    }
+}
+
+impl<F: TextFont + ?Sized> GUIElement for RotatedText<F> {
+    fn resize(&mut self, _width: u32, _height: u32) {
+        // Ignore, as the font dictates the size of the text.
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        (
+            (self.font.ascender() + self.font.descender()) as u32,
+            self.length,
+        )
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.min_size()
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        if y < 0 || y >= self.length as i32 {
+            return;
+        }
+        let clip = clip.row();
+        let height = (self.font.ascender() + self.font.descender()) as i32;
+        // Column of the unrotated text.
+        let x = match self.rotation {
+            TextRotation::Rotate90 => y,
+            TextRotation::Rotate270 => self.length as i32 - 1 - y,
+        };
+        let scale = self.font.scale();
+        for (_, glyph, pos) in layout(self.font, self.text, 0) {
+            let image_x = x - pos - glyph.image_left as i32 * scale as i32;
+            let image_y = self.font.ascender() as i32 - glyph.image_top as i32 * scale as i32;
+            match self.rotation {
+                TextRotation::Rotate90 => glyph.image.render_column_transparent(
+                    row,
+                    &clip,
+                    image_x,
+                    offset + height - 1 - image_y,
+                    true,
+                    scale,
+                ),
+                TextRotation::Rotate270 => glyph.image.render_column_transparent(
+                    row,
+                    &clip,
+                    image_x,
+                    offset + image_y,
+                    false,
+                    scale,
+                ),
+            }
+        }
+    }
+}
+
+/// Line drawn over the glyphs of a `TextRun`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextDecoration {
+    None,
+    /// Line below the baseline.
+    Underline,
+    /// Line through the middle of the lowercase letters.
+    Strikethrough,
+}
+
+/// Part of a `RichText` with its own font and style.
+#[derive(Copy, Clone)]
+pub struct TextRun {
+    pub text: &'static str,
+    pub font: &'static (dyn TextFont + Sync),
+    /// Color of the glyphs or, if the run is inverted, of the background.
+    pub color: Color,
+    /// Draws white glyphs on a background of `color`, e.g., to highlight
+    /// keywords.
+    pub inverted: bool,
+    pub decoration: TextDecoration,
+}
+
+impl TextRun {
+    /// Creates a run with black glyphs and no decoration.
+    pub const fn new(text: &'static str, font: &'static (dyn TextFont + Sync)) -> TextRun {
+        TextRun {
+            text,
+            font,
+            color: Color::Black,
+            inverted: false,
+            decoration: TextDecoration::None,
+        }
+    }
+
+    /// Sets the color of the glyphs, e.g., `Color::Red` on black/white/red
+    /// displays or `Color::Gray(1)` on grayscale displays.
+    pub const fn color(mut self, color: Color) -> TextRun {
+        self.color = color;
+        self
+    }
+
+    pub const fn inverted(mut self) -> TextRun {
+        self.inverted = true;
+        self
+    }
+
+    pub const fn underline(mut self) -> TextRun {
+        self.decoration = TextDecoration::Underline;
+        self
+    }
+
+    pub const fn strikethrough(mut self) -> TextRun {
+        self.decoration = TextDecoration::Strikethrough;
+        self
+    }
+
+    /// Returns the rows covered by the decoration line relative to the
+    /// baseline. Underlines are moved up to fit into the `descender` of the
+    /// line.
+    fn decoration_rows(&self, descender: u16) -> Option<(i32, i32)> {
+        let thickness = self.font.scale() as i32;
+        let top = match self.decoration {
+            TextDecoration::None => return None,
+            TextDecoration::Underline => min(thickness, descender as i32 - thickness),
+            TextDecoration::Strikethrough => -(self.font.ascender() as i32 + 2) / 3,
+        };
+        Some((top, top + thickness))
+    }
+}
+
+/// Single line of text composed of runs with different fonts and styles, e.g.,
+/// a heading with a bold price.
+///
+/// All runs share a common baseline which is placed below the largest
+/// ascender of the fonts. Kerning is not applied across runs.
+///
+/// ```ignore
+/// static RUNS: [TextRun; 2] = [
+///     TextRun::new("Price: ", &SANS),
+///     TextRun::new("9.99", &BOLD).color(Color::Red).underline(),
+/// ];
+/// let text = RichText::new(&RUNS);
+/// ```
+pub struct RichText<'a> {
+    runs: &'a [TextRun],
+    ascender: u16,
+    descender: u16,
+    width: u32,
+}
+
+impl<'a> RichText<'a> {
+    pub fn new(runs: &'a [TextRun]) -> RichText<'a> {
+        let mut text = RichText {
+            runs,
+            ascender: 0,
+            descender: 0,
+            width: 0,
+        };
+        text.set_runs(runs);
+        text
+    }
+
+    pub fn runs(&self) -> &'a [TextRun] {
+        self.runs
+    }
+
+    pub fn set_runs(&mut self, runs: &'a [TextRun]) {
+        self.runs = runs;
+        self.ascender = runs
+            .iter()
+            .map(|run| run.font.ascender())
+            .max()
+            .unwrap_or(0);
+        self.descender = runs
+            .iter()
+            .map(|run| run.font.descender())
+            .max()
+            .unwrap_or(0);
+        self.width = runs
+            .iter()
+            .map(|run| run.font.get_text_size(run.text).0)
+            .sum();
+    }
+}
+
+impl<'a> GUIElement for RichText<'a> {
+    fn resize(&mut self, _width: u32, _height: u32) {
+        // Ignore, as the fonts dictate the size of the text.
+    }
+
+    fn min_size(&self) -> (u32, u32) {
+        (self.width, (self.ascender + self.descender) as u32)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.min_size()
+    }
+
+    fn render_row(&self, row: &mut RowRenderer, clip: &ClipRect, y: i32, offset: i32) {
+        if y < 0 || y >= (self.ascender + self.descender) as i32 {
+            return;
+        }
+        let clip = clip.row();
+        let baseline_y = y - self.ascender as i32;
+        let mut x = offset;
+        for run in self.runs {
+            let width = run.font.get_text_size(run.text).0 as i32;
+            let color = if run.inverted {
+                row.fill(&clip, x, x + width, run.color);
+                Color::White
+            } else {
+                run.color
+            };
+            let run_y = baseline_y + run.font.ascender() as i32;
+            run.font
+                .render_row_color(row, &clip, run.text, run_y, x, color);
+            if let Some((top, bottom)) = run.decoration_rows(self.descender) {
+                if baseline_y >= top && baseline_y < bottom {
+                    row.fill(&clip, x, x + width, color);
+                }
+            }
+            x += width;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Fit, Overflow, Paragraph, RichText, RotatedText, TextAlign, TextLine, TextRotation, TextRun,
+    };
+    use crate::gui::font::{Font, Glyph, GlyphImage, ScaledFont};
+    use crate::gui::image::RLEImage;
+    use crate::gui::{Align, GUIElement, HorizontalAlign, Layout, Text, VerticalAlign};
+    use crate::{ClipRect, Display, RowRenderer, TestDisplay};
+
+    static GLYPHS: [Glyph; 3] = [
+        Glyph {
+            image: GlyphImage::Rle(RLEImage {
+                data: &[3, 4, 5, 0x8002, 0x8002],
+                width: 2,
+                height: 2,
+            }),
+            image_left: 0,
+            image_top: 2,
+            advance: 3,
+        },
+        Glyph {
+            image: GlyphImage::Rle(RLEImage {
+                data: &[0],
+                width: 0,
+                height: 0,
+            }),
+            image_left: 0,
+            image_top: 0,
+            advance: 3,
+        },
+        Glyph {
+            image: GlyphImage::Rle(RLEImage {
+                data: &[3, 4, 5, 0x8001, 0x8002],
+                width: 2,
+                height: 2,
+            }),
+            image_left: 0,
+            image_top: 2,
+            advance: 3,
+        },
+    ];
+
+    /// Monospace font with blocks for all characters except "L".
+    static FONT: Font = Font {
+        ascender: 2,
+        descender: 0,
+        glyphs: &GLYPHS,
+        get_glyph_index: |c| match c {
+            ' ' => Some(1),
+            'L' => Some(2),
+            _ => Some(0),
+        },
+        kerning: &[],
+        replacement: None,
+    };
+
+    static SMALL_GLYPHS: [Glyph; 1] = [Glyph {
+        image: GlyphImage::Rle(RLEImage {
+            data: &[2, 3, 0x8001],
+            width: 1,
+            height: 1,
+        }),
+        image_left: 0,
+        image_top: 1,
+        advance: 1,
+    }];
+
+    static SMALL_FONT: Font = Font {
+        ascender: 1,
+        descender: 0,
+        glyphs: &SMALL_GLYPHS,
+        get_glyph_index: |_| Some(0),
+        kerning: &[],
+        replacement: None,
+    };
+
+    fn lines(text: &'static str, width: u32) -> std::vec::Vec<&'static str> {
+        let mut paragraph = Paragraph::new(text, &FONT);
+        paragraph.resize(width, 0);
+        paragraph
+            .lines_from(0)
+            .map(|line| &text[line.start..line.end])
+            .collect()
+    }
+
+    fn render_row(paragraph: &Paragraph<&str>, y: i32) -> [u8; 3] {
+        let mut buffer = [0xffu8; 3];
+        let mut row = RowRenderer::new(&mut buffer, 24);
+        paragraph.render_row(&mut row, &ClipRect::new(0, y, 24, y + 1), y, 0);
+        row.finish();
+        buffer
+    }
+
+    #[test]
+    fn test_wrapping() {
+        assert_eq!(lines("aa bb cc", 15), ["aa bb", "cc"]);
+        assert_eq!(lines("aa  bb", 9), ["aa", "bb"]);
+        assert_eq!(lines("aa     bb", 9), ["aa", "bb"]);
+        assert_eq!(lines("aaaaaa", 10), ["aaa", "aaa"]);
+        assert_eq!(lines("a\n\nb c", 100), ["a", "", "b c"]);
+
+        let mut paragraph = Paragraph::new("aa bb cc", &FONT);
+        paragraph.set_line_spacing(1);
+        assert_eq!(paragraph.min_height_for_width(15), 5);
+        assert_eq!(paragraph.min_height_for_width(100), 2);
+    }
+
+    #[test]
+    fn test_alignment() {
+        let mut paragraph = Paragraph::new("a a aa a a", &FONT);
+        paragraph.resize(20, 5);
+        paragraph.set_align(TextAlign::Justify);
+        assert_eq!(render_row(&paragraph, 0), [0x3e, 0x7c, 0x9f]);
+        // The last line is not justified.
+        assert_eq!(render_row(&paragraph, 2), [0x3c, 0xff, 0xff]);
+        paragraph.set_align(TextAlign::Center);
+        assert_eq!(render_row(&paragraph, 0), [0x9e, 0x79, 0x3f]);
+        paragraph.set_align(TextAlign::Right);
+        assert_eq!(render_row(&paragraph, 3), [0xff, 0xe7, 0x9f]);
+
+        // Rows can be rendered in any order.
+        paragraph.set_align(TextAlign::Left);
+        let rows: std::vec::Vec<_> = (0..5).map(|y| render_row(&paragraph, y)).collect();
+        for &y in [4, 0, 3, 3, 1, 2].iter() {
+            assert_eq!(render_row(&paragraph, y), rows[y as usize]);
+        }
+        paragraph.set_text("a");
+        assert_eq!(render_row(&paragraph, 2), [0xff; 3]);
+    }
+
+    #[test]
+    fn test_bands() {
+        let mut paragraph = Paragraph::new("aa bb cc dd ee ff gg hh ii jj kk ll", &FONT);
+        paragraph.set_align(TextAlign::Justify);
+        paragraph.set_line_spacing(3);
+        let layout = Layout::new(TestDisplay::WIDTH, TestDisplay::HEIGHT, paragraph);
+        let mut display = TestDisplay::new();
+        layout.render(&mut display, &mut [0u8; 40]);
+        let expected = display.frame;
+        assert!(expected.iter().any(|&b| b != 0xff));
+        for rows in [3, 16].iter() {
+            let mut display = TestDisplay::new();
+            let mut buffer = std::vec![0u8; 40 * rows];
+            layout.render(&mut display, &mut buffer);
+            assert!(display.frame == expected);
+        }
+    }
+
+    #[test]
+    fn test_overflow() {
+        let text = "abcdefgh";
+        let fit = |overflow| {
+            let mut line = TextLine::new(&FONT, text);
+            line.set_overflow(overflow, text);
+            line.resize(14, text);
+            (line.fit, line.size().0)
+        };
+        assert!(matches!(fit(Overflow::Visible), (Fit::Full, 24)));
+        assert!(matches!(fit(Overflow::Clip), (Fit::Full, 14)));
+        assert!(matches!(
+            fit(Overflow::Ellipsis),
+            (Fit::Ellipsis { head: 3, tail: 8 }, 12)
+        ));
+        assert!(matches!(
+            fit(Overflow::EllipsisMiddle),
+            (Fit::Ellipsis { head: 2, tail: 7 }, 12)
+        ));
+        assert!(matches!(
+            fit(Overflow::Shrink(&SMALL_FONT)),
+            (Fit::Shrunk, 8)
+        ));
+        // Only the ellipsis remains if no character fits.
+        let mut line = TextLine::new(&FONT, text);
+        line.set_overflow(Overflow::EllipsisMiddle, text);
+        line.resize(4, text);
+        assert!(matches!(line.fit, Fit::Ellipsis { head: 0, tail: 8 }));
+
+        // The smaller font shares the baseline.
+        let mut shrunk = Text::new(text, &FONT);
+        shrunk.set_overflow(Overflow::Shrink(&SMALL_FONT));
+        shrunk.resize(14, 2);
+        for (y, expected) in [[0xff, 0xff], [0x00, 0xff]].iter().enumerate() {
+            let mut buffer = [0xffu8; 2];
+            let mut row = RowRenderer::new(&mut buffer, 16);
+            let y = y as i32;
+            shrunk.render_row(&mut row, &ClipRect::new(0, y, 16, y + 1), y, 0);
+            row.finish();
+            assert_eq!(buffer, *expected);
+        }
+
+        // Aligned text is truncated to the available width.
+        let mut text = Text::new(text, &FONT);
+        text.set_overflow(Overflow::Ellipsis);
+        let mut align = Align::new(HorizontalAlign::Center, VerticalAlign::Center, text);
+        align.resize(14, 10);
+        assert_eq!(align.element.size(), (12, 2));
+        align.resize(100, 10);
+        assert_eq!(align.element.size(), (24, 2));
+
+        let mut paragraph = Paragraph::new("aa bb cc", &FONT);
+        paragraph.set_line_spacing(1);
+        let mut align = Align::new(HorizontalAlign::Center, VerticalAlign::Center, paragraph);
+        align.resize(15, 10);
+        assert_eq!(align.element.size(), (15, 5));
+        align.resize(100, 10);
+        assert_eq!(align.element.size(), (24, 2));
+    }
+
+    #[test]
+    fn test_rotated_text() {
+        let render = |text: &RotatedText, y: i32| {
+            let mut buffer = [0xffu8; 2];
+            let mut row = RowRenderer::new(&mut buffer, 16);
+            text.render_row(&mut row, &ClipRect::new(0, y, 16, y + 1), y, 4);
+            row.finish();
+            buffer[0]
+        };
+        let text = RotatedText::new("L", &FONT, TextRotation::Rotate90);
+        assert_eq!(text.size(), (2, 3));
+        let rows: std::vec::Vec<u8> = (0..3).map(|y| render(&text, y)).collect();
+        assert_eq!(rows, [0xf3, 0xf7, 0xff]);
+        let text = RotatedText::new("L", &FONT, TextRotation::Rotate270);
+        let rows: std::vec::Vec<u8> = (0..3).map(|y| render(&text, y)).collect();
+        assert_eq!(rows, [0xff, 0xfb, 0xf3]);
+
+        static SCALED: ScaledFont = ScaledFont::new(&FONT, 2);
+        let text = RotatedText::new("L", &SCALED, TextRotation::Rotate90);
+        assert_eq!(text.size(), (4, 6));
+        let rows: std::vec::Vec<u8> = (0..6)
+            .map(|y| {
+                let mut buffer = [0xffu8; 2];
+                let mut row = RowRenderer::new(&mut buffer, 16);
+                text.render_row(&mut row, &ClipRect::new(0, y, 16, y + 1), y, 4);
+                row.finish();
+                buffer[0]
+            })
+            .collect();
+        assert_eq!(rows, [0xf0, 0xf0, 0xf3, 0xf3, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_rich_text() {
+        static RUNS: [TextRun; 3] = [
+            TextRun::new("A", &FONT),
+            TextRun::new("x", &SMALL_FONT).inverted(),
+            TextRun::new("A", &FONT).underline(),
+        ];
+        let text = RichText::new(&RUNS);
+        assert_eq!(text.size(), (7, 2));
+        let rows: std::vec::Vec<u8> = (0..2)
+            .map(|y| {
+                let mut buffer = [0xffu8; 1];
+                let mut row = RowRenderer::new(&mut buffer, 8);
+                text.render_row(&mut row, &ClipRect::new(0, y, 8, y + 1), y, 0);
+                row.finish();
+                buffer[0]
+            })
+            .collect();
+        // The small run shares the baseline, and its glyph is white on black.
+        assert_eq!(rows, [0x23, 0x31]);
+    }
+}
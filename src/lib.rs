@@ -8,8 +8,10 @@ extern crate std;
 #[cfg(test)]
 use std::vec::Vec;
 
+pub mod framebuffer;
 pub mod gdew042z15;
 pub mod gui;
+pub mod storage;
 
 use core::cmp::max;
 use core::cmp::min;
@@ -24,6 +26,100 @@ pub struct Hertz(pub u32);
 pub enum Color {
     White,
     Black,
+    /// Gray level from 0 (black) to 3 (white). On displays without grayscale
+    /// support, levels 0 and 1 are black and levels 2 and 3 are white.
+    Gray(u8),
+    /// Red on black/white/red displays (see `RedDisplay`), black on all other
+    /// displays.
+    Red,
+}
+
+/// Bit plane of a frame for displays with red pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RedPlane {
+    /// Black and white pixels. Red pixels are white in this plane.
+    BlackWhite,
+    /// Red pixels, which are stored as cleared bits. All other pixels are set.
+    Red,
+}
+
+/// Order of the pixels within the bytes of a bitmap.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BitOrder {
+    /// The leftmost pixel is stored in the most significant bit, as used by
+    /// the displays and by most image formats (e.g., PBM and BMP).
+    MsbFirst,
+    /// The leftmost pixel is stored in the least significant bit (e.g., XBM).
+    LsbFirst,
+}
+
+/// Meaning of set bits in a bitmap.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Polarity {
+    OneIsWhite,
+    OneIsBlack,
+}
+
+/// Mirroring of the image on the display.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Mirror {
+    None,
+    Horizontal,
+    Vertical,
+    Both,
+}
+
+impl Mirror {
+    fn is_horizontal(self) -> bool {
+        match self {
+            Mirror::Horizontal | Mirror::Both => true,
+            Mirror::None | Mirror::Vertical => false,
+        }
+    }
+
+    fn is_vertical(self) -> bool {
+        match self {
+            Mirror::Vertical | Mirror::Both => true,
+            Mirror::None | Mirror::Horizontal => false,
+        }
+    }
+}
+
+/// 8x8 pattern for pattern fills. Each byte is one row, with the most
+/// significant bit being the leftmost pixel. Set bits are drawn, cleared bits
+/// are left unchanged.
+///
+/// Patterns are aligned to absolute coordinates, so adjacent areas filled with
+/// the same pattern line up seamlessly.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Pattern(pub [u8; 8]);
+
+impl Pattern {
+    pub const CHECKERBOARD: Pattern = Pattern([0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55]);
+    /// Every fourth pixel of every other row.
+    pub const SPARSE: Pattern = Pattern([0x88, 0x00, 0x22, 0x00, 0x88, 0x00, 0x22, 0x00]);
+    pub const HORIZONTAL_HATCH: Pattern = Pattern([0xff, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00]);
+    pub const VERTICAL_HATCH: Pattern = Pattern([0x88; 8]);
+    /// Lines from the bottom left to the top right.
+    pub const DIAGONAL_HATCH: Pattern = Pattern([0x11, 0x22, 0x44, 0x88, 0x11, 0x22, 0x44, 0x88]);
+    /// Lines from the top left to the bottom right.
+    pub const ANTIDIAGONAL_HATCH: Pattern =
+        Pattern([0x88, 0x44, 0x22, 0x11, 0x88, 0x44, 0x22, 0x11]);
+    pub const CROSS_HATCH: Pattern = Pattern([0xff, 0x88, 0x88, 0x88, 0xff, 0x88, 0x88, 0x88]);
+
+    /// Returns the pattern bits for the absolute row `y`.
+    fn row(&self, y: i32) -> u8 {
+        self.0[(y & 7) as usize]
+    }
+
+    /// Returns the pattern with set and cleared bits swapped.
+    pub fn inverted(&self) -> Pattern {
+        let mut rows = self.0;
+        for row in rows.iter_mut() {
+            *row = !*row;
+        }
+        Pattern(rows)
+    }
 }
 
 pub trait Display {
@@ -44,6 +140,32 @@ pub trait Display {
     }
 }
 
+/// Displays which can show four gray levels.
+///
+/// A grayscale frame is transferred as two bit planes, and all rows are sent
+/// once for each plane. The rows contain two bits per pixel as rendered by
+/// `RowRenderer::new_gray()`.
+pub trait GrayscaleDisplay: Display {
+    fn start_gray_frame(&mut self) -> nb::Result<(), Error>;
+    /// Called after all rows have been sent for the first bit plane.
+    fn next_gray_plane(&mut self);
+    fn end_gray_frame(&mut self);
+
+    fn draw_gray_row(&mut self, row: &[u8]);
+}
+
+/// Displays which can show red pixels in addition to black and white.
+///
+/// A frame is transferred as a black/white plane followed by a red plane, and
+/// all rows are sent once for each plane with `Display::draw_row()`. The rows
+/// are rendered by `RowRenderer::new_red()`.
+pub trait RedDisplay: Display {
+    fn start_red_frame(&mut self) -> nb::Result<(), Error>;
+    /// Called after all rows have been sent for the black/white plane.
+    fn next_red_plane(&mut self);
+    fn end_red_frame(&mut self);
+}
+
 pub trait PartialRefresh {
     fn start_partial(
         &mut self,
@@ -57,9 +179,138 @@ pub trait PartialRefresh {
     fn draw_partial_row(&mut self, row: &[u8]);
 }
 
+/// Dithering algorithm used to render grayscale content.
+pub enum Dither<'a> {
+    /// Pixels darker than 50% gray are black.
+    Threshold,
+    /// Ordered dithering with a 4x4 Bayer matrix aligned to the display.
+    Bayer,
+    /// Floyd-Steinberg error diffusion. The error buffer needs two rows.
+    FloydSteinberg(ErrorBuffer<'a>),
+    /// Atkinson error diffusion, which only diffuses 3/4 of the error and
+    /// therefore produces higher contrast. The error buffer needs three rows.
+    Atkinson(ErrorBuffer<'a>),
+}
+
+const BAYER_MATRIX: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Quantization error which error diffusion carries from one row to the
+/// following rows.
+///
+/// The error is only carried over if the rows are rendered in order from top
+/// to bottom, which is not the case if the layout is flipped vertically.
+/// Whenever rows are skipped or rendered out of order, the error is reset.
+pub struct ErrorBuffer<'a> {
+    errors: &'a mut [i16],
+    width: usize,
+    rows: usize,
+    current_y: Option<i32>,
+}
+
+impl<'a> ErrorBuffer<'a> {
+    /// Creates an error buffer for content with a width of up to `width`
+    /// pixels. The size of the buffer determines the number of rows.
+    ///
+    /// Panics if `width` is zero.
+    pub fn new(errors: &'a mut [i16], width: u32) -> ErrorBuffer<'a> {
+        assert!(width > 0, "error buffer width must not be zero");
+        let width = width as usize;
+        ErrorBuffer {
+            rows: errors.len() / width,
+            errors,
+            width,
+            current_y: None,
+        }
+    }
+
+    fn start_row(&mut self, y: i32, width: usize, rows: usize) {
+        assert!(
+            width <= self.width && rows <= self.rows,
+            "error buffer too small"
+        );
+        match self.current_y {
+            // Another span of the same row.
+            Some(current_y) if current_y == y => {}
+            // The next row, the error of the previous row is not needed
+            // anymore.
+            Some(current_y) if current_y + 1 == y => {
+                let start = self.index(current_y, 0);
+                for error in self.errors[start..start + self.width].iter_mut() {
+                    *error = 0;
+                }
+            }
+            _ => {
+                for error in self.errors.iter_mut() {
+                    *error = 0;
+                }
+            }
+        }
+        self.current_y = Some(y);
+    }
+
+    fn index(&self, y: i32, x: usize) -> usize {
+        y.rem_euclid(self.rows as i32) as usize * self.width + x
+    }
+
+    fn get(&self, y: i32, x: usize) -> i16 {
+        self.errors[self.index(y, x)]
+    }
+
+    fn add(&mut self, y: i32, x: i32, width: usize, error: i16) {
+        if x >= 0 && (x as usize) < width {
+            let index = self.index(y, x as usize);
+            self.errors[index] += error;
+        }
+    }
+}
+
+/// Returns the value of a pixel with one or two bits per pixel.
+fn pixel_level(buffer: &[u8], x: usize, gray: bool) -> u8 {
+    if gray {
+        (buffer[x / 4] >> (6 - 2 * (x & 3))) & 3
+    } else {
+        (buffer[x / 8] >> (7 - (x & 7))) & 1
+    }
+}
+
+fn set_pixel_level(buffer: &mut [u8], x: usize, gray: bool, level: u8) {
+    if gray {
+        let shift = 6 - 2 * (x & 3);
+        buffer[x / 4] = (buffer[x / 4] & !(3 << shift)) | (level << shift);
+    } else {
+        let mask = 0x80 >> (x & 7);
+        if level != 0 {
+            buffer[x / 8] |= mask;
+        } else {
+            buffer[x / 8] &= !mask;
+        }
+    }
+}
+
+/// Returns the indices of the bytes of a one bit per pixel row covered by the
+/// span from `left` to `right` together with the masks of the covered bits.
+fn byte_masks(left: i32, right: i32) -> impl Iterator<Item = (usize, u8)> {
+    let (left_index, right_index) = ((left >> 3) as usize, ((right - 1) >> 3) as usize);
+    let left_mask = 0xffu8 >> (left & 7);
+    let right_mask = (0xff00u16 >> (((right - 1) & 7) + 1)) as u8;
+    (left_index..=right_index).map(move |index| {
+        let mut mask = 0xff;
+        if index == left_index {
+            mask &= left_mask;
+        }
+        if index == right_index {
+            mask &= right_mask;
+        }
+        (index, mask)
+    })
+}
+
 pub struct RowRenderer<'a> {
     buffer: &'a mut [u8],
     width: u32,
+    y: i32,
+    gray: bool,
+    red: Option<RedPlane>,
 }
 
 impl<'a> RowRenderer<'a> {
@@ -68,9 +319,60 @@ impl<'a> RowRenderer<'a> {
         RowRenderer {
             buffer: buffer,
             width: width,
+            y: 0,
+            gray: false,
+            red: None,
         }
     }
 
+    /// Creates a renderer for one of the planes of a black/white/red display.
+    /// The row has one bit per pixel like for `new()`.
+    ///
+    /// In the red plane, only `Color::Red` is drawn as a cleared bit, and all
+    /// other colors, images and grayscale content are drawn as set bits.
+    /// Inverting does not change the red plane.
+    pub fn new_red(buffer: &'a mut [u8], width: u32, plane: RedPlane) -> RowRenderer<'a> {
+        let mut renderer = Self::new(buffer, width);
+        renderer.red = Some(plane);
+        renderer
+    }
+
+    /// Creates a renderer for a row with two bits per pixel (four gray
+    /// levels). The leftmost pixel is stored in the most significant bits.
+    pub fn new_gray(buffer: &'a mut [u8], width: u32) -> RowRenderer<'a> {
+        assert!(buffer.len() * 4 >= width as usize);
+        RowRenderer {
+            buffer,
+            width,
+            y: 0,
+            gray: true,
+            red: None,
+        }
+    }
+
+    pub fn is_gray(&self) -> bool {
+        self.gray
+    }
+
+    /// Returns the plane which is rendered for black/white/red displays.
+    pub fn red_plane(&self) -> Option<RedPlane> {
+        self.red
+    }
+
+    fn is_red_plane(&self) -> bool {
+        self.red == Some(RedPlane::Red)
+    }
+
+    /// Sets the absolute row which is rendered. The row is used to align
+    /// patterns to the display.
+    pub fn set_y(&mut self, y: i32) {
+        self.y = y;
+    }
+
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+
     pub fn finish(self) {
         // Destroy the renderer and release the buffer.
     }
@@ -81,6 +383,13 @@ impl<'a> RowRenderer<'a> {
             return;
         }
         let (left, right) = line_clip.get();
+        if self.gray {
+            let level = self.color_level(color);
+            for x in left..right {
+                self.set_level(x, level);
+            }
+            return;
+        }
         let (left_index, right_index) = ((left >> 3) as usize, (right >> 3) as usize);
         let (left_offset, right_offset) = (left & 7, right & 7);
         let left_mask = 0xffu8 >> left_offset;
@@ -89,14 +398,14 @@ impl<'a> RowRenderer<'a> {
         if left_index == right_index {
             // Both ends are in the same byte.
             let mask = left_mask & right_mask;
-            if let Color::White = color {
+            if self.color_level(color) != 0 {
                 self.buffer[left_index] |= mask;
             } else {
                 self.buffer[left_index] &= !mask;
             }
         } else {
             // We cross byte boundaries.
-            if let Color::White = color {
+            if self.color_level(color) != 0 {
                 self.buffer[left_index] |= left_mask;
                 for i in (left_index + 1)..right_index {
                     self.buffer[i] = 0xff;
@@ -116,23 +425,473 @@ impl<'a> RowRenderer<'a> {
         }
     }
 
-    pub fn render_bitmap(&mut self, clip: &ClipRow, left: i32, right: i32, bits: &[u8]) {
+    /// Fills the set pixels of the pattern with the specified color. The other
+    /// pixels are left unchanged, so opaque patterns are drawn by filling the
+    /// area with the background color first.
+    pub fn fill_pattern(
+        &mut self,
+        clip: &ClipRow,
+        left: i32,
+        right: i32,
+        pattern: &Pattern,
+        color: Color,
+    ) {
+        let line_clip = clip.clip(left, right);
+        if line_clip.is_empty() {
+            return;
+        }
+        let bits = pattern.row(self.y);
+        let level = self.color_level(color);
+        if self.gray {
+            for x in line_clip.get().0..line_clip.get().1 {
+                if bits & (0x80 >> (x & 7)) != 0 {
+                    self.set_level(x, level);
+                }
+            }
+            return;
+        }
+        let (left, right) = line_clip.get();
+        for (index, mask) in byte_masks(left, right) {
+            if level != 0 {
+                self.buffer[index] |= bits & mask;
+            } else {
+                self.buffer[index] &= !(bits & mask);
+            }
+        }
+    }
+
+    /// Inverts all pixels between `left` and `right`. Gray levels are mirrored,
+    /// i.e., level 1 becomes level 2.
+    pub fn invert(&mut self, clip: &ClipRow, left: i32, right: i32) {
+        self.invert_pattern(clip, left, right, &Pattern([0xff; 8]));
+    }
+
+    /// Inverts the pixels at the set bits of the pattern (XOR drawing).
+    pub fn invert_pattern(&mut self, clip: &ClipRow, left: i32, right: i32, pattern: &Pattern) {
+        let line_clip = clip.clip(left, right);
+        if line_clip.is_empty() || self.is_red_plane() {
+            return;
+        }
+        let bits = pattern.row(self.y);
+        let (left, right) = line_clip.get();
+        if self.gray {
+            for x in left..right {
+                if bits & (0x80 >> (x & 7)) != 0 {
+                    let level = pixel_level(self.buffer, x as usize, true);
+                    self.set_level(x, 3 - level);
+                }
+            }
+            return;
+        }
+        for (index, mask) in byte_masks(left, right) {
+            self.buffer[index] ^= bits & mask;
+        }
+    }
+
+    /// Renders a row of a bitmap with one bit per pixel, with `bits[0]`
+    /// containing the pixel at `left`.
+    ///
+    /// If `left` is a multiple of eight, whole bytes are copied to the row.
+    pub fn render_bitmap(
+        &mut self,
+        clip: &ClipRow,
+        left: i32,
+        right: i32,
+        bits: &[u8],
+        order: BitOrder,
+        polarity: Polarity,
+    ) {
         let line_clip = clip.clip(left, right);
         if line_clip.is_empty() {
             return;
         }
+        if self.is_red_plane() {
+            self.fill(clip, left, right, Color::White);
+            return;
+        }
+        let (clip_left, clip_right) = line_clip.get();
+        let invert = match polarity {
+            Polarity::OneIsWhite => 0x00,
+            Polarity::OneIsBlack => 0xff,
+        };
+
+        if !self.gray && left & 7 == 0 {
+            // The source bytes line up with the bytes of the row.
+            // `left` can be negative, e.g., for centered images wider than
+            // the available space.
+            let first_byte = left >> 3;
+            for (index, mask) in byte_masks(clip_left, clip_right) {
+                let source = bits[(index as i32 - first_byte) as usize];
+                let source = match order {
+                    BitOrder::MsbFirst => source,
+                    BitOrder::LsbFirst => source.reverse_bits(),
+                } ^ invert;
+                self.buffer[index] = (self.buffer[index] & !mask) | (source & mask);
+            }
+            return;
+        }
 
-        for x in line_clip.get().0..line_clip.get().1 {
-            let byte = (x - left) / 8;
-            let bit = (x - left) & 7;
-            if (bits[byte as usize] & (1 << bit)) != 0 {
-                self.buffer[(x / 8) as usize] |= 0x80 >> (x & 7);
+        let white = self.max_level();
+        for x in clip_left..clip_right {
+            let byte = bits[((x - left) / 8) as usize] ^ invert;
+            let bit = match order {
+                BitOrder::MsbFirst => 0x80 >> ((x - left) & 7),
+                BitOrder::LsbFirst => 1 << ((x - left) & 7),
+            };
+            if (byte & bit) != 0 {
+                self.set_level(x, white);
             } else {
-                self.buffer[(x / 8) as usize] &= !(0x80 >> (x & 7));
+                self.set_level(x, 0);
+            }
+        }
+    }
+
+    /// Renders grayscale pixels from 0 (black) to 255 (white), with
+    /// `luminance[i]` containing the pixel at `left + i`.
+    ///
+    /// For error diffusion, the whole span is processed even if it is partly
+    /// clipped so that the result does not depend on the clipping area.
+    pub fn render_gray(
+        &mut self,
+        clip: &ClipRow,
+        left: i32,
+        right: i32,
+        luminance: &[u8],
+        dither: &mut Dither,
+    ) {
+        let line_clip = clip.clip(left, right);
+        if line_clip.is_empty() {
+            return;
+        }
+        if self.is_red_plane() {
+            self.fill(clip, left, right, Color::White);
+            return;
+        }
+        let (clip_left, clip_right) = line_clip.get();
+        let luminance = &luminance[..(right - left) as usize];
+        match dither {
+            Dither::Threshold | Dither::Bayer => {
+                let bayer = matches!(dither, Dither::Bayer);
+                for x in clip_left..clip_right {
+                    let level = self.ordered_level(x, luminance[(x - left) as usize], bayer);
+                    self.set_level(x, level);
+                }
+            }
+            Dither::FloydSteinberg(errors) => {
+                self.diffuse(errors, &line_clip, left, left, right, luminance, false)
+            }
+            Dither::Atkinson(errors) => {
+                self.diffuse(errors, &line_clip, left, left, right, luminance, true)
+            }
+        }
+    }
+
+    /// Renders a span with a single gray level.
+    pub fn fill_gray(
+        &mut self,
+        clip: &ClipRow,
+        left: i32,
+        right: i32,
+        luminance: u8,
+        dither: &mut Dither,
+    ) {
+        let line_clip = clip.clip(left, right);
+        if line_clip.is_empty() {
+            return;
+        }
+        if self.is_red_plane() {
+            self.fill(clip, left, right, Color::White);
+            return;
+        }
+        let (errors, atkinson) = match dither {
+            Dither::Threshold | Dither::Bayer => {
+                let bayer = matches!(dither, Dither::Bayer);
+                let (clip_left, clip_right) = line_clip.get();
+                for x in clip_left..clip_right {
+                    let level = self.ordered_level(x, luminance, bayer);
+                    self.set_level(x, level);
+                }
+                return;
             }
+            Dither::FloydSteinberg(errors) => (errors, false),
+            Dither::Atkinson(errors) => (errors, true),
+        };
+        // Error diffusion needs to process the whole span.
+        let pixels = [luminance; 64];
+        let mut start = left;
+        while start < right {
+            self.diffuse(errors, &line_clip, left, start, right, &pixels, atkinson);
+            start += pixels.len() as i32;
         }
     }
 
+    /// Like `render_gray()`, but takes the pixels from an iterator, e.g., a
+    /// decoder, so that the row does not have to be stored. Exactly
+    /// `right - left` pixels are taken from the iterator even if the span is
+    /// partly clipped.
+    pub fn render_gray_pixels<I: Iterator<Item = u8>>(
+        &mut self,
+        clip: &ClipRow,
+        left: i32,
+        right: i32,
+        mut pixels: I,
+        dither: &mut Dither,
+    ) {
+        let line_clip = clip.clip(left, right);
+        if self.is_red_plane() {
+            pixels.by_ref().take((right - left) as usize).for_each(drop);
+            self.fill(clip, left, right, Color::White);
+            return;
+        }
+        let mut chunk = [0u8; 64];
+        let mut start = left;
+        while start < right {
+            let length = min(right - start, chunk.len() as i32) as usize;
+            for value in chunk[..length].iter_mut() {
+                *value = pixels.next().unwrap_or(255);
+            }
+            if !line_clip.is_empty() {
+                let chunk = &chunk[..length];
+                match dither {
+                    Dither::Threshold | Dither::Bayer => {
+                        let bayer = matches!(dither, Dither::Bayer);
+                        let (clip_left, clip_right) = line_clip.clip(start, right).get();
+                        for x in clip_left..min(clip_right, start + length as i32) {
+                            let level = self.ordered_level(x, chunk[(x - start) as usize], bayer);
+                            self.set_level(x, level);
+                        }
+                    }
+                    Dither::FloydSteinberg(errors) => {
+                        self.diffuse(errors, &line_clip, left, start, right, chunk, false)
+                    }
+                    Dither::Atkinson(errors) => {
+                        self.diffuse(errors, &line_clip, left, start, right, chunk, true)
+                    }
+                }
+            }
+            start += length as i32;
+        }
+    }
+
+    fn ordered_level(&self, x: i32, luminance: u8, bayer: bool) -> u8 {
+        let threshold = if bayer {
+            BAYER_MATRIX[(self.y & 3) as usize][(x & 3) as usize] * 16 + 8
+        } else {
+            128
+        };
+        ((luminance as u32 * self.max_level() as u32 + threshold as u32) / 256) as u8
+    }
+
+    /// Applies Floyd-Steinberg or, if `atkinson` is set, Atkinson error
+    /// diffusion to the pixels starting at `start`. The error buffer is
+    /// indexed relative to `base`.
+    #[allow(clippy::too_many_arguments)]
+    fn diffuse(
+        &mut self,
+        errors: &mut ErrorBuffer,
+        clip: &ClipRow,
+        base: i32,
+        start: i32,
+        right: i32,
+        luminance: &[u8],
+        atkinson: bool,
+    ) {
+        let (clip_left, clip_right) = clip.get();
+        let width = (right - base) as usize;
+        let y = self.y;
+        let rows = if atkinson { 3 } else { 2 };
+        errors.start_row(y, width, rows);
+        let end = min(right, start + luminance.len() as i32);
+        for x in start..end {
+            let i = x - base;
+            let value = luminance[(x - start) as usize] as i16 + errors.get(y, i as usize);
+            let (level, error) = self.quantize(value);
+            if atkinson {
+                let error = error / 8;
+                errors.add(y, i + 1, width, error);
+                errors.add(y, i + 2, width, error);
+                errors.add(y + 1, i - 1, width, error);
+                errors.add(y + 1, i, width, error);
+                errors.add(y + 1, i + 1, width, error);
+                errors.add(y + 2, i, width, error);
+            } else {
+                errors.add(y, i + 1, width, error * 7 / 16);
+                errors.add(y + 1, i - 1, width, error * 3 / 16);
+                errors.add(y + 1, i, width, error * 5 / 16);
+                errors.add(y + 1, i + 1, width, error / 16);
+            }
+            if x >= clip_left && x < clip_right {
+                self.set_level(x, level);
+            }
+        }
+    }
+
+    /// Returns the closest level for a luminance value and the remaining
+    /// quantization error.
+    fn quantize(&self, value: i16) -> (u8, i16) {
+        let max_level = self.max_level() as i16;
+        let level = ((value * max_level + 127) / 255).clamp(0, max_level);
+        (level as u8, value - level * 255 / max_level)
+    }
+
+    fn max_level(&self) -> u8 {
+        if self.gray {
+            3
+        } else {
+            1
+        }
+    }
+
+    fn color_level(&self, color: Color) -> u8 {
+        match (color, self.red) {
+            (Color::Red, Some(RedPlane::BlackWhite)) => self.max_level(),
+            (Color::Red, _) => 0,
+            (_, Some(RedPlane::Red)) => 1,
+            (Color::White, _) => self.max_level(),
+            (Color::Black, _) => 0,
+            (Color::Gray(level), _) if self.gray => min(level, 3),
+            (Color::Gray(level), _) => (level >= 2) as u8,
+        }
+    }
+
+    fn set_level(&mut self, x: i32, level: u8) {
+        set_pixel_level(self.buffer, x as usize, self.gray, level);
+    }
+
+    /// Mirrors the row horizontally.
+    pub fn mirror(&mut self) {
+        let width = self.width as usize;
+        for x in 0..width / 2 {
+            let other = width - 1 - x;
+            let a = pixel_level(self.buffer, x, self.gray);
+            let b = pixel_level(self.buffer, other, self.gray);
+            set_pixel_level(self.buffer, x, self.gray, b);
+            set_pixel_level(self.buffer, other, self.gray, a);
+        }
+    }
+
+    pub fn full_row(&self) -> ClipRow {
+        ClipRow {
+            left: 0,
+            right: self.width as i32,
+        }
+    }
+}
+
+/// Renderer for a band of consecutive rows stored in a single buffer.
+pub struct BandRenderer<'a> {
+    buffer: &'a mut [u8],
+    width: u32,
+    stride: usize,
+    rows: u32,
+    y: i32,
+    gray: bool,
+    red: Option<RedPlane>,
+}
+
+impl<'a> BandRenderer<'a> {
+    pub fn new(buffer: &'a mut [u8], width: u32, rows: u32) -> BandRenderer<'a> {
+        let stride = width.div_ceil(8) as usize;
+        assert!(buffer.len() >= stride * rows as usize);
+        BandRenderer {
+            buffer,
+            width,
+            stride,
+            rows,
+            y: 0,
+            gray: false,
+            red: None,
+        }
+    }
+
+    /// Creates a renderer for rows of one of the planes of a black/white/red
+    /// display. See `RowRenderer::new_red()`.
+    pub fn new_red(
+        buffer: &'a mut [u8],
+        width: u32,
+        rows: u32,
+        plane: RedPlane,
+    ) -> BandRenderer<'a> {
+        let mut band = Self::new(buffer, width, rows);
+        band.red = Some(plane);
+        band
+    }
+
+    /// Creates a renderer for rows with two bits per pixel. See
+    /// `RowRenderer::new_gray()`.
+    pub fn new_gray(buffer: &'a mut [u8], width: u32, rows: u32) -> BandRenderer<'a> {
+        let stride = width.div_ceil(4) as usize;
+        assert!(buffer.len() >= stride * rows as usize);
+        BandRenderer {
+            buffer,
+            width,
+            stride,
+            rows,
+            y: 0,
+            gray: true,
+            red: None,
+        }
+    }
+
+    /// Sets the absolute row of the first row of the band. See
+    /// `RowRenderer::set_y()`.
+    pub fn set_y(&mut self, y: i32) {
+        self.y = y;
+    }
+
+    pub fn finish(self) {
+        // Destroy the renderer and release the buffer.
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    /// Returns the absolute row of the first row of the band.
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+
+    /// Returns a renderer for the specified row of the band.
+    pub fn row(&mut self, row: u32) -> RowRenderer<'_> {
+        assert!(row < self.rows);
+        let start = row as usize * self.stride;
+        let buffer = &mut self.buffer[start..start + self.stride];
+        let mut renderer = match (self.gray, self.red) {
+            (true, _) => RowRenderer::new_gray(buffer, self.width),
+            (false, Some(plane)) => RowRenderer::new_red(buffer, self.width, plane),
+            (false, None) => RowRenderer::new(buffer, self.width),
+        };
+        renderer.set_y(self.y + row as i32);
+        renderer
+    }
+
+    /// Splits the band into two bands, the first containing `row` rows.
+    pub fn split_at(&mut self, row: u32) -> (BandRenderer<'_>, BandRenderer<'_>) {
+        assert!(row <= self.rows);
+        let (top, bottom) = self.buffer.split_at_mut(row as usize * self.stride);
+        (
+            BandRenderer {
+                buffer: top,
+                width: self.width,
+                stride: self.stride,
+                rows: row,
+                y: self.y,
+                gray: self.gray,
+                red: self.red,
+            },
+            BandRenderer {
+                buffer: bottom,
+                width: self.width,
+                stride: self.stride,
+                rows: self.rows - row,
+                y: self.y + row as i32,
+                gray: self.gray,
+                red: self.red,
+            },
+        )
+    }
+
     pub fn full_row(&self) -> ClipRow {
         ClipRow {
             left: 0,
@@ -163,15 +922,73 @@ impl ClipRow {
     }
 }
 
+/// Clipping rectangle in absolute coordinates, i.e., relative to the top left
+/// corner of the GUI.
+///
+/// Containers intersect the rectangle with the bounds of their children, so
+/// elements cannot draw outside of the area assigned to them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClipRect {
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+}
+
+impl ClipRect {
+    pub fn new(left: i32, top: i32, right: i32, bottom: i32) -> ClipRect {
+        ClipRect {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    pub fn get(&self) -> (i32, i32, i32, i32) {
+        (self.left, self.top, self.right, self.bottom)
+    }
+
+    pub fn clip(&self, left: i32, top: i32, right: i32, bottom: i32) -> ClipRect {
+        ClipRect {
+            left: max(self.left, left),
+            top: max(self.top, top),
+            right: min(self.right, right),
+            bottom: min(self.bottom, bottom),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.left >= self.right || self.top >= self.bottom
+    }
+
+    /// Returns whether the absolute row `y` is within the rectangle.
+    pub fn contains_row(&self, y: i32) -> bool {
+        y >= self.top && y < self.bottom && self.left < self.right
+    }
+
+    /// Returns the horizontal span of the rectangle for row rendering.
+    pub fn row(&self) -> ClipRow {
+        ClipRow {
+            left: self.left,
+            right: self.right,
+        }
+    }
+}
+
 #[cfg(test)]
 pub struct TestDisplay {
     pub frame: Vec<u8>,
+    pub planes: u32,
 }
 
 #[cfg(test)]
 impl TestDisplay {
     pub fn new() -> Self {
-        Self { frame: Vec::new() }
+        Self {
+            frame: Vec::new(),
+            planes: 0,
+        }
     }
 }
 
@@ -197,9 +1014,51 @@ impl Display for TestDisplay {
     }
 }
 
+#[cfg(test)]
+impl GrayscaleDisplay for TestDisplay {
+    fn start_gray_frame(&mut self) -> nb::Result<(), Error> {
+        self.frame = Vec::new();
+        self.planes = 1;
+        Ok(())
+    }
+    fn next_gray_plane(&mut self) {
+        assert!(self.frame.len() == (Self::WIDTH * Self::HEIGHT / 4) as usize);
+        self.planes += 1;
+    }
+    fn end_gray_frame(&mut self) {
+        assert!(self.frame.len() == (Self::WIDTH * Self::HEIGHT / 2) as usize);
+    }
+
+    fn draw_gray_row(&mut self, row: &[u8]) {
+        assert!(row.len() >= Self::WIDTH as usize / 4);
+        self.frame
+            .extend_from_slice(&row[..Self::WIDTH as usize / 4]);
+    }
+}
+
+#[cfg(test)]
+impl RedDisplay for TestDisplay {
+    fn start_red_frame(&mut self) -> nb::Result<(), Error> {
+        self.frame = Vec::new();
+        self.planes = 1;
+        Ok(())
+    }
+    fn next_red_plane(&mut self) {
+        assert!(self.frame.len() == (Self::WIDTH * Self::HEIGHT / 8) as usize);
+        self.planes += 1;
+    }
+    fn end_red_frame(&mut self) {
+        assert!(self.frame.len() == (Self::WIDTH * Self::HEIGHT / 4) as usize);
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Color, RowRenderer};
+    use super::{
+        BitOrder, ClipRow, Color, Dither, ErrorBuffer, Pattern, Polarity, RedPlane, RowRenderer,
+    };
+    use crate::gui::image::BitmapImage;
+    use std::vec::Vec;
 
     #[test]
     #[should_panic]
@@ -208,6 +1067,13 @@ mod tests {
         RowRenderer::new(&mut buffer, 12);
     }
 
+    #[test]
+    #[should_panic(expected = "error buffer width must not be zero")]
+    fn test_error_buffer_zero_width() {
+        let mut errors = [0i16; 4];
+        ErrorBuffer::new(&mut errors, 0);
+    }
+
     struct FillTest {
         before: [u8; 4],
         clip: (i32, i32),
@@ -301,4 +1167,178 @@ mod tests {
             assert!(buffer == test.ok);
         }
     }
+
+    #[test]
+    fn test_row_renderer_pattern() {
+        let fill = |y: i32, clip: (i32, i32)| {
+            let mut buffer = [0u8; 4];
+            let mut renderer = RowRenderer::new(&mut buffer, 32);
+            renderer.set_y(y);
+            let clip = ClipRow {
+                left: clip.0,
+                right: clip.1,
+            };
+            renderer.fill_pattern(&clip, 4, 20, &Pattern::CHECKERBOARD, Color::White);
+            buffer
+        };
+        assert_eq!(fill(0, (0, 32)), [0x0a, 0xaa, 0xa0, 0x00]);
+        assert_eq!(fill(1, (0, 32)), [0x05, 0x55, 0x50, 0x00]);
+        // The pattern is aligned to absolute coordinates.
+        assert_eq!(fill(8, (8, 16)), [0x00, 0xaa, 0x00, 0x00]);
+        assert_eq!(fill(-1, (0, 32)), fill(7, (0, 32)));
+
+        let mut buffer = [0x0fu8, 0x00, 0xff, 0x55];
+        let mut renderer = RowRenderer::new(&mut buffer, 32);
+        let clip = renderer.full_row();
+        renderer.invert(&clip, 4, 28);
+        assert_eq!(buffer, [0x00, 0xff, 0x00, 0xa5]);
+
+        let mut buffer = [0xffu8, 0xff];
+        let mut renderer = RowRenderer::new_gray(&mut buffer, 8);
+        let clip = renderer.full_row();
+        renderer.fill_pattern(&clip, 0, 8, &Pattern::VERTICAL_HATCH, Color::Black);
+        assert_eq!(buffer, [0x3f, 0x3f]);
+
+        let mut buffer = [0x1bu8];
+        let mut renderer = RowRenderer::new_gray(&mut buffer, 4);
+        let clip = renderer.full_row();
+        renderer.invert(&clip, 0, 4);
+        assert_eq!(buffer, [0xe4]);
+    }
+
+    #[test]
+    fn test_row_renderer_bitmap() {
+        let render =
+            |before: u8, left: i32, clip: (i32, i32), order: BitOrder, polarity: Polarity| {
+                let mut buffer = [before; 4];
+                let mut renderer = RowRenderer::new(&mut buffer, 32);
+                let clip = ClipRow {
+                    left: clip.0,
+                    right: clip.1,
+                };
+                renderer.render_bitmap(&clip, left, left + 16, &[0x81, 0x0f], order, polarity);
+                buffer
+            };
+        // Byte-aligned copies.
+        assert_eq!(
+            render(0x00, 8, (0, 32), BitOrder::MsbFirst, Polarity::OneIsWhite),
+            [0x00, 0x81, 0x0f, 0x00]
+        );
+        assert_eq!(
+            render(0x00, 8, (0, 32), BitOrder::LsbFirst, Polarity::OneIsWhite),
+            [0x00, 0x81, 0xf0, 0x00]
+        );
+        assert_eq!(
+            render(0xff, 8, (10, 20), BitOrder::MsbFirst, Polarity::OneIsBlack),
+            [0xff, 0xfe, 0xff, 0xff]
+        );
+        // Images starting left of the row.
+        assert_eq!(
+            render(0x00, -8, (0, 32), BitOrder::MsbFirst, Polarity::OneIsWhite),
+            [0x0f, 0x00, 0x00, 0x00]
+        );
+        // Unaligned images are copied pixel by pixel.
+        assert_eq!(
+            render(0x00, 9, (0, 32), BitOrder::MsbFirst, Polarity::OneIsWhite),
+            [0x00, 0x40, 0x87, 0x80]
+        );
+        assert_eq!(
+            render(0xff, 9, (0, 32), BitOrder::LsbFirst, Polarity::OneIsBlack),
+            [0xff, 0xbf, 0x07, 0xff]
+        );
+
+        const IMAGE: BitmapImage = BitmapImage::new_msb_black(&[0x80, 0x00, 0x7f, 0x80], 9, 2);
+        let mut buffer = [0xffu8; 2];
+        let mut renderer = RowRenderer::new_gray(&mut buffer, 8);
+        let clip = renderer.full_row();
+        IMAGE.render_row_transparent(&mut renderer, &clip, 0, 0);
+        assert_eq!(buffer, [0x3f, 0xff]);
+    }
+
+    fn count_white(rows: &[[u8; 4]]) -> u32 {
+        rows.iter().flatten().map(|b| b.count_ones()).sum()
+    }
+
+    #[test]
+    fn test_row_renderer_dither() {
+        // Ordered dithering of 50% gray produces exactly half white pixels
+        // in every row.
+        let mut rows = [[0u8; 4]; 8];
+        for (y, buffer) in rows.iter_mut().enumerate() {
+            let mut renderer = RowRenderer::new(&mut buffer[..], 32);
+            renderer.set_y(y as i32);
+            let clip = renderer.full_row();
+            renderer.fill_gray(&clip, 0, 32, 128, &mut Dither::Bayer);
+            renderer.finish();
+            assert_eq!(count_white(&[*buffer]), 16);
+        }
+        assert!(rows[0] != rows[1]);
+
+        let mut buffer = [0u8; 4];
+        let mut renderer = RowRenderer::new(&mut buffer[..], 32);
+        let clip = renderer.full_row();
+        let pixels: Vec<u8> = (0..32).map(|x| x * 8).collect();
+        renderer.render_gray(&clip, 0, 32, &pixels, &mut Dither::Threshold);
+        renderer.finish();
+        assert_eq!(buffer, [0x00, 0x00, 0xff, 0xff]);
+
+        // Floyd-Steinberg approximately preserves the average brightness,
+        // whereas Atkinson produces darker results as it discards some of the
+        // error.
+        let mut errors = [0i16; 2 * 32];
+        let mut more_errors = [0i16; 3 * 32];
+        let mut dithers = [
+            (
+                Dither::FloydSteinberg(ErrorBuffer::new(&mut errors, 32)),
+                100..130,
+            ),
+            (
+                Dither::Atkinson(ErrorBuffer::new(&mut more_errors, 32)),
+                60..100,
+            ),
+        ];
+        for (dither, expected) in dithers.iter_mut() {
+            let mut rows = [[0u8; 4]; 16];
+            for (y, buffer) in rows.iter_mut().enumerate() {
+                let mut renderer = RowRenderer::new(&mut buffer[..], 32);
+                renderer.set_y(y as i32);
+                let clip = renderer.full_row().clip(3, 32);
+                renderer.fill_gray(&clip, 0, 32, 64, dither);
+                renderer.finish();
+            }
+            let white = count_white(&rows);
+            assert!(expected.contains(&white), "{} white pixels", white);
+        }
+    }
+
+    #[test]
+    fn test_red_planes() {
+        let image = [0x0fu8];
+        let render = |red: Option<RedPlane>| {
+            let mut buffer = [0xffu8; 4];
+            let mut row = match red {
+                Some(plane) => RowRenderer::new_red(&mut buffer, 32, plane),
+                None => RowRenderer::new(&mut buffer, 32),
+            };
+            let clip = row.full_row();
+            row.fill(&clip, 0, 8, Color::Red);
+            row.fill(&clip, 4, 6, Color::Black);
+            row.render_bitmap(
+                &clip,
+                8,
+                16,
+                &image,
+                BitOrder::MsbFirst,
+                Polarity::OneIsBlack,
+            );
+            row.invert(&clip, 16, 24);
+            row.fill_gray(&clip, 24, 32, 0, &mut Dither::Threshold);
+            row.finish();
+            buffer
+        };
+        // Red is black on displays without red.
+        assert_eq!(render(None), [0x00, 0xf0, 0x00, 0x00]);
+        assert_eq!(render(Some(RedPlane::BlackWhite)), [0xf3, 0xf0, 0x00, 0x00]);
+        assert_eq!(render(Some(RedPlane::Red)), [0x0c, 0xff, 0xff, 0xff]);
+    }
 }
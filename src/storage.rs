@@ -0,0 +1,175 @@
+//! Access to data outside of the firmware image, such as fonts and images in
+//! external SPI flash or in a file on an SD card.
+
+/// Storage which can be read at arbitrary offsets.
+///
+/// Implemented for byte slices and, with the `storage` feature, for
+/// `embedded-storage` devices wrapped in `Storage`. Files are supported by
+/// implementing the trait for the file type of the file system.
+pub trait BlockRead {
+    type Error;
+
+    /// Fills `buffer` with the data starting at `offset`.
+    fn read(&mut self, offset: u32, buffer: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+/// Error returned when reading beyond the end of a byte slice.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OutOfBounds;
+
+impl BlockRead for &[u8] {
+    type Error = OutOfBounds;
+
+    fn read(&mut self, offset: u32, buffer: &mut [u8]) -> Result<(), OutOfBounds> {
+        let start = offset as usize;
+        let data = start
+            .checked_add(buffer.len())
+            .and_then(|end| self.get(start..end))
+            .ok_or(OutOfBounds)?;
+        buffer.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+/// Adapter for devices implementing `embedded_storage::ReadStorage`.
+#[cfg(feature = "storage")]
+pub struct Storage<S>(pub S);
+
+#[cfg(feature = "storage")]
+impl<S: embedded_storage::ReadStorage> BlockRead for Storage<S> {
+    type Error = S::Error;
+
+    fn read(&mut self, offset: u32, buffer: &mut [u8]) -> Result<(), S::Error> {
+        self.0.read(offset, buffer)
+    }
+}
+
+/// Cache for `BLOCKS` blocks of `SIZE` bytes of slow storage. When all blocks
+/// are used, the least recently used block is replaced.
+///
+/// Rendering reads the same glyph and image data for every row, so even a
+/// small cache saves most accesses to the storage.
+pub struct BlockCache<R: BlockRead, const BLOCKS: usize, const SIZE: usize> {
+    reader: R,
+    blocks: [[u8; SIZE]; BLOCKS],
+    /// Index of the block of the storage in each cache entry.
+    tags: [Option<u32>; BLOCKS],
+    last_used: [u32; BLOCKS],
+    time: u32,
+}
+
+impl<R: BlockRead, const BLOCKS: usize, const SIZE: usize> BlockCache<R, BLOCKS, SIZE> {
+    pub fn new(reader: R) -> Self {
+        assert!(BLOCKS > 0 && SIZE > 0);
+        BlockCache {
+            reader,
+            blocks: [[0; SIZE]; BLOCKS],
+            tags: [None; BLOCKS],
+            last_used: [0; BLOCKS],
+            time: 0,
+        }
+    }
+
+    /// Discards all cached data, e.g., after the storage has been written.
+    pub fn invalidate(&mut self) {
+        self.tags = [None; BLOCKS];
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Returns the cache entry containing the block, loading it if
+    /// necessary.
+    fn entry(&mut self, block: u32) -> Result<usize, R::Error> {
+        self.time = self.time.wrapping_add(1);
+        if let Some(entry) = self.tags.iter().position(|&tag| tag == Some(block)) {
+            self.last_used[entry] = self.time;
+            return Ok(entry);
+        }
+        let entry = (0..BLOCKS)
+            .max_by_key(|&entry| match self.tags[entry] {
+                Some(_) => self.time.wrapping_sub(self.last_used[entry]),
+                None => u32::MAX,
+            })
+            .unwrap();
+        self.tags[entry] = None;
+        self.reader
+            .read(block * SIZE as u32, &mut self.blocks[entry])?;
+        self.tags[entry] = Some(block);
+        self.last_used[entry] = self.time;
+        Ok(entry)
+    }
+}
+
+impl<R: BlockRead, const BLOCKS: usize, const SIZE: usize> BlockRead
+    for BlockCache<R, BLOCKS, SIZE>
+{
+    type Error = R::Error;
+
+    fn read(&mut self, offset: u32, buffer: &mut [u8]) -> Result<(), R::Error> {
+        let mut pos = 0;
+        while pos < buffer.len() {
+            let address = offset + pos as u32;
+            let block = address / SIZE as u32;
+            let start = (address % SIZE as u32) as usize;
+            let length = (SIZE - start).min(buffer.len() - pos);
+            match self.entry(block) {
+                Ok(entry) => buffer[pos..pos + length]
+                    .copy_from_slice(&self.blocks[entry][start..start + length]),
+                // The block may extend beyond the end of the storage, so the
+                // data is read without caching.
+                Err(_) => self.reader.read(address, &mut buffer[pos..pos + length])?,
+            }
+            pos += length;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockCache, BlockRead, OutOfBounds};
+
+    /// Counts the reads from the storage.
+    struct Counter<'a> {
+        data: &'a [u8],
+        reads: usize,
+    }
+
+    impl<'a> BlockRead for Counter<'a> {
+        type Error = OutOfBounds;
+
+        fn read(&mut self, offset: u32, buffer: &mut [u8]) -> Result<(), OutOfBounds> {
+            self.reads += 1;
+            self.data.read(offset, buffer)
+        }
+    }
+
+    #[test]
+    fn test_block_cache() {
+        let data: std::vec::Vec<u8> = (0..30).collect();
+        let mut cache: BlockCache<_, 2, 8> = BlockCache::new(Counter {
+            data: &data,
+            reads: 0,
+        });
+        let mut buffer = [0u8; 6];
+        cache.read(5, &mut buffer).unwrap();
+        assert_eq!(buffer, [5, 6, 7, 8, 9, 10]);
+        assert_eq!(cache.reader.reads, 2);
+        cache.read(9, &mut buffer[..2]).unwrap();
+        assert_eq!(cache.reader.reads, 2);
+
+        // Block 0 is replaced, as block 1 was used more recently.
+        cache.read(16, &mut buffer[..1]).unwrap();
+        cache.read(8, &mut buffer[..1]).unwrap();
+        assert_eq!(cache.reader.reads, 3);
+        cache.read(0, &mut buffer[..1]).unwrap();
+        assert_eq!(cache.reader.reads, 4);
+
+        // The last block is incomplete and read directly.
+        cache.read(27, &mut buffer[..3]).unwrap();
+        assert_eq!(buffer[..3], [27, 28, 29]);
+        assert_eq!(cache.read(28, &mut buffer[..3]), Err(OutOfBounds));
+    }
+}
@@ -0,0 +1,130 @@
+//! Import of fonts in the Glyph Bitmap Distribution Format.
+
+use epd_image::Bitmap;
+
+use super::{parse_error, Error, FontData, GlyphData};
+
+/// Parses a BDF font and returns the glyphs for the specified characters.
+///
+/// Glyphs are selected by their `ENCODING`, which is assumed to be Unicode.
+/// Characters missing from the font are skipped.
+pub fn parse(text: &str, chars: &[char]) -> Result<FontData, Error> {
+    let mut font = FontData {
+        ascender: 0,
+        descender: 0,
+        glyphs: Vec::new(),
+        kerning: Vec::new(),
+        replacement: None,
+    };
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("FONT_ASCENT") => font.ascender = parse_field(fields.next())?,
+            Some("FONT_DESCENT") => font.descender = parse_field(fields.next())?,
+            Some("STARTCHAR") => {
+                if let Some(glyph) = parse_char(&mut lines)? {
+                    if chars.binary_search(&glyph.0).is_ok() {
+                        font.glyphs.push(glyph);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    font.glyphs.sort_by_key(|(c, _)| *c);
+    font.glyphs.dedup_by_key(|(c, _)| *c);
+    Ok(font)
+}
+
+/// Parses a single glyph up to `ENDCHAR`. Returns `None` for glyphs without a
+/// valid Unicode encoding.
+fn parse_char<'a>(
+    lines: &mut impl Iterator<Item = &'a str>,
+) -> Result<Option<(char, GlyphData)>, Error> {
+    let mut encoding = None;
+    let mut advance = 0;
+    let mut bbx = (0u32, 0u32, 0i16, 0i16);
+    let mut image = None;
+    while let Some(line) = lines.next() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            // Glyphs without a standard encoding use -1.
+            Some("ENCODING") => encoding = fields.next().and_then(|field| field.parse().ok()),
+            Some("DWIDTH") => advance = parse_field(fields.next())?,
+            Some("BBX") => {
+                bbx = (
+                    parse_field(fields.next())?,
+                    parse_field(fields.next())?,
+                    parse_field(fields.next())?,
+                    parse_field(fields.next())?,
+                )
+            }
+            Some("BITMAP") => {
+                let (width, height, _, _) = bbx;
+                let mut bitmap = Bitmap::new(width, height);
+                for y in 0..height {
+                    let row = match lines.next() {
+                        Some(row) => row.trim(),
+                        None => return parse_error("truncated bitmap"),
+                    };
+                    for x in 0..width {
+                        let digit = row
+                            .get(x as usize / 4..x as usize / 4 + 1)
+                            .and_then(|digit| u8::from_str_radix(digit, 16).ok());
+                        let digit = match digit {
+                            Some(digit) => digit,
+                            None => return parse_error("invalid bitmap row"),
+                        };
+                        bitmap.set_black(x, y, digit & (0x8 >> (x & 3)) != 0);
+                    }
+                }
+                image = Some(bitmap);
+            }
+            Some("ENDCHAR") => {
+                let (_, height, left, bottom) = bbx;
+                let c = encoding.and_then(char::from_u32);
+                return Ok(c.zip(image).map(|(c, image)| {
+                    let glyph = GlyphData {
+                        image,
+                        image_left: left,
+                        image_top: height as i16 + bottom,
+                        advance,
+                    };
+                    (c, glyph)
+                }));
+            }
+            _ => {}
+        }
+    }
+    parse_error("missing ENDCHAR")
+}
+
+fn parse_field<T: std::str::FromStr>(field: Option<&str>) -> Result<T, Error> {
+    match field.and_then(|field| field.parse().ok()) {
+        Some(value) => Ok(value),
+        None => parse_error("invalid field"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::tests::TEST_BDF;
+
+    #[test]
+    fn test_bdf() {
+        let font = parse(TEST_BDF, &['A', 'g', 'z']).unwrap();
+        assert_eq!((font.ascender, font.descender), (4, 1));
+        assert_eq!(font.glyphs.len(), 2);
+        let (c, a) = &font.glyphs[0];
+        assert_eq!(*c, 'A');
+        assert_eq!((a.image.width, a.image.height), (3, 4));
+        assert_eq!(a.image.data, [0xbf, 0x5f, 0x1f, 0x5f]);
+        assert_eq!((a.image_left, a.image_top, a.advance), (0, 4, 4));
+        let (c, g) = &font.glyphs[1];
+        assert_eq!(*c, 'g');
+        assert_eq!(g.image.data, [0x3f, 0xbf, 0x7f]);
+        assert_eq!((g.image_left, g.image_top, g.advance), (1, 2, 4));
+    }
+}
@@ -0,0 +1,517 @@
+//! Conversion of fonts into the format used by `embedded_epd::gui::font`.
+//!
+//! Outline fonts (TTF/OTF) are rasterized at a fixed pixel size, bitmap fonts
+//! (BDF/PCF) are imported directly. The resulting `FontData` can be written as
+//! Rust source code, which makes the library usable from build scripts:
+//!
+//! ```no_run
+//! let data = std::fs::read("DejaVuSans.ttf").unwrap();
+//! let chars = epd_font::parse_char_set("0x20-0x7e").unwrap();
+//! let font = epd_font::truetype::rasterize(&data, 16.0, &chars, 128).unwrap();
+//! let out_dir = std::env::var("OUT_DIR").unwrap();
+//! std::fs::write(format!("{}/font.rs", out_dir), font.to_rust("SANS_16").unwrap()).unwrap();
+//! ```
+//!
+//! The generated file is then included with
+//! `include!(concat!(env!("OUT_DIR"), "/font.rs"));`.
+
+use std::fmt::{self, Write};
+
+use epd_image::{encode_heatshrink, encode_nibble, encode_packed, encode_rle, rle_bytes, Bitmap};
+
+pub mod bdf;
+pub mod pcf;
+pub mod truetype;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The font file could not be parsed.
+    Parse(String),
+    /// A glyph is too large for the image format.
+    TooLarge(char),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(message) => write!(f, "invalid font: {}", message),
+            Error::TooLarge(c) => write!(f, "glyph {:?} too large", c),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub(crate) fn parse_error<T>(message: &str) -> Result<T, Error> {
+    Err(Error::Parse(message.to_string()))
+}
+
+/// Rasterized glyph. The image uses set (white) bits for the background.
+pub struct GlyphData {
+    pub image: Bitmap,
+    /// Horizontal position of the image relative to the pen position.
+    pub image_left: i16,
+    /// Distance from the top of the image to the baseline.
+    pub image_top: i16,
+    pub advance: u32,
+}
+
+impl GlyphData {
+    /// Returns the glyph with the image cropped to the bounding box of the
+    /// black pixels.
+    pub fn cropped(&self) -> GlyphData {
+        let image = &self.image;
+        let black_rows: Vec<u32> = (0..image.height)
+            .filter(|&y| (0..image.width).any(|x| image.is_black(x, y)))
+            .collect();
+        let black_columns: Vec<u32> = (0..image.width)
+            .filter(|&x| (0..image.height).any(|y| image.is_black(x, y)))
+            .collect();
+        let (top, left) = match (black_rows.first(), black_columns.first()) {
+            (Some(&top), Some(&left)) => (top, left),
+            _ => {
+                return GlyphData {
+                    image: Bitmap::new(0, 0),
+                    image_left: 0,
+                    image_top: 0,
+                    advance: self.advance,
+                }
+            }
+        };
+        let height = black_rows.last().unwrap() + 1 - top;
+        let width = black_columns.last().unwrap() + 1 - left;
+        let mut cropped = Bitmap::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                cropped.set_black(x, y, image.is_black(x + left, y + top));
+            }
+        }
+        GlyphData {
+            image: cropped,
+            image_left: self.image_left + left as i16,
+            image_top: self.image_top - top as i16,
+            advance: self.advance,
+        }
+    }
+}
+
+/// Storage format of the glyph images, see `embedded_epd::gui::image`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GlyphFormat {
+    /// `RLEImage` with 16-bit runs and a row table.
+    Rle,
+    /// `NibbleImage` with 4-bit runs, usually the smallest format for small
+    /// glyphs.
+    Nibble,
+    /// `PackedImage` with one bit per pixel.
+    Packed,
+    /// `HeatshrinkImage`, for large glyphs.
+    Heatshrink,
+}
+
+/// Font with the glyphs for a set of characters.
+pub struct FontData {
+    pub ascender: u16,
+    pub descender: u16,
+    /// Glyphs sorted by character.
+    pub glyphs: Vec<(char, GlyphData)>,
+    /// Kerning pairs as (left, right, offset in pixels), sorted by the
+    /// characters.
+    pub kerning: Vec<(char, char, i16)>,
+    /// Character whose glyph is displayed for missing characters.
+    pub replacement: Option<char>,
+}
+
+impl FontData {
+    /// Returns Rust source code defining a `Font` static with the specified
+    /// name, together with its glyph table and lookup function.
+    pub fn to_rust(&self, name: &str) -> Result<String, Error> {
+        self.to_rust_with_format(name, GlyphFormat::Rle)
+    }
+
+    /// Like `to_rust()`, but stores the glyph images in the specified format.
+    /// The images are cropped to the bounding boxes of the glyphs.
+    pub fn to_rust_with_format(&self, name: &str, format: GlyphFormat) -> Result<String, Error> {
+        let lower = name.to_lowercase();
+        let mut source = String::new();
+        writeln!(source, "// Generated by epd-font.").unwrap();
+        writeln!(
+            source,
+            "pub static {}: embedded_epd::gui::font::Font = embedded_epd::gui::font::Font {{",
+            name
+        )
+        .unwrap();
+        writeln!(source, "    ascender: {},", self.ascender).unwrap();
+        writeln!(source, "    descender: {},", self.descender).unwrap();
+        writeln!(source, "    glyphs: &{}_GLYPHS,", name).unwrap();
+        writeln!(source, "    get_glyph_index: {}_glyph_index,", lower).unwrap();
+        writeln!(source, "    kerning: &{}_KERNING,", name).unwrap();
+        match self.replacement.and_then(|c| self.glyph_index(c)) {
+            Some(index) => writeln!(source, "    replacement: Some({}),", index).unwrap(),
+            None => writeln!(source, "    replacement: None,").unwrap(),
+        }
+        writeln!(source, "}};").unwrap();
+        writeln!(source).unwrap();
+
+        writeln!(
+            source,
+            "static {}_GLYPHS: [embedded_epd::gui::font::Glyph; {}] = [",
+            name,
+            self.glyphs.len()
+        )
+        .unwrap();
+        for (c, glyph) in self.glyphs.iter() {
+            let glyph = glyph.cropped();
+            if glyph.image.width > 0xffff || glyph.image.height > 0xffff {
+                return Err(Error::TooLarge(*c));
+            }
+            let bytes = |data: Vec<u8>| -> Vec<String> {
+                data.iter().map(|v| format!("0x{:02x}", v)).collect()
+            };
+            let (image_type, data, block_rows) = match format {
+                GlyphFormat::Rle => {
+                    let data = encode_rle(&glyph.image).map_err(|_| Error::TooLarge(*c))?;
+                    let data = data.iter().map(|v| format!("0x{:04x}", v)).collect();
+                    ("Rle(embedded_epd::gui::image::RLEImage", data, None)
+                }
+                GlyphFormat::Nibble => (
+                    "Nibble(embedded_epd::gui::image::NibbleImage",
+                    bytes(encode_nibble(&glyph.image)),
+                    None,
+                ),
+                GlyphFormat::Packed => (
+                    "Packed(embedded_epd::gui::image::PackedImage",
+                    bytes(encode_packed(&glyph.image)),
+                    None,
+                ),
+                GlyphFormat::Heatshrink => (
+                    "Heatshrink(embedded_epd::gui::image::HeatshrinkImage",
+                    bytes(encode_heatshrink(&glyph.image, glyph.image.height).0),
+                    Some(glyph.image.height),
+                ),
+            };
+            writeln!(source, "    // {:?} (U+{:04X})", c, *c as u32).unwrap();
+            writeln!(source, "    embedded_epd::gui::font::Glyph {{").unwrap();
+            writeln!(
+                source,
+                "        image: embedded_epd::gui::font::GlyphImage::{} {{",
+                image_type
+            )
+            .unwrap();
+            writeln!(source, "            data: &[{}],", data.join(", ")).unwrap();
+            if let Some(block_rows) = block_rows {
+                writeln!(source, "            blocks: &[],").unwrap();
+                writeln!(source, "            block_rows: {},", block_rows).unwrap();
+            }
+            writeln!(source, "            width: {},", glyph.image.width).unwrap();
+            writeln!(source, "            height: {},", glyph.image.height).unwrap();
+            writeln!(source, "        }}),").unwrap();
+            writeln!(source, "        image_left: {},", glyph.image_left).unwrap();
+            writeln!(source, "        image_top: {},", glyph.image_top).unwrap();
+            writeln!(source, "        advance: {},", glyph.advance).unwrap();
+            writeln!(source, "    }},").unwrap();
+        }
+        writeln!(source, "];").unwrap();
+        writeln!(source).unwrap();
+
+        let kerning: Vec<(usize, usize, i16)> = self
+            .kerning
+            .iter()
+            .filter_map(|&(left, right, offset)| {
+                Some((self.glyph_index(left)?, self.glyph_index(right)?, offset))
+            })
+            .collect();
+        writeln!(
+            source,
+            "static {}_KERNING: [embedded_epd::gui::font::KerningPair; {}] = [",
+            name,
+            kerning.len()
+        )
+        .unwrap();
+        for (left, right, offset) in kerning {
+            writeln!(
+                source,
+                "    embedded_epd::gui::font::KerningPair {{ left: {}, right: {}, offset: {} }},",
+                left, right, offset
+            )
+            .unwrap();
+        }
+        writeln!(source, "];").unwrap();
+        writeln!(source).unwrap();
+
+        // Consecutive characters are looked up with a single range pattern.
+        writeln!(
+            source,
+            "fn {}_glyph_index(c: char) -> Option<usize> {{",
+            lower
+        )
+        .unwrap();
+        writeln!(source, "    match c as u32 {{").unwrap();
+        for (start, end, index) in ranges(&self.glyphs) {
+            if start == end {
+                writeln!(source, "        0x{:x} => Some({}),", start, index).unwrap();
+            } else if index == 0 {
+                writeln!(
+                    source,
+                    "        0x{:x}..=0x{:x} => Some((c as u32 - 0x{:x}) as usize),",
+                    start, end, start
+                )
+                .unwrap();
+            } else {
+                writeln!(
+                    source,
+                    "        0x{:x}..=0x{:x} => Some((c as u32 - 0x{:x}) as usize + {}),",
+                    start, end, start, index
+                )
+                .unwrap();
+            }
+        }
+        writeln!(source, "        _ => None,").unwrap();
+        writeln!(source, "    }}").unwrap();
+        writeln!(source, "}}").unwrap();
+        Ok(source)
+    }
+
+    /// Returns the font as asset for `embedded_epd::gui::external::ExternalFont`
+    /// with cropped RLE glyph images.
+    pub fn to_asset(&self) -> Result<Vec<u8>, Error> {
+        if let Some((c, _)) = self.glyphs.get(0xffff) {
+            return Err(Error::TooLarge(*c));
+        }
+        let kerning: Vec<(usize, usize, i16)> = self
+            .kerning
+            .iter()
+            .filter_map(|&(left, right, offset)| {
+                Some((self.glyph_index(left)?, self.glyph_index(right)?, offset))
+            })
+            .collect();
+        let ranges = ranges(&self.glyphs);
+        let replacement = self.replacement.and_then(|c| self.glyph_index(c));
+
+        let mut asset = Vec::new();
+        for value in [
+            self.ascender,
+            self.descender,
+            self.glyphs.len() as u16,
+            ranges.len() as u16,
+            kerning.len() as u16,
+            replacement.map(|index| index as u16).unwrap_or(0xffff),
+        ] {
+            asset.extend_from_slice(&value.to_le_bytes());
+        }
+        for (first, last, index) in ranges {
+            asset.extend_from_slice(&first.to_le_bytes());
+            asset.extend_from_slice(&last.to_le_bytes());
+            asset.extend_from_slice(&(index as u16).to_le_bytes());
+            asset.extend_from_slice(&[0, 0]);
+        }
+
+        // The images follow the glyph and kerning tables.
+        let mut image_offset = asset.len() + self.glyphs.len() * 16 + kerning.len() * 8;
+        let mut images = Vec::new();
+        for (c, glyph) in self.glyphs.iter() {
+            let glyph = glyph.cropped();
+            let image = encode_rle(&glyph.image).map_err(|_| Error::TooLarge(*c))?;
+            asset.extend_from_slice(&(image_offset as u32).to_le_bytes());
+            asset.extend_from_slice(&(glyph.image.width as u16).to_le_bytes());
+            asset.extend_from_slice(&(glyph.image.height as u16).to_le_bytes());
+            asset.extend_from_slice(&glyph.image_left.to_le_bytes());
+            asset.extend_from_slice(&glyph.image_top.to_le_bytes());
+            asset.extend_from_slice(&(glyph.advance as u16).to_le_bytes());
+            asset.extend_from_slice(&[0, 0]);
+            image_offset += image.len() * 2;
+            images.extend(rle_bytes(&image));
+        }
+        for (left, right, offset) in kerning {
+            asset.extend_from_slice(&(left as u16).to_le_bytes());
+            asset.extend_from_slice(&(right as u16).to_le_bytes());
+            asset.extend_from_slice(&offset.to_le_bytes());
+            asset.extend_from_slice(&[0, 0]);
+        }
+        asset.extend(images);
+        Ok(asset)
+    }
+
+    /// Returns the index of the glyph for a character.
+    pub fn glyph_index(&self, c: char) -> Option<usize> {
+        self.glyphs.binary_search_by_key(&c, |(c, _)| *c).ok()
+    }
+}
+
+/// Returns runs of consecutive characters as (first, last, glyph index).
+fn ranges(glyphs: &[(char, GlyphData)]) -> Vec<(u32, u32, usize)> {
+    let mut ranges: Vec<(u32, u32, usize)> = Vec::new();
+    for (index, (c, _)) in glyphs.iter().enumerate() {
+        let c = *c as u32;
+        match ranges.last_mut() {
+            Some(range) if range.1 + 1 == c => range.1 = c,
+            _ => ranges.push((c, c, index)),
+        }
+    }
+    ranges
+}
+
+/// Parses a character set such as `0x20-0x7e,0xa0-0xff,8364`.
+///
+/// Characters are specified as hexadecimal (with `0x` prefix) or decimal
+/// code points, either alone or as inclusive ranges.
+pub fn parse_char_set(spec: &str) -> Result<Vec<char>, Error> {
+    let code = |s: &str| {
+        let s = s.trim();
+        let value = match s.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => s.parse(),
+        };
+        value
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| Error::Parse(format!("invalid character {:?}", s)))
+    };
+    let mut chars = Vec::new();
+    for item in spec.split(',').filter(|item| !item.trim().is_empty()) {
+        match item.split_once('-') {
+            Some((first, last)) => chars.extend(code(first)?..=code(last)?),
+            None => chars.push(code(item)?),
+        }
+    }
+    chars.sort_unstable();
+    chars.dedup();
+    Ok(chars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bdf, parse_char_set, GlyphData, GlyphFormat};
+    use epd_image::Bitmap;
+
+    pub(crate) const TEST_BDF: &str = "STARTFONT 2.1
+FONT -test-fixed-medium-r-normal--4-40-75-75-c-40-iso10646-1
+SIZE 4 75 75
+FONTBOUNDINGBOX 4 5 0 -1
+STARTPROPERTIES 2
+FONT_ASCENT 4
+FONT_DESCENT 1
+ENDPROPERTIES
+CHARS 3
+STARTCHAR A
+ENCODING 65
+DWIDTH 4 0
+BBX 3 4 0 0
+BITMAP
+40
+A0
+E0
+A0
+ENDCHAR
+STARTCHAR B
+ENCODING 66
+DWIDTH 4 0
+BBX 3 4 0 0
+BITMAP
+C0
+E0
+A0
+C0
+ENDCHAR
+STARTCHAR g
+ENCODING 103
+DWIDTH 4 0
+BBX 2 3 1 -1
+BITMAP
+C0
+40
+80
+ENDCHAR
+ENDFONT
+";
+
+    #[test]
+    fn test_char_set() {
+        let chars = parse_char_set("0x41-0x43, 97,0x41").unwrap();
+        assert_eq!(chars, ['A', 'B', 'C', 'a']);
+        assert!(parse_char_set("0x41-x").is_err());
+    }
+
+    #[test]
+    fn test_to_rust() {
+        let chars = parse_char_set("0x41-0x43,0x67").unwrap();
+        let mut font = bdf::parse(TEST_BDF, &chars).unwrap();
+        font.kerning = vec![('A', 'g', -1), ('A', 'x', -1)];
+        font.replacement = Some('B');
+        let source = font.to_rust("TEST").unwrap();
+        assert!(source.contains("static TEST_GLYPHS: [embedded_epd::gui::font::Glyph; 3]"));
+        assert!(source.contains("0x41..=0x42 => Some((c as u32 - 0x41) as usize),"));
+        assert!(source.contains("0x67 => Some(2),"));
+        assert!(source.contains("replacement: Some(1),"));
+        assert!(source.contains("KerningPair; 1]"));
+        assert!(source.contains("KerningPair { left: 0, right: 2, offset: -1 },"));
+
+        let mut image = Bitmap::new(4, 4);
+        image.set_black(2, 1, true);
+        let glyph = GlyphData {
+            image,
+            image_left: -1,
+            image_top: 4,
+            advance: 4,
+        }
+        .cropped();
+        assert_eq!((glyph.image.width, glyph.image.height), (1, 1));
+        assert_eq!((glyph.image_left, glyph.image_top), (1, 3));
+
+        let source = font
+            .to_rust_with_format("TEST", GlyphFormat::Nibble)
+            .unwrap();
+        assert!(source.contains("GlyphImage::Nibble(embedded_epd::gui::image::NibbleImage {"));
+        let source = font
+            .to_rust_with_format("TEST", GlyphFormat::Heatshrink)
+            .unwrap();
+        assert!(source.contains("            blocks: &[],"));
+    }
+
+    #[test]
+    fn test_to_asset() {
+        use embedded_epd::gui::external::{Container, ENTRY_FONT};
+        use embedded_epd::RowRenderer;
+        use epd_image::{container, Asset};
+        use std::cell::RefCell;
+
+        let chars = parse_char_set("0x41-0x42,0x67").unwrap();
+        let mut font = bdf::parse(TEST_BDF, &chars).unwrap();
+        font.kerning = vec![('A', 'g', -1)];
+        font.replacement = Some('B');
+        let data = container(&[Asset {
+            name: "test".to_string(),
+            kind: ENTRY_FONT,
+            data: font.to_asset().unwrap(),
+        }]);
+        let reader = RefCell::new(&data[..]);
+        let font = Container::open(&reader, 0).unwrap().font("test").unwrap();
+        assert_eq!((font.ascender(), font.descender()), (4, 1));
+        assert!(font.has_glyph('g') && !font.has_glyph('x'));
+        // The kerning pair moves "g" left by one pixel, "x" is replaced.
+        assert_eq!(font.get_text_size("Ag"), (7, 5));
+        assert_eq!(font.get_text_size("x"), (4, 5));
+
+        let rows: Vec<u8> = (0..5)
+            .map(|y| {
+                let mut row = [0xff];
+                let mut renderer = RowRenderer::new(&mut row, 8);
+                let clip = renderer.full_row();
+                font.render_row(&mut renderer, &clip, "Ag", y, 0);
+                !row[0]
+            })
+            .collect();
+        assert_eq!(rows, [0x40, 0xa0, 0xec, 0xa4, 0x08]);
+
+        // Corrupt values do not cause overflows: the ascender, and the glyph
+        // index of the first range, which makes "A" and "B" missing.
+        let mut data = data;
+        let font_offset = 36;
+        data[font_offset..font_offset + 2].copy_from_slice(&[0xff, 0xff]);
+        data[font_offset + 20..font_offset + 22].copy_from_slice(&[0xff, 0xff]);
+        let reader = RefCell::new(&data[..]);
+        let font = Container::open(&reader, 0).unwrap().font("test").unwrap();
+        assert_eq!(font.get_text_size("A").1, 0x10000);
+        assert!(!font.has_glyph('A') && !font.has_glyph('B') && font.has_glyph('g'));
+    }
+}
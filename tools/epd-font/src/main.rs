@@ -0,0 +1,122 @@
+//! Converts TTF, OTF, BDF and PCF fonts into `Font` definitions or font assets
+//! for `ExternalFont`.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use clap::{Parser, ValueEnum};
+use embedded_epd::gui::external::ENTRY_FONT;
+use epd_image::{add_to_container_file, Asset};
+
+use epd_font::{bdf, parse_char_set, pcf, truetype, Error, FontData, GlyphFormat};
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Format {
+    /// 16-bit runs.
+    Rle,
+    /// 4-bit runs, usually the smallest format for small glyphs.
+    Nibble,
+    /// One bit per pixel.
+    Packed,
+    /// Heatshrink compression, for large glyphs.
+    Heatshrink,
+}
+
+#[derive(Parser)]
+#[command(about = "Converts fonts for embedded-epd")]
+struct Args {
+    /// Input font. The format is selected by the file extension.
+    input: PathBuf,
+    /// Output file.
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Name of the generated font static or of the asset in the container.
+    #[arg(short, long, default_value = "FONT")]
+    name: String,
+    /// Size of the em square in pixels (TTF/OTF only).
+    #[arg(short, long, default_value_t = 16.0)]
+    size: f32,
+    /// Characters to include, e.g., "0x20-0x7e,0xa0-0xff".
+    #[arg(short, long, default_value = "0x20-0x7e")]
+    chars: String,
+    /// Additionally include all characters used in a text file.
+    #[arg(long)]
+    text: Option<PathBuf>,
+    /// Character displayed in place of characters missing from the font.
+    #[arg(short, long)]
+    replacement: Option<char>,
+    /// Minimum coverage (0-255) for black pixels (TTF/OTF only).
+    #[arg(short, long, default_value_t = 128)]
+    threshold: u8,
+    /// Storage format of the glyph images.
+    #[arg(short, long, value_enum, default_value = "rle")]
+    format: Format,
+    /// Add the font to an asset container instead of generating Rust code.
+    /// The glyph images are always stored as RLE data.
+    #[arg(long)]
+    container: bool,
+}
+
+fn convert(args: &Args) -> Result<FontData, Box<dyn std::error::Error>> {
+    let mut chars = parse_char_set(&args.chars)?;
+    chars.extend(args.replacement);
+    if let Some(text) = &args.text {
+        chars.extend(
+            fs::read_to_string(text)?
+                .chars()
+                .filter(|c| !c.is_control()),
+        );
+    }
+    chars.sort_unstable();
+    chars.dedup();
+    let data = fs::read(&args.input)?;
+    let extension = args
+        .input
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    let mut font = match extension.as_deref() {
+        Some("ttf") | Some("otf") => truetype::rasterize(&data, args.size, &chars, args.threshold)?,
+        Some("bdf") => bdf::parse(&String::from_utf8_lossy(&data), &chars)?,
+        Some("pcf") => pcf::parse(&data, &chars)?,
+        _ => return Err(Error::Parse("unknown font format".to_string()).into()),
+    };
+    font.replacement = args.replacement;
+    let missing = chars.len() - font.glyphs.len();
+    if missing != 0 {
+        eprintln!("warning: {} characters missing from the font", missing);
+    }
+    Ok(font)
+}
+
+fn main() {
+    let args = Args::parse();
+    let result = convert(&args).and_then(|font| {
+        if args.container {
+            if args.name.len() > 16 {
+                return Err("asset names are limited to 16 bytes".into());
+            }
+            let asset = Asset {
+                name: args.name.clone(),
+                kind: ENTRY_FONT,
+                data: font.to_asset()?,
+            };
+            add_to_container_file(&args.output, asset)?;
+            return Ok(());
+        }
+        let format = match args.format {
+            Format::Rle => GlyphFormat::Rle,
+            Format::Nibble => GlyphFormat::Nibble,
+            Format::Packed => GlyphFormat::Packed,
+            Format::Heatshrink => GlyphFormat::Heatshrink,
+        };
+        let source = font.to_rust_with_format(&args.name, format)?;
+        fs::write(&args.output, source)?;
+        Ok(())
+    });
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
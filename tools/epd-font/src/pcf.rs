@@ -0,0 +1,329 @@
+//! Import of fonts in the X11 Portable Compiled Format.
+
+use epd_image::Bitmap;
+
+use super::{parse_error, Error, FontData, GlyphData};
+
+const PCF_ACCELERATORS: u32 = 1 << 1;
+const PCF_METRICS: u32 = 1 << 2;
+const PCF_BITMAPS: u32 = 1 << 3;
+const PCF_BDF_ENCODINGS: u32 = 1 << 5;
+const PCF_BDF_ACCELERATORS: u32 = 1 << 8;
+
+const PCF_GLYPH_PAD_MASK: u32 = 3;
+const PCF_BYTE_MASK: u32 = 1 << 2;
+const PCF_BIT_MASK: u32 = 1 << 3;
+const PCF_SCAN_UNIT_MASK: u32 = 3 << 4;
+const PCF_COMPRESSED_METRICS: u32 = 0x100;
+
+/// Table within the file, starting with its format field.
+struct Table<'a> {
+    data: &'a [u8],
+    format: u32,
+}
+
+impl<'a> Table<'a> {
+    fn new(data: &'a [u8]) -> Result<Table<'a>, Error> {
+        if data.len() < 4 {
+            return parse_error("truncated table");
+        }
+        let format = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        Ok(Table { data, format })
+    }
+
+    fn big_endian(&self) -> bool {
+        self.format & PCF_BYTE_MASK != 0
+    }
+
+    fn bytes<const N: usize>(&self, offset: usize) -> Result<[u8; N], Error> {
+        match self.data.get(offset..offset + N) {
+            Some(bytes) => {
+                let mut result = [0; N];
+                result.copy_from_slice(bytes);
+                if !self.big_endian() {
+                    result.reverse();
+                }
+                Ok(result)
+            }
+            None => parse_error("truncated table"),
+        }
+    }
+
+    fn u8(&self, offset: usize) -> Result<u8, Error> {
+        Ok(self.bytes::<1>(offset)?[0])
+    }
+
+    fn i16(&self, offset: usize) -> Result<i16, Error> {
+        Ok(i16::from_be_bytes(self.bytes(offset)?))
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.bytes(offset)?))
+    }
+
+    fn i32(&self, offset: usize) -> Result<i32, Error> {
+        Ok(i32::from_be_bytes(self.bytes(offset)?))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.bytes(offset)?))
+    }
+}
+
+struct Metrics {
+    left_bearing: i16,
+    right_bearing: i16,
+    width: i16,
+    ascent: i16,
+    descent: i16,
+}
+
+/// Parses a PCF font and returns the glyphs for the specified characters.
+///
+/// The encoding of the font is assumed to be Unicode (ISO 10646). Characters
+/// missing from the font are skipped.
+pub fn parse(data: &[u8], chars: &[char]) -> Result<FontData, Error> {
+    if data.len() < 8 || &data[0..4] != b"\x01fcp" {
+        return parse_error("not a PCF file");
+    }
+    let count = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+    let mut tables = Vec::new();
+    for i in 0..count {
+        let entry = match data.get(8 + i * 16..24 + i * 16) {
+            Some(entry) => entry,
+            None => return parse_error("truncated table of contents"),
+        };
+        let field = |i: usize| {
+            u32::from_le_bytes([entry[i], entry[i + 1], entry[i + 2], entry[i + 3]]) as usize
+        };
+        let table_data = match data.get(field(12)..field(12) + field(8)) {
+            Some(table_data) => table_data,
+            None => return parse_error("truncated table"),
+        };
+        tables.push((field(0) as u32, Table::new(table_data)?));
+    }
+    let table = |table_type: u32| {
+        tables
+            .iter()
+            .find(|(t, _)| *t == table_type)
+            .map(|(_, table)| table)
+    };
+
+    // The BDF accelerators are more accurate, if present.
+    let accelerators = match table(PCF_BDF_ACCELERATORS).or_else(|| table(PCF_ACCELERATORS)) {
+        Some(table) => table,
+        None => return parse_error("missing accelerator table"),
+    };
+    let (metrics, bitmaps, encodings) = match (
+        table(PCF_METRICS),
+        table(PCF_BITMAPS),
+        table(PCF_BDF_ENCODINGS),
+    ) {
+        (Some(metrics), Some(bitmaps), Some(encodings)) => (metrics, bitmaps, encodings),
+        _ => return parse_error("missing table"),
+    };
+
+    let mut font = FontData {
+        ascender: accelerators.i32(12)?.max(0) as u16,
+        descender: accelerators.i32(16)?.max(0) as u16,
+        glyphs: Vec::new(),
+        kerning: Vec::new(),
+        replacement: None,
+    };
+    for &c in chars {
+        let index = match glyph_index(encodings, c)? {
+            Some(index) => index,
+            None => continue,
+        };
+        let metrics = read_metrics(metrics, index)?;
+        let image = read_bitmap(bitmaps, index, &metrics)?;
+        let glyph = GlyphData {
+            image,
+            image_left: metrics.left_bearing,
+            image_top: metrics.ascent,
+            advance: metrics.width.max(0) as u32,
+        };
+        font.glyphs.push((c, glyph));
+    }
+    Ok(font)
+}
+
+fn glyph_index(encodings: &Table, c: char) -> Result<Option<usize>, Error> {
+    let min_byte2 = encodings.i16(4)? as u32;
+    let max_byte2 = encodings.i16(6)? as u32;
+    let min_byte1 = encodings.i16(8)? as u32;
+    let max_byte1 = encodings.i16(10)? as u32;
+    let (byte1, byte2) = (c as u32 >> 8, c as u32 & 0xff);
+    if byte1 < min_byte1 || byte1 > max_byte1 || byte2 < min_byte2 || byte2 > max_byte2 {
+        return Ok(None);
+    }
+    let entry = (byte1 - min_byte1) * (max_byte2 - min_byte2 + 1) + byte2 - min_byte2;
+    match encodings.u16(14 + entry as usize * 2)? {
+        0xffff => Ok(None),
+        index => Ok(Some(index as usize)),
+    }
+}
+
+fn read_metrics(metrics: &Table, index: usize) -> Result<Metrics, Error> {
+    if metrics.format & PCF_COMPRESSED_METRICS != 0 {
+        let offset = 6 + index * 5;
+        let field = |i: usize| Ok::<i16, Error>(metrics.u8(offset + i)? as i16 - 0x80);
+        Ok(Metrics {
+            left_bearing: field(0)?,
+            right_bearing: field(1)?,
+            width: field(2)?,
+            ascent: field(3)?,
+            descent: field(4)?,
+        })
+    } else {
+        let offset = 8 + index * 12;
+        Ok(Metrics {
+            left_bearing: metrics.i16(offset)?,
+            right_bearing: metrics.i16(offset + 2)?,
+            width: metrics.i16(offset + 4)?,
+            ascent: metrics.i16(offset + 6)?,
+            descent: metrics.i16(offset + 8)?,
+        })
+    }
+}
+
+fn read_bitmap(bitmaps: &Table, index: usize, metrics: &Metrics) -> Result<Bitmap, Error> {
+    let width = (metrics.right_bearing - metrics.left_bearing).max(0) as u32;
+    let height = (metrics.ascent + metrics.descent).max(0) as u32;
+    let count = bitmaps.u32(4)? as usize;
+    let offset = 8 + count * 4 + 16 + bitmaps.u32(8 + index * 4)? as usize;
+    let pad = 1 << (bitmaps.format & PCF_GLYPH_PAD_MASK);
+    let stride = (width as usize).div_ceil(8 * pad) * pad;
+
+    let mut bitmap = Bitmap::new(width, height);
+    for y in 0..height {
+        let start = offset + y as usize * stride;
+        let row = match bitmaps.data.get(start..start + stride) {
+            Some(row) => convert_row(row, bitmaps.format),
+            None => return parse_error("truncated bitmap"),
+        };
+        for x in 0..width {
+            bitmap.set_black(x, y, row[x as usize / 8] & (0x80 >> (x & 7)) != 0);
+        }
+    }
+    Ok(bitmap)
+}
+
+/// Converts a bitmap row into MSB-first bit order. The conversion is its own
+/// inverse.
+fn convert_row(row: &[u8], format: u32) -> Vec<u8> {
+    let msb_bytes = format & PCF_BYTE_MASK != 0;
+    let msb_bits = format & PCF_BIT_MASK != 0;
+    let scan_unit = 1 << ((format & PCF_SCAN_UNIT_MASK) >> 4);
+    let mut row = row.to_vec();
+    if msb_bytes != msb_bits {
+        for unit in row.chunks_mut(scan_unit) {
+            unit.reverse();
+        }
+    }
+    if !msb_bits {
+        for byte in row.iter_mut() {
+            *byte = byte.reverse_bits();
+        }
+    }
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        convert_row, parse, PCF_BDF_ACCELERATORS, PCF_BDF_ENCODINGS, PCF_BITMAPS, PCF_BYTE_MASK,
+        PCF_COMPRESSED_METRICS, PCF_METRICS,
+    };
+
+    /// Writes a font with the glyphs "A" (3x2) and "g" (2x3, one row below
+    /// the baseline).
+    fn write_pcf(format: u32) -> Vec<u8> {
+        let big_endian = format & PCF_BYTE_MASK != 0;
+        let push = |table: &mut Vec<u8>, bytes: &[u8]| {
+            if big_endian {
+                table.extend(bytes.iter());
+            } else {
+                table.extend(bytes.iter().rev());
+            }
+        };
+
+        let mut accelerators = format.to_le_bytes().to_vec();
+        accelerators.extend([0; 8].iter());
+        push(&mut accelerators, &3i32.to_be_bytes());
+        push(&mut accelerators, &1i32.to_be_bytes());
+
+        let mut metrics = (format | PCF_COMPRESSED_METRICS).to_le_bytes().to_vec();
+        push(&mut metrics, &2u16.to_be_bytes());
+        metrics.extend([0x80, 0x83, 0x84, 0x82, 0x80].iter());
+        metrics.extend([0x81, 0x83, 0x84, 0x82, 0x81].iter());
+
+        let pad = 1 << (format & 3);
+        let rows: [&[u8]; 5] = [&[0x40], &[0xe0], &[0xc0], &[0x40], &[0x80]];
+        let mut bitmaps = format.to_le_bytes().to_vec();
+        push(&mut bitmaps, &2u32.to_be_bytes());
+        push(&mut bitmaps, &0u32.to_be_bytes());
+        push(&mut bitmaps, &(2 * pad as u32).to_be_bytes());
+        bitmaps.extend([0; 16].iter());
+        for row in rows.iter() {
+            let mut padded = row.to_vec();
+            padded.resize(pad, 0);
+            bitmaps.extend(convert_row(&padded, format));
+        }
+
+        let mut encodings = format.to_le_bytes().to_vec();
+        for value in [0x41i16, 0x67, 0, 0, 0].iter() {
+            push(&mut encodings, &value.to_be_bytes());
+        }
+        for c in 0x41..=0x67 {
+            let index: u16 = match c {
+                0x41 => 0,
+                0x67 => 1,
+                _ => 0xffff,
+            };
+            push(&mut encodings, &index.to_be_bytes());
+        }
+
+        let tables = [
+            (PCF_BDF_ACCELERATORS, accelerators),
+            (PCF_METRICS, metrics),
+            (PCF_BITMAPS, bitmaps),
+            (PCF_BDF_ENCODINGS, encodings),
+        ];
+        let mut data = b"\x01fcp".to_vec();
+        data.extend(&(tables.len() as u32).to_le_bytes());
+        let mut offset = 8 + tables.len() * 16;
+        for (table_type, table) in tables.iter() {
+            data.extend(&table_type.to_le_bytes());
+            data.extend(&table[..4]);
+            data.extend(&(table.len() as u32).to_le_bytes());
+            data.extend(&(offset as u32).to_le_bytes());
+            offset += table.len();
+        }
+        for (_, table) in tables.iter() {
+            data.extend(table);
+        }
+        data
+    }
+
+    #[test]
+    fn test_pcf() {
+        // MSB first with 4-byte padding, LSB first, and mixed byte and bit
+        // order with a 4-byte scan unit.
+        for &format in [0x0e, 0x02, 0x2a].iter() {
+            let font = parse(&write_pcf(format), &['A', 'B', 'g']).unwrap();
+            assert_eq!((font.ascender, font.descender), (3, 1));
+            assert_eq!(font.glyphs.len(), 2);
+            let (c, a) = &font.glyphs[0];
+            assert_eq!(*c, 'A');
+            assert_eq!((a.image.width, a.image.height), (3, 2));
+            assert_eq!(a.image.data, [0xbf, 0x1f]);
+            assert_eq!((a.image_left, a.image_top, a.advance), (0, 2, 4));
+            let (c, g) = &font.glyphs[1];
+            assert_eq!(*c, 'g');
+            assert_eq!((g.image.width, g.image.height), (2, 3));
+            assert_eq!(g.image.data, [0x3f, 0xbf, 0x7f]);
+            assert_eq!((g.image_left, g.image_top, g.advance), (1, 2, 4));
+        }
+    }
+}
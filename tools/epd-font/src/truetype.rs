@@ -0,0 +1,236 @@
+//! Rasterization of TrueType and OpenType fonts.
+
+use ab_glyph::{Font, FontRef, ScaleFont};
+use epd_image::Bitmap;
+use std::collections::{BTreeMap, BTreeSet};
+use ttf_parser::gpos::{PairAdjustment, PositioningSubtable};
+use ttf_parser::kern::Format;
+use ttf_parser::opentype_layout::ClassDefinition;
+use ttf_parser::{Face, GlyphId, Tag};
+
+use super::{parse_error, Error, FontData, GlyphData};
+
+/// Rasterizes the specified characters of a TTF/OTF font.
+///
+/// `size` is the height of the em square in pixels. Pixels with a coverage of
+/// at least `threshold` (0 to 255) become black. Characters missing from the
+/// font are skipped.
+///
+/// Kerning pairs are taken from the `kern` feature of the GPOS table or, if
+/// the font has no GPOS table, from the `kern` table. Ligatures (the `liga`
+/// feature of the GSUB table) are not converted, as `Font` always displays
+/// one glyph per character.
+pub fn rasterize(data: &[u8], size: f32, chars: &[char], threshold: u8) -> Result<FontData, Error> {
+    let (font, face) = match (FontRef::try_from_slice(data), Face::parse(data, 0)) {
+        (Ok(font), Ok(face)) => (font, face),
+        _ => return parse_error("invalid TrueType/OpenType font"),
+    };
+    // ab_glyph scales fonts by their line height instead of the em size.
+    let units_per_em = font.units_per_em().unwrap_or(1000.0);
+    let font = font.as_scaled(size * font.height_unscaled() / units_per_em);
+    let threshold = threshold as f32 / 255.0;
+
+    let mut result = FontData {
+        ascender: font.ascent().ceil().max(0.0) as u16,
+        descender: (-font.descent()).ceil().max(0.0) as u16,
+        glyphs: Vec::new(),
+        kerning: Vec::new(),
+        replacement: None,
+    };
+    for &c in chars {
+        let id = font.glyph_id(c);
+        if id.0 == 0 {
+            continue;
+        }
+        let advance = font.h_advance(id).round().max(0.0) as u32;
+        let glyph = match font.outline_glyph(id.with_scale(font.scale())) {
+            Some(outline) => {
+                let bounds = outline.px_bounds();
+                let mut image = Bitmap::new(bounds.width() as u32, bounds.height() as u32);
+                outline.draw(|x, y, coverage| {
+                    if coverage >= threshold {
+                        image.set_black(x, y, true);
+                    }
+                });
+                GlyphData {
+                    image,
+                    image_left: bounds.min.x as i16,
+                    image_top: -bounds.min.y as i16,
+                    advance,
+                }
+            }
+            // Whitespace has no outline.
+            None => GlyphData {
+                image: Bitmap::new(0, 0),
+                image_left: 0,
+                image_top: 0,
+                advance,
+            },
+        };
+        result.glyphs.push((c, glyph));
+    }
+
+    // Several characters can share a glyph.
+    let mut chars: BTreeMap<GlyphId, Vec<char>> = BTreeMap::new();
+    for (c, _) in result.glyphs.iter() {
+        chars
+            .entry(GlyphId(font.glyph_id(*c).0))
+            .or_default()
+            .push(*c);
+    }
+    let glyphs: Vec<GlyphId> = chars.keys().copied().collect();
+    let pairs = match kern_lookups(&face) {
+        Some(lookups) => gpos_kerning(&face, &lookups, &glyphs),
+        None => kern_table_kerning(&face, &glyphs),
+    };
+    for ((left, right), units) in pairs {
+        let offset = (units as f32 * size / units_per_em).round() as i16;
+        if offset == 0 {
+            continue;
+        }
+        for &left in chars[&left].iter() {
+            for &right in chars[&right].iter() {
+                result.kerning.push((left, right, offset));
+            }
+        }
+    }
+    result.kerning.sort_unstable();
+    Ok(result)
+}
+
+/// Returns the indices of the GPOS lookups used by the `kern` feature, or
+/// `None` if the font has no GPOS table.
+fn kern_lookups(face: &Face) -> Option<Vec<u16>> {
+    let gpos = face.tables().gpos?;
+    let mut lookups: Vec<u16> = gpos
+        .features
+        .into_iter()
+        .filter(|feature| feature.tag == Tag::from_bytes(b"kern"))
+        .flat_map(|feature| feature.lookup_indices.into_iter())
+        .collect();
+    lookups.sort_unstable();
+    lookups.dedup();
+    Some(lookups)
+}
+
+/// Sums the horizontal advance adjustments of all pair adjustment lookups for
+/// the pairs of `glyphs`. Within a lookup, only the first subtable covering a
+/// pair applies.
+fn gpos_kerning(
+    face: &Face,
+    lookups: &[u16],
+    glyphs: &[GlyphId],
+) -> BTreeMap<(GlyphId, GlyphId), i32> {
+    let mut kerning = BTreeMap::new();
+    let gpos = match face.tables().gpos {
+        Some(gpos) => gpos,
+        None => return kerning,
+    };
+    for lookup in lookups.iter().filter_map(|&index| gpos.lookups.get(index)) {
+        let mut pairs = BTreeMap::new();
+        // Left glyphs for which a class-based subtable applies to all pairs.
+        let mut done = BTreeSet::new();
+        for subtable in lookup.subtables.into_iter::<PositioningSubtable>() {
+            let pair = match subtable {
+                PositioningSubtable::Pair(pair) => pair,
+                _ => continue,
+            };
+            let covered = glyphs
+                .iter()
+                .copied()
+                .filter(|&left| !done.contains(&left) && pair.coverage().contains(left));
+            match pair {
+                PairAdjustment::Format1 { coverage, sets } => {
+                    // The pair sets can only be searched, but only the sets
+                    // of the covered glyphs have to be.
+                    for left in covered {
+                        let set = match coverage.get(left).and_then(|index| sets.get(index)) {
+                            Some(set) => set,
+                            None => continue,
+                        };
+                        for &right in glyphs {
+                            if let Some((value, _)) = set.get(right) {
+                                pairs.entry((left, right)).or_insert(value.x_advance as i32);
+                            }
+                        }
+                    }
+                }
+                PairAdjustment::Format2 {
+                    classes, matrix, ..
+                } => {
+                    // Every pair of classes is only read once.
+                    let lefts = group_by_class(classes.0, covered);
+                    let rights = group_by_class(classes.1, glyphs.iter().copied());
+                    for (&left_class, lefts) in lefts.iter() {
+                        for (&right_class, rights) in rights.iter() {
+                            let value = match matrix.get((left_class, right_class)) {
+                                Some((value, _)) => value.x_advance as i32,
+                                None => continue,
+                            };
+                            for &left in lefts.iter() {
+                                for &right in rights.iter() {
+                                    pairs.entry((left, right)).or_insert(value);
+                                }
+                            }
+                        }
+                        done.extend(lefts.iter().copied());
+                    }
+                }
+            }
+        }
+        for (pair, value) in pairs {
+            *kerning.entry(pair).or_insert(0) += value;
+        }
+    }
+    kerning
+}
+
+fn group_by_class<I: Iterator<Item = GlyphId>>(
+    classes: ClassDefinition,
+    glyphs: I,
+) -> BTreeMap<u16, Vec<GlyphId>> {
+    let mut groups: BTreeMap<u16, Vec<GlyphId>> = BTreeMap::new();
+    for glyph in glyphs {
+        groups.entry(classes.get(glyph)).or_default().push(glyph);
+    }
+    groups
+}
+
+/// Sums the kerning values of all horizontal subtables of the `kern` table
+/// for the pairs of `glyphs`.
+fn kern_table_kerning(face: &Face, glyphs: &[GlyphId]) -> BTreeMap<(GlyphId, GlyphId), i32> {
+    let mut kerning = BTreeMap::new();
+    let kern = match face.tables().kern {
+        Some(kern) => kern,
+        None => return kerning,
+    };
+    let known: BTreeSet<GlyphId> = glyphs.iter().copied().collect();
+    let subtables = kern
+        .subtables
+        .into_iter()
+        .filter(|subtable| subtable.horizontal && !subtable.variable && !subtable.has_cross_stream);
+    for subtable in subtables {
+        match &subtable.format {
+            // Format 0 lists the pairs.
+            Format::Format0(pairs) => {
+                for pair in pairs.pairs {
+                    if known.contains(&pair.left()) && known.contains(&pair.right()) {
+                        *kerning.entry((pair.left(), pair.right())).or_insert(0) +=
+                            pair.value as i32;
+                    }
+                }
+            }
+            // The class-based formats of Apple fonts can only be searched.
+            _ => {
+                for &left in glyphs {
+                    for &right in glyphs {
+                        if let Some(value) = subtable.glyphs_kerning(left, right) {
+                            *kerning.entry((left, right)).or_insert(0) += value as i32;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    kerning
+}
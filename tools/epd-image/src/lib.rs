@@ -0,0 +1,745 @@
+//! Conversion of images into the formats used by `embedded-epd`.
+//!
+//! Images are first converted into a `Bitmap` with one bit per pixel, which
+//! can then be encoded for `BitmapImage`, `RLEImage`, `NibbleImage`,
+//! `PackedImage` or `HeatshrinkImage` and written either as Rust source code
+//! or as a binary blob. Bitmaps and RLE images can also be stored in an
+//! asset container for `embedded_epd::gui::external`.
+
+use std::fmt::Write;
+
+use embedded_epd::gui::external::{CONTAINER_MAGIC, CONTAINER_VERSION, IMAGE_BITMAP, IMAGE_RLE};
+use embedded_epd::gui::image::{HEATSHRINK_LOOKAHEAD_BITS, HEATSHRINK_WINDOW_BITS};
+use embedded_epd::{BitOrder, Color, Dither, ErrorBuffer, Polarity, RowRenderer};
+
+/// Image with one bit per pixel in the format of the display rows, i.e., the
+/// leftmost pixel is in the most significant bit and set bits are white.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Bitmap {
+    pub fn new(width: u32, height: u32) -> Bitmap {
+        Bitmap {
+            width,
+            height,
+            data: vec![0xff; width.div_ceil(8) as usize * height as usize],
+        }
+    }
+
+    pub fn stride(&self) -> usize {
+        self.width.div_ceil(8) as usize
+    }
+
+    pub fn row(&self, y: u32) -> &[u8] {
+        let start = y as usize * self.stride();
+        &self.data[start..start + self.stride()]
+    }
+
+    pub fn is_black(&self, x: u32, y: u32) -> bool {
+        self.row(y)[x as usize / 8] & (0x80 >> (x & 7)) == 0
+    }
+
+    pub fn set_black(&mut self, x: u32, y: u32, black: bool) {
+        let index = y as usize * self.stride() + x as usize / 8;
+        let mask = 0x80 >> (x & 7);
+        if black {
+            self.data[index] &= !mask;
+        } else {
+            self.data[index] |= mask;
+        }
+    }
+}
+
+/// Method used to reduce grayscale images to black and white.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Conversion {
+    /// Pixels with a luminance below the threshold are black.
+    Threshold(u8),
+    Bayer,
+    FloydSteinberg,
+    Atkinson,
+}
+
+/// Converts a grayscale image with one byte per pixel to a bitmap.
+///
+/// The dithering algorithms are the ones used by `RowRenderer`, so converted
+/// images look the same as images dithered on the device.
+pub fn convert(luminance: &[u8], width: u32, height: u32, conversion: Conversion) -> Bitmap {
+    assert_eq!(luminance.len(), width as usize * height as usize);
+    let mut bitmap = Bitmap::new(width, height);
+    let stride = bitmap.stride();
+    let rows = match conversion {
+        Conversion::Atkinson => 3,
+        _ => 2,
+    };
+    let mut errors = vec![0i16; width as usize * rows];
+    let mut dither = match conversion {
+        Conversion::Threshold(_) => Dither::Threshold,
+        Conversion::Bayer => Dither::Bayer,
+        Conversion::FloydSteinberg => Dither::FloydSteinberg(ErrorBuffer::new(&mut errors, width)),
+        Conversion::Atkinson => Dither::Atkinson(ErrorBuffer::new(&mut errors, width)),
+    };
+    for (y, row) in bitmap.data.chunks_mut(stride).enumerate() {
+        let source = &luminance[y * width as usize..(y + 1) * width as usize];
+        if let Conversion::Threshold(threshold) = conversion {
+            for (x, &value) in source.iter().enumerate() {
+                if value < threshold {
+                    row[x / 8] &= !(0x80 >> (x & 7));
+                }
+            }
+            continue;
+        }
+        let mut renderer = RowRenderer::new(row, width);
+        renderer.set_y(y as i32);
+        let clip = renderer.full_row();
+        renderer.render_gray(&clip, 0, width as i32, source, &mut dither);
+    }
+    bitmap
+}
+
+/// Encodes a bitmap for `BitmapImage` with rows padded to whole bytes.
+pub fn encode_bitmap(bitmap: &Bitmap, order: BitOrder, polarity: Polarity) -> Vec<u8> {
+    let mut data = vec![0u8; bitmap.data.len()];
+    let stride = bitmap.stride();
+    for y in 0..bitmap.height {
+        for x in 0..bitmap.width {
+            let set = match polarity {
+                Polarity::OneIsWhite => !bitmap.is_black(x, y),
+                Polarity::OneIsBlack => bitmap.is_black(x, y),
+            };
+            if set {
+                let bit = match order {
+                    BitOrder::MsbFirst => 0x80 >> (x & 7),
+                    BitOrder::LsbFirst => 1 << (x & 7),
+                };
+                data[y as usize * stride + x as usize / 8] |= bit;
+            }
+        }
+    }
+    data
+}
+
+/// Error returned if an image is too large for the RLE format.
+#[derive(Debug, PartialEq)]
+pub struct TooLarge;
+
+/// Encodes a bitmap for `RLEImage`.
+///
+/// The data starts with a table of `height + 1` offsets, so row `y` consists
+/// of the entries from `data[y]` to `data[y + 1]`. Every entry of a row is a
+/// run with the length in the lower 15 bits and the most significant bit set
+/// for black pixels. Runs of white pixels at the end of a row are omitted.
+pub fn encode_rle(bitmap: &Bitmap) -> Result<Vec<u16>, TooLarge> {
+    if bitmap.width > 0xffff || bitmap.height > 0xffff {
+        return Err(TooLarge);
+    }
+    let mut data = vec![0u16; bitmap.height as usize + 1];
+    for y in 0..bitmap.height {
+        data[y as usize] = offset(data.len())?;
+        let mut x = 0;
+        while x < bitmap.width {
+            let black = bitmap.is_black(x, y);
+            let mut length = 1;
+            while x + length < bitmap.width
+                && bitmap.is_black(x + length, y) == black
+                && length < 0x7fff
+            {
+                length += 1;
+            }
+            let trailing_white = !black && x + length == bitmap.width;
+            if !trailing_white {
+                data.push(length as u16 | if black { 0x8000 } else { 0 });
+            }
+            x += length;
+        }
+    }
+    data[bitmap.height as usize] = offset(data.len())?;
+    Ok(data)
+}
+
+fn offset(index: usize) -> Result<u16, TooLarge> {
+    if index > 0xffff {
+        Err(TooLarge)
+    } else {
+        Ok(index as u16)
+    }
+}
+
+/// Decodes data created by `encode_rle()`.
+pub fn decode_rle(data: &[u16], width: u32, height: u32) -> Bitmap {
+    let mut bitmap = Bitmap::new(width, height);
+    let stride = bitmap.stride();
+    for (y, row) in bitmap.data.chunks_mut(stride).enumerate() {
+        let runs = &data[data[y] as usize..data[y + 1] as usize];
+        let mut renderer = RowRenderer::new(row, width);
+        let clip = renderer.full_row();
+        let mut x = 0;
+        for run in runs {
+            let length = (run & 0x7fff) as i32;
+            if run & 0x8000 != 0 {
+                renderer.fill(&clip, x, x + length, Color::Black);
+            }
+            x += length;
+        }
+    }
+    bitmap
+}
+
+/// Encodes a bitmap for `NibbleImage`.
+pub fn encode_nibble(bitmap: &Bitmap) -> Vec<u8> {
+    let mut nibbles = Vec::new();
+    let mut black = false;
+    let mut length = 0;
+    for y in 0..bitmap.height {
+        for x in 0..bitmap.width {
+            if bitmap.is_black(x, y) != black {
+                push_nibble_run(&mut nibbles, length);
+                black = !black;
+                length = 0;
+            }
+            length += 1;
+        }
+    }
+    if black {
+        push_nibble_run(&mut nibbles, length);
+    }
+    nibbles
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair.get(1).cloned().unwrap_or(0))
+        .collect()
+}
+
+fn push_nibble_run(nibbles: &mut Vec<u8>, mut length: u32) {
+    while length >= 15 {
+        nibbles.push(15);
+        length -= 15;
+    }
+    nibbles.push(length as u8);
+}
+
+/// Encodes a bitmap for `PackedImage`.
+pub fn encode_packed(bitmap: &Bitmap) -> Vec<u8> {
+    let pixels = bitmap.width as usize * bitmap.height as usize;
+    let mut data = vec![0u8; pixels.div_ceil(8)];
+    for y in 0..bitmap.height {
+        for x in 0..bitmap.width {
+            if bitmap.is_black(x, y) {
+                let bit = y as usize * bitmap.width as usize + x as usize;
+                data[bit / 8] |= 0x80 >> (bit & 7);
+            }
+        }
+    }
+    data
+}
+
+/// Encodes a bitmap for `HeatshrinkImage`.
+///
+/// Returns the compressed data and the offsets of the blocks of `block_rows`
+/// rows. If the image is not higher than `block_rows`, it is compressed as a
+/// single block and no offsets are returned.
+pub fn encode_heatshrink(bitmap: &Bitmap, block_rows: u32) -> (Vec<u8>, Vec<u32>) {
+    let black_bits = encode_bitmap(bitmap, BitOrder::MsbFirst, Polarity::OneIsBlack);
+    if bitmap.height <= block_rows {
+        return (heatshrink_compress(&black_bits), Vec::new());
+    }
+    let mut data = Vec::new();
+    let mut blocks = Vec::new();
+    for block in black_bits.chunks(block_rows as usize * bitmap.stride()) {
+        blocks.push(data.len() as u32);
+        data.extend(heatshrink_compress(block));
+    }
+    (data, blocks)
+}
+
+/// Compresses data in the Heatshrink format used by `HeatshrinkImage`.
+pub fn heatshrink_compress(data: &[u8]) -> Vec<u8> {
+    let window = 1 << HEATSHRINK_WINDOW_BITS;
+    let lookahead = 1 << HEATSHRINK_LOOKAHEAD_BITS;
+    let mut output = BitWriter::default();
+    let mut pos = 0;
+    while pos < data.len() {
+        // Longest match within the window. Matches may overlap the current
+        // position.
+        let mut best = (0, 0);
+        for distance in 1..=window.min(pos) {
+            let length = (0..lookahead.min(data.len() - pos))
+                .take_while(|&i| data[pos + i] == data[pos + i - distance])
+                .count();
+            if length > best.1 {
+                best = (distance, length);
+            }
+        }
+        // A back-reference is shorter than two literals.
+        if best.1 >= 2 {
+            output.write(0, 1);
+            output.write(best.0 as u32 - 1, HEATSHRINK_WINDOW_BITS);
+            output.write(best.1 as u32 - 1, HEATSHRINK_LOOKAHEAD_BITS);
+            pos += best.1;
+        } else {
+            output.write(1, 1);
+            output.write(data[pos] as u32, 8);
+            pos += 1;
+        }
+    }
+    output.data
+}
+
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        for i in (0..bits).rev() {
+            if self.bits.is_multiple_of(8) {
+                self.data.push(0);
+            }
+            if value & (1 << i) != 0 {
+                *self.data.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+    }
+}
+
+/// Returns Rust source code defining a `BitmapImage` static.
+pub fn bitmap_source(name: &str, bitmap: &Bitmap, order: BitOrder, polarity: Polarity) -> String {
+    let data = encode_bitmap(bitmap, order, polarity);
+    let mut source = String::new();
+    writeln!(
+        source,
+        "pub static {}: embedded_epd::gui::image::BitmapImage =",
+        name
+    )
+    .unwrap();
+    writeln!(source, "    embedded_epd::gui::image::BitmapImage::new(").unwrap();
+    write_array(&mut source, "        ", "&", &data, |v| {
+        format!("0x{:02x}", v)
+    });
+    writeln!(source, "        {},", bitmap.width).unwrap();
+    writeln!(source, "        {},", bitmap.height).unwrap();
+    writeln!(source, "        {},", bitmap.stride()).unwrap();
+    writeln!(source, "        embedded_epd::BitOrder::{:?},", order).unwrap();
+    writeln!(source, "        embedded_epd::Polarity::{:?},", polarity).unwrap();
+    writeln!(source, "    );").unwrap();
+    source
+}
+
+/// Returns Rust source code defining an `RLEImage` static.
+pub fn rle_source(name: &str, bitmap: &Bitmap) -> Result<String, TooLarge> {
+    let data = encode_rle(bitmap)?;
+    let mut source = String::new();
+    writeln!(
+        source,
+        "pub static {}: embedded_epd::gui::image::RLEImage = embedded_epd::gui::image::RLEImage {{",
+        name
+    )
+    .unwrap();
+    write_array(&mut source, "    ", "data: &", &data, |v| {
+        format!("0x{:04x}", v)
+    });
+    writeln!(source, "    width: {},", bitmap.width).unwrap();
+    writeln!(source, "    height: {},", bitmap.height).unwrap();
+    writeln!(source, "}};").unwrap();
+    Ok(source)
+}
+
+/// Returns Rust source code defining a `NibbleImage` or `PackedImage` static.
+pub fn byte_image_source(name: &str, image_type: &str, data: &[u8], bitmap: &Bitmap) -> String {
+    let mut source = String::new();
+    writeln!(
+        source,
+        "pub static {}: embedded_epd::gui::image::{} = embedded_epd::gui::image::{} {{",
+        name, image_type, image_type
+    )
+    .unwrap();
+    write_array(&mut source, "    ", "data: &", data, |v| {
+        format!("0x{:02x}", v)
+    });
+    writeln!(source, "    width: {},", bitmap.width).unwrap();
+    writeln!(source, "    height: {},", bitmap.height).unwrap();
+    writeln!(source, "}};").unwrap();
+    source
+}
+
+/// Returns Rust source code defining a `HeatshrinkImage` static.
+pub fn heatshrink_source(name: &str, bitmap: &Bitmap, block_rows: u32) -> String {
+    let (data, blocks) = encode_heatshrink(bitmap, block_rows);
+    let mut source = String::new();
+    writeln!(
+        source,
+        "pub static {}: embedded_epd::gui::image::HeatshrinkImage = embedded_epd::gui::image::HeatshrinkImage {{",
+        name
+    )
+    .unwrap();
+    write_array(&mut source, "    ", "data: &", &data, |v| {
+        format!("0x{:02x}", v)
+    });
+    write_array(&mut source, "    ", "blocks: &", &blocks, |v| v.to_string());
+    writeln!(source, "    block_rows: {},", block_rows.min(bitmap.height)).unwrap();
+    writeln!(source, "    width: {},", bitmap.width).unwrap();
+    writeln!(source, "    height: {},", bitmap.height).unwrap();
+    writeln!(source, "}};").unwrap();
+    source
+}
+
+/// Returns the RLE data as little-endian binary blob.
+pub fn rle_bytes(data: &[u16]) -> Vec<u8> {
+    data.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Encodes a bitmap as an image asset for `ExternalImage`, either as bitmap
+/// with set bits for black pixels or as RLE data.
+pub fn image_asset(bitmap: &Bitmap, rle: bool) -> Result<Vec<u8>, TooLarge> {
+    if bitmap.width > 0xffff || bitmap.height > 0xffff {
+        return Err(TooLarge);
+    }
+    let (format, data) = if rle {
+        (IMAGE_RLE, rle_bytes(&encode_rle(bitmap)?))
+    } else {
+        let data = encode_bitmap(bitmap, BitOrder::MsbFirst, Polarity::OneIsBlack);
+        (IMAGE_BITMAP, data)
+    };
+    let mut asset = vec![format, 0];
+    asset.extend_from_slice(&(bitmap.width as u16).to_le_bytes());
+    asset.extend_from_slice(&(bitmap.height as u16).to_le_bytes());
+    asset.extend_from_slice(&[0, 0]);
+    asset.extend_from_slice(&data);
+    Ok(asset)
+}
+
+/// Entry of an asset container.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Asset {
+    /// Name with at most 16 bytes.
+    pub name: String,
+    /// `ENTRY_IMAGE` or `ENTRY_FONT`.
+    pub kind: u8,
+    pub data: Vec<u8>,
+}
+
+/// Creates an asset container for `embedded_epd::gui::external::Container`.
+///
+/// # Panics
+///
+/// Panics if a name is longer than 16 bytes.
+pub fn container(assets: &[Asset]) -> Vec<u8> {
+    let mut data = CONTAINER_MAGIC.to_vec();
+    data.extend_from_slice(&CONTAINER_VERSION.to_le_bytes());
+    data.extend_from_slice(&(assets.len() as u16).to_le_bytes());
+    let mut offset = 8 + 28 * assets.len();
+    for asset in assets {
+        assert!(asset.name.len() <= 16, "asset name too long");
+        let mut name = [0u8; 16];
+        name[..asset.name.len()].copy_from_slice(asset.name.as_bytes());
+        data.extend_from_slice(&name);
+        data.extend_from_slice(&[asset.kind, 0, 0, 0]);
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+        data.extend_from_slice(&(asset.data.len() as u32).to_le_bytes());
+        // Assets are aligned to four bytes.
+        offset += (asset.data.len() + 3) & !3;
+    }
+    for asset in assets {
+        data.extend_from_slice(&asset.data);
+        data.resize((data.len() + 3) & !3, 0);
+    }
+    data
+}
+
+/// Returns the assets of a container created by `container()`, or `None` if
+/// the data is not a valid container.
+pub fn parse_container(data: &[u8]) -> Option<Vec<Asset>> {
+    let u32_at = |pos: usize| -> Option<usize> {
+        let bytes = data.get(pos..pos + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    };
+    if data.get(..4)? != CONTAINER_MAGIC || data.get(4..6)? != CONTAINER_VERSION.to_le_bytes() {
+        return None;
+    }
+    let count = u16::from_le_bytes([data[6], data[7]]) as usize;
+    (0..count)
+        .map(|i| {
+            let entry = 8 + i * 28;
+            let name = data.get(entry..entry + 16)?;
+            let length = name.iter().position(|&b| b == 0).unwrap_or(16);
+            let (offset, size) = (u32_at(entry + 20)?, u32_at(entry + 24)?);
+            Some(Asset {
+                name: String::from_utf8(name[..length].to_vec()).ok()?,
+                kind: *data.get(entry + 16)?,
+                data: data.get(offset..offset + size)?.to_vec(),
+            })
+        })
+        .collect()
+}
+
+/// Adds an asset to a container file, replacing any asset with the same name
+/// and kind. A new container is created if the file does not exist.
+pub fn add_to_container_file(path: &std::path::Path, asset: Asset) -> std::io::Result<()> {
+    let mut assets = match std::fs::read(path) {
+        Ok(data) => parse_container(&data).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "not an asset container")
+        })?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    assets.retain(|other| other.name != asset.name || other.kind != asset.kind);
+    assets.push(asset);
+    std::fs::write(path, container(&assets))
+}
+
+fn write_array<T, F>(source: &mut String, indent: &str, prefix: &str, data: &[T], format: F)
+where
+    F: Fn(&T) -> String,
+{
+    writeln!(source, "{}{}[", indent, prefix).unwrap();
+    for chunk in data.chunks(12) {
+        let values: Vec<String> = chunk.iter().map(&format).collect();
+        writeln!(source, "{}    {},", indent, values.join(", ")).unwrap();
+    }
+    writeln!(source, "{}],", indent).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        convert, decode_rle, encode_bitmap, encode_heatshrink, encode_nibble, encode_packed,
+        encode_rle, Bitmap, Conversion,
+    };
+    use embedded_epd::gui::image::{
+        BitmapImage, HeatshrinkImage, NibbleImage, PackedImage, RLEImage, RowImage,
+    };
+    use embedded_epd::{BitOrder, Polarity, RowRenderer};
+
+    fn random_bitmap(width: u32, height: u32, seed: u32) -> Bitmap {
+        // Random runs of pixels.
+        let mut bitmap = Bitmap::new(width, height);
+        let mut state = seed;
+        let mut black = false;
+        for y in 0..height {
+            for x in 0..width {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                if (state >> 16).is_multiple_of(5) {
+                    black = !black;
+                }
+                bitmap.set_black(x, y, black);
+            }
+        }
+        bitmap
+    }
+
+    /// Renders the image with the renderer of `embedded-epd`.
+    fn render<F>(width: u32, height: u32, render_row: F) -> Bitmap
+    where
+        F: Fn(&mut RowRenderer, i32),
+    {
+        let mut bitmap = Bitmap::new(width, height);
+        let stride = bitmap.stride();
+        for (y, row) in bitmap.data.chunks_mut(stride).enumerate() {
+            let mut renderer = RowRenderer::new(row, width);
+            render_row(&mut renderer, y as i32);
+        }
+        bitmap
+    }
+
+    #[test]
+    fn test_rle() {
+        let mut glyph = Bitmap::new(3, 3);
+        for &(x, y) in [(0, 0), (1, 0), (2, 0), (1, 1), (0, 2), (1, 2), (2, 2)].iter() {
+            glyph.set_black(x, y, true);
+        }
+        assert_eq!(
+            encode_rle(&glyph),
+            Ok(vec![4, 5, 7, 8, 0x8003, 0x0001, 0x8001, 0x8003])
+        );
+
+        // Runs longer than 15 bits are split.
+        let mut wide = Bitmap::new(40000, 1);
+        for x in 0..40000 {
+            wide.set_black(x, 0, true);
+        }
+        assert_eq!(encode_rle(&wide), Ok(vec![2, 4, 0xffff, 0x8000 | 7233]));
+
+        for &(width, height) in [(1, 1), (13, 7), (64, 32), (40000, 2)].iter() {
+            let bitmap = random_bitmap(width, height, width + height);
+            let data = encode_rle(&bitmap).unwrap();
+            assert_eq!(decode_rle(&data, width, height), bitmap);
+
+            let image: &'static RLEImage = Box::leak(Box::new(RLEImage {
+                data: Box::leak(data.into_boxed_slice()),
+                width: width as u16,
+                height: height as u16,
+            }));
+            let rendered = render(width, height, |row, y| {
+                let clip = row.full_row();
+                image.render_row_transparent(row, &clip, y, 0);
+            });
+            assert_eq!(rendered, bitmap);
+        }
+    }
+
+    /// Checks that the image renders the bitmap, also when scaled.
+    fn check_image<I: RowImage>(image: &I, bitmap: &Bitmap) {
+        let (width, height) = (bitmap.width, bitmap.height);
+        let rendered = render(width, height, |row, y| {
+            let clip = row.full_row();
+            image.render_row_transparent(row, &clip, y, 0);
+        });
+        assert_eq!(rendered, bitmap.clone());
+
+        let rendered = render(width * 2, height * 2, |row, y| {
+            let clip = row.full_row();
+            image.render_row_scaled(row, &clip, y, 0, 2, embedded_epd::Color::Black);
+        });
+        for y in 0..height * 2 {
+            for x in 0..width * 2 {
+                assert_eq!(rendered.is_black(x, y), bitmap.is_black(x / 2, y / 2));
+            }
+        }
+    }
+
+    fn leak<T>(data: Vec<T>) -> &'static [T] {
+        Box::leak(data.into_boxed_slice())
+    }
+
+    #[test]
+    fn test_compact_formats() {
+        let mut glyph = Bitmap::new(20, 2);
+        for x in 2..20 {
+            glyph.set_black(x, 0, true);
+        }
+        // 2 white, 15 + 3 black. The remaining white pixels are omitted.
+        assert_eq!(encode_nibble(&glyph), [0x2f, 0x30]);
+        assert_eq!(encode_packed(&Bitmap::new(3, 3)), [0, 0]);
+
+        for &(width, height) in [(1, 1), (13, 7), (64, 32), (100, 40)].iter() {
+            let bitmap = random_bitmap(width, height, width * height);
+            let image = NibbleImage {
+                data: leak(encode_nibble(&bitmap)),
+                width: width as u16,
+                height: height as u16,
+            };
+            check_image(&image, &bitmap);
+
+            let image = PackedImage {
+                data: leak(encode_packed(&bitmap)),
+                width: width as u16,
+                height: height as u16,
+            };
+            check_image(&image, &bitmap);
+
+            for &block_rows in [3, 1000].iter() {
+                let (data, blocks) = encode_heatshrink(&bitmap, block_rows);
+                let image = HeatshrinkImage {
+                    data: leak(data),
+                    blocks: leak(blocks),
+                    block_rows: block_rows.min(height) as u16,
+                    width: width as u16,
+                    height: height as u16,
+                };
+                check_image(&image, &bitmap);
+            }
+        }
+    }
+
+    #[test]
+    fn test_container() {
+        use super::{container, image_asset, parse_container, Asset};
+        use embedded_epd::gui::external::{AssetError, Container, ENTRY_IMAGE};
+        use embedded_epd::storage::BlockCache;
+        use std::cell::RefCell;
+
+        let bitmaps = [random_bitmap(21, 5, 2), random_bitmap(300, 20, 3)];
+        let assets = vec![
+            Asset {
+                name: "bitmap".to_string(),
+                kind: ENTRY_IMAGE,
+                data: image_asset(&bitmaps[0], false).unwrap(),
+            },
+            Asset {
+                name: "rle".to_string(),
+                kind: ENTRY_IMAGE,
+                data: image_asset(&bitmaps[1], true).unwrap(),
+            },
+        ];
+        let data = container(&assets);
+        assert_eq!(parse_container(&data), Some(assets));
+
+        // The container does not need to start at the beginning of the
+        // storage.
+        let mut storage = vec![0xff; 3];
+        storage.extend(data);
+        let reader = RefCell::new(BlockCache::<_, 2, 16>::new(&storage[..]));
+        let assets = Container::open(&reader, 3).unwrap();
+        check_image(&assets.image("bitmap").unwrap(), &bitmaps[0]);
+        check_image(&assets.image("rle").unwrap(), &bitmaps[1]);
+        assert!(matches!(assets.image("missing"), Err(AssetError::NotFound)));
+        assert!(matches!(assets.font("rle"), Err(AssetError::NotFound)));
+        assert!(matches!(
+            Container::open(&reader, 0),
+            Err(AssetError::InvalidFormat)
+        ));
+    }
+
+    #[test]
+    fn test_heatshrink() {
+        use super::heatshrink_compress;
+        // Literal "a" followed by a back-reference with distance 1 and length
+        // 3, which overlaps the current position.
+        assert_eq!(heatshrink_compress(b"aaaa"), [0xb0, 0x80, 0x08]);
+        // Uniform images compress well.
+        let (data, _) = encode_heatshrink(&Bitmap::new(256, 256), 1000);
+        assert!(data.len() < 8192 / 8);
+    }
+
+    #[test]
+    fn test_bitmap() {
+        let bitmap = random_bitmap(21, 5, 1);
+        let formats = [
+            (BitOrder::MsbFirst, Polarity::OneIsWhite),
+            (BitOrder::MsbFirst, Polarity::OneIsBlack),
+            (BitOrder::LsbFirst, Polarity::OneIsWhite),
+            (BitOrder::LsbFirst, Polarity::OneIsBlack),
+        ];
+        for &(order, polarity) in formats.iter() {
+            let data = encode_bitmap(&bitmap, order, polarity);
+            let image: &'static BitmapImage = Box::leak(Box::new(BitmapImage::new(
+                Box::leak(data.into_boxed_slice()),
+                21,
+                5,
+                3,
+                order,
+                polarity,
+            )));
+            // Render at an offset to compare with the unaligned code path.
+            for &offset in [0, 3].iter() {
+                let rendered = render(21 + offset, 5, |row, y| {
+                    let clip = row.full_row();
+                    image.render_row_transparent(row, &clip, y, offset as i32);
+                });
+                for y in 0..5 {
+                    for x in 0..21 {
+                        assert_eq!(rendered.is_black(x + offset, y), bitmap.is_black(x, y));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_convert() {
+        let gradient: Vec<u8> = (0..16).map(|x| x * 17).collect();
+        let bitmap = convert(&gradient, 16, 1, Conversion::Threshold(100));
+        assert_eq!(bitmap.data, [0x03, 0xff]);
+
+        let gray = vec![128u8; 64];
+        let bitmap = convert(&gray, 8, 8, Conversion::FloydSteinberg);
+        let white: u32 = bitmap.data.iter().map(|b| b.count_ones()).sum();
+        assert!((24..=40).contains(&white));
+    }
+}
@@ -0,0 +1,192 @@
+//! Converts PNG, BMP and PBM files into image data for `embedded-epd`.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use clap::{Parser, ValueEnum};
+use embedded_epd::gui::external::ENTRY_IMAGE;
+use embedded_epd::{BitOrder, Polarity};
+use epd_image::{add_to_container_file, encode_rle, image_asset, rle_bytes, Asset};
+use epd_image::{bitmap_source, byte_image_source, convert, heatshrink_source, rle_source};
+use epd_image::{encode_bitmap, encode_heatshrink, encode_nibble, encode_packed};
+use epd_image::{Conversion, TooLarge};
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Format {
+    /// `BitmapImage` with rows padded to whole bytes.
+    Bitmap,
+    /// `RLEImage` with 16-bit runs.
+    Rle,
+    /// `NibbleImage` with 4-bit runs.
+    Nibble,
+    /// `PackedImage` without padding between the rows.
+    Packed,
+    /// `HeatshrinkImage` compressed in blocks of rows.
+    Heatshrink,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Output {
+    /// Rust source code defining a static.
+    Rust,
+    /// Raw data, with RLE data as little-endian 16-bit values. Heatshrink
+    /// data is compressed as a single block.
+    Binary,
+    /// Asset in a container for `ExternalImage` (bitmap and RLE only). The
+    /// image is added to the output file if it already is a container.
+    Container,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum DitherMode {
+    Threshold,
+    Bayer,
+    FloydSteinberg,
+    Atkinson,
+}
+
+#[derive(Parser)]
+#[command(about = "Converts images for embedded-epd")]
+struct Args {
+    /// Input image (PNG, BMP or PBM/PGM/PPM).
+    input: PathBuf,
+    /// Output file.
+    #[arg(short, long)]
+    output: PathBuf,
+    #[arg(short, long, value_enum, default_value = "bitmap")]
+    format: Format,
+    #[arg(long, value_enum, default_value = "rust")]
+    output_format: Output,
+    #[arg(short, long, value_enum, default_value = "threshold")]
+    dither: DitherMode,
+    /// Luminance below which pixels are black when not dithering.
+    #[arg(short, long, default_value_t = 128)]
+    threshold: u8,
+    /// Invert the image.
+    #[arg(long)]
+    invert: bool,
+    /// Name of the generated static or of the asset in the container.
+    #[arg(short, long, default_value = "IMAGE")]
+    name: String,
+    /// Store the leftmost pixel in the least significant bit (bitmaps only).
+    #[arg(long)]
+    lsb_first: bool,
+    /// Use set bits for black pixels (bitmaps only).
+    #[arg(long)]
+    one_is_black: bool,
+    /// Number of rows which are compressed together (Heatshrink only).
+    #[arg(long, default_value_t = 16)]
+    block_rows: u32,
+}
+
+fn main() {
+    let args = Args::parse();
+    let image = match image::open(&args.input) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("{}: {}", args.input.display(), e);
+            process::exit(1);
+        }
+    };
+
+    // Transparent pixels are drawn on a white background.
+    let image = image.to_luma_alpha8();
+    let (width, height) = image.dimensions();
+    let luminance: Vec<u8> = image
+        .pixels()
+        .map(|p| {
+            let [value, alpha] = p.0;
+            let value = (value as u32 * alpha as u32 + 255 * (255 - alpha as u32)) / 255;
+            if args.invert {
+                255 - value as u8
+            } else {
+                value as u8
+            }
+        })
+        .collect();
+    let conversion = match args.dither {
+        DitherMode::Threshold => Conversion::Threshold(args.threshold),
+        DitherMode::Bayer => Conversion::Bayer,
+        DitherMode::FloydSteinberg => Conversion::FloydSteinberg,
+        DitherMode::Atkinson => Conversion::Atkinson,
+    };
+    let bitmap = convert(&luminance, width, height, conversion);
+
+    let order = if args.lsb_first {
+        BitOrder::LsbFirst
+    } else {
+        BitOrder::MsbFirst
+    };
+    let polarity = if args.one_is_black {
+        Polarity::OneIsBlack
+    } else {
+        Polarity::OneIsWhite
+    };
+    if let Output::Container = args.output_format {
+        let asset = match args.format {
+            Format::Bitmap => image_asset(&bitmap, false),
+            Format::Rle => image_asset(&bitmap, true),
+            _ => {
+                eprintln!("only bitmap and RLE images can be stored in containers");
+                process::exit(1);
+            }
+        };
+        let data = match asset {
+            Ok(data) => data,
+            Err(TooLarge) => {
+                eprintln!("{}: image too large", args.input.display());
+                process::exit(1);
+            }
+        };
+        if args.name.len() > 16 {
+            eprintln!("asset names are limited to 16 bytes");
+            process::exit(1);
+        }
+        let asset = Asset {
+            name: args.name.clone(),
+            kind: ENTRY_IMAGE,
+            data,
+        };
+        if let Err(e) = add_to_container_file(&args.output, asset) {
+            eprintln!("{}: {}", args.output.display(), e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let output = match (args.format, args.output_format) {
+        (Format::Bitmap, Output::Rust) => {
+            Ok(bitmap_source(&args.name, &bitmap, order, polarity).into_bytes())
+        }
+        (Format::Bitmap, Output::Binary) => Ok(encode_bitmap(&bitmap, order, polarity)),
+        (Format::Rle, Output::Rust) => rle_source(&args.name, &bitmap).map(String::into_bytes),
+        (Format::Rle, Output::Binary) => encode_rle(&bitmap).map(|data| rle_bytes(&data)),
+        (Format::Nibble, Output::Rust) => {
+            let data = encode_nibble(&bitmap);
+            Ok(byte_image_source(&args.name, "NibbleImage", &data, &bitmap).into_bytes())
+        }
+        (Format::Nibble, Output::Binary) => Ok(encode_nibble(&bitmap)),
+        (Format::Packed, Output::Rust) => {
+            let data = encode_packed(&bitmap);
+            Ok(byte_image_source(&args.name, "PackedImage", &data, &bitmap).into_bytes())
+        }
+        (Format::Packed, Output::Binary) => Ok(encode_packed(&bitmap)),
+        (Format::Heatshrink, Output::Rust) => {
+            Ok(heatshrink_source(&args.name, &bitmap, args.block_rows).into_bytes())
+        }
+        (Format::Heatshrink, Output::Binary) => Ok(encode_heatshrink(&bitmap, bitmap.height).0),
+        (_, Output::Container) => unreachable!(),
+    };
+    let output = match output {
+        Ok(output) => output,
+        Err(TooLarge) => {
+            eprintln!("{}: image too large for RLE", args.input.display());
+            process::exit(1);
+        }
+    };
+    if let Err(e) = fs::write(&args.output, output) {
+        eprintln!("{}: {}", args.output.display(), e);
+        process::exit(1);
+    }
+}